@@ -2,9 +2,12 @@
 
 set -eux -o pipefail
 
+# The provider renames a complete file over the configuration (`moved_to`), or writes
+# it in place when it cannot (`close_write`). Its temporary, lock and backup files do not
+# end with `.conf`, so they neither trigger a reload nor are read by dnsmasq.
 while true;
 do
     dnsmasq &
-    inotifywait -r -e modify -e create /etc/dnsmasq.d/ &&
+    inotifywait -r -e close_write -e moved_to -e delete --include '\.conf$' /etc/dnsmasq.d/ &&
     killall dnsmasq
-done
\ No newline at end of file
+done
@@ -11,20 +11,26 @@
 #![allow(clippy::wildcard_dependencies)]
 
 use std::future::ready;
-use std::str::FromStr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::{collections::HashSet, path::PathBuf};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use clap::Parser;
-use core::fmt::Display;
 use externaldns_webhook::{
-    Provider, Status, Webhook,
+    Provider, Webhook,
     changes::Changes,
     domain_filter::DomainFilter,
-    endpoint::{Endpoint, RecordType},
+    endpoint::Endpoint,
+    providers::dnsmasq::DnsmasqProvider,
+    recorder::Recorder,
+    wrappers::{
+        policy::{Policy, PolicyGuard},
+        reload::{ReloadAction, Reloading},
+    },
 };
-use eyre::{Result, eyre};
+use eyre::Result;
+use opentelemetry::KeyValue;
 use opentelemetry::global;
 use opentelemetry::metrics::Gauge;
 use opentelemetry::trace::TracerProvider;
@@ -33,11 +39,7 @@ use opentelemetry_otlp::MetricExporter;
 use opentelemetry_sdk::metrics::SdkMeterProvider;
 use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
 use opentelemetry_sdk::{Resource, logs::SdkLoggerProvider, trace::SdkTracerProvider};
-use tokio::{
-    fs,
-    io::{AsyncBufReadExt, BufReader},
-};
-use tracing::{error, info, instrument, level_filters::LevelFilter, warn};
+use tracing::{instrument, level_filters::LevelFilter, warn};
 use tracing_error::ErrorLayer;
 use tracing_subscriber::{
     EnvFilter, Layer,
@@ -119,12 +121,23 @@ async fn main() -> eyre::Result<()> {
 
     let args = Args::parse();
 
-    let provider = Arc::new(Dnsmasq {
-        domain_name: args.domain_name,
-        conf_filename: args.conf_filename,
-        gauge_record_count: gauge,
-    });
-    Webhook::new(provider.clone(), provider).start().await?;
+    let provider = Arc::new(provider(&args, gauge));
+    let guard = PolicyGuard::new(provider.clone(), args.policy);
+    let suppressed = guard.suppressed();
+    let _suppressed_changes = meter
+        .u64_observable_counter("suppressed_changes")
+        .with_description("Changes suppressed by the policy")
+        .with_unit("changes")
+        .with_callback(move |o| {
+            o.observe(suppressed.updates(), &[KeyValue::new("change", "update")]);
+            o.observe(suppressed.deletes(), &[KeyValue::new("change", "delete")]);
+        })
+        .build();
+    let mut webhook = Webhook::new(Arc::new(guard), provider);
+    if let Some(record) = args.record {
+        webhook = webhook.with_recorder(Arc::new(Recorder::create(record)?));
+    }
+    webhook.start().await?;
 
     // log_provider.shutdown()?;
     // metric_provider.shutdown()?;
@@ -132,6 +145,50 @@ async fn main() -> eyre::Result<()> {
     Ok(())
 }
 
+/// The dnsmasq provider, reloading dnsmasq as asked for on the command line.
+fn provider(args: &Args, gauge_record_count: Gauge<u64>) -> Reloading {
+    let mut inner = DnsmasqProvider::new(
+        DomainFilter::Strings {
+            include: Some(vec![args.domain_name.clone()]),
+            exclude: None,
+        },
+        args.conf_filename.clone(),
+    )
+    .with_host_records(args.host_records)
+    .with_backups(args.backups);
+    if let Some(ttl) = args.default_ttl {
+        inner = inner.with_default_ttl(ttl);
+    }
+    Reloading::new(
+        Arc::new(Dnsmasq {
+            inner,
+            gauge_record_count,
+        }),
+        reload_actions(args),
+    )
+    .with_debounce(Duration::from_secs(args.reload_debounce))
+}
+
+/// The reload actions asked for on the command line.
+fn reload_actions(args: &Args) -> Vec<ReloadAction> {
+    let mut actions = Vec::new();
+    if let Some(pidfile) = &args.reload_pidfile {
+        actions.push(ReloadAction::Signal {
+            pidfile: pidfile.clone(),
+            signal: args.reload_signal.clone(),
+        });
+    }
+    if let Some(command) = &args.reload_command {
+        actions.push(ReloadAction::Command(
+            command.split_whitespace().map(String::from).collect(),
+        ));
+    }
+    if let Some(path) = &args.reload_touch {
+        actions.push(ReloadAction::Touch(path.clone()));
+    }
+    actions
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
@@ -141,172 +198,68 @@ struct Args {
     /// Dnsmasq configuration file path
     #[arg(long)]
     conf_filename: PathBuf,
+    /// TTL of records without one, where dnsmasq takes a TTL
+    #[arg(long)]
+    default_ttl: Option<i64>,
+    /// Serve A and AAAA records with host-record instead of address
+    #[arg(long)]
+    host_records: bool,
+    /// Former versions of the configuration file to keep
+    #[arg(long, default_value_t = 0)]
+    backups: usize,
+    /// Signal the process whose PID is in this file after changes
+    #[arg(long)]
+    reload_pidfile: Option<PathBuf>,
+    /// Signal to send to the process of --reload-pidfile
+    #[arg(long, default_value = "HUP")]
+    reload_signal: String,
+    /// Run this command after changes, such as "systemctl restart dnsmasq"
+    #[arg(long)]
+    reload_command: Option<String>,
+    /// Touch this file after changes
+    #[arg(long)]
+    reload_touch: Option<PathBuf>,
+    /// Seconds to wait for more changes before reloading
+    #[arg(long, default_value_t = 1)]
+    reload_debounce: u64,
+    /// Record the webhook traffic to this JSON-lines file
+    #[arg(long)]
+    record: Option<PathBuf>,
+    /// Changes allowed, whatever External-DNS asks: sync, upsert-only or create-only
+    #[arg(long, default_value_t = Policy::Sync)]
+    policy: Policy,
 }
 
+// The library provider, counting the records in a gauge.
 #[derive(Debug)]
 struct Dnsmasq {
-    domain_name: String,
-    conf_filename: PathBuf,
+    inner: DnsmasqProvider,
     gauge_record_count: Gauge<u64>,
 }
 #[async_trait]
 impl Provider for Dnsmasq {
     #[instrument(skip_all)]
     async fn domain_filter(&self) -> Result<DomainFilter> {
-        Ok(DomainFilter::Strings {
-            include: Some(vec![self.domain_name.clone()]),
-            exclude: None,
-        })
+        self.inner.domain_filter().await
     }
 
     #[instrument(skip_all)]
     async fn records(&self) -> Result<Vec<Endpoint>> {
-        let file = fs::OpenOptions::new()
-            .read(true)
-            .open(&self.conf_filename)
-            .await?;
-        let mut conf = BufReader::new(file).lines();
-        let mut buf = Vec::new();
-        let mut result = Vec::new();
-        while let Some(l) = conf.next_line().await? {
-            if l.is_empty() {
-                if !buf.is_empty() {
-                    result.push(EndpointED::from_str(&buf.join("\n"))?.0);
-                }
-                buf = vec![];
-            } else {
-                buf.push(l);
-            }
-        }
-        if !buf.is_empty() {
-            result.push(EndpointED::from_str(&buf.join("\n"))?.0);
-        }
-
+        let result = self.inner.records().await?;
         self.gauge_record_count
             .record(result.len().try_into()?, &[]);
-
         Ok(result)
     }
 
     #[instrument(skip_all)]
     async fn apply_changes(&self, changes: Changes) -> Result<()> {
-        let simple_show = |ep: &Endpoint| {
-            let empty_string = String::new();
-            let empty_vec = Vec::new();
-            format!(
-                "{}/{}",
-                ep.dns_name.as_ref().unwrap_or(&empty_string),
-                ep.targets.as_ref().unwrap_or(&empty_vec).join(",")
-            )
-        };
-        let mut endpoints: HashSet<Endpoint> = self.records().await?.into_iter().collect();
-        for i in changes.create {
-            info!(target: "e_d report", message = format!("Insert: {}", simple_show(&i)));
-            endpoints.insert(i);
-        }
-        for i in changes.delete {
-            info!(target: "e_d report", message = format!("Delete: {}", simple_show(&i)));
-            endpoints.remove(&i);
-        }
-        for i in changes.update {
-            info!(target: "e_d report", message = format!("Update: {} -> {}", simple_show(&i.from), simple_show(&i.to)));
-            endpoints.remove(&i.from);
-            endpoints.insert(i.to);
-        }
-
-        self.gauge_record_count
-            .record(endpoints.len().try_into()?, &[]);
-
-        fs::write(
-            &self.conf_filename,
-            endpoints
-                .into_iter()
-                .map(|x| format!("{}", EndpointED(x)))
-                .collect::<Vec<String>>()
-                .join("\n"),
-        )
-        .await?;
-        Ok(())
+        self.inner.apply_changes(changes).await?;
+        self.records().await.map(|_| ())
     }
-}
-
-#[async_trait]
-impl Status for Dnsmasq {}
-
-// address=/domain[/domain]/ip
-// cname=cname[,cname],target[,ttl]
-// txt-record=name[,"text"]*
-// ptr-record=name[,target]
-struct EndpointED(Endpoint);
-impl Display for EndpointED {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let endpoint = self.0.clone();
-        let mut try_block = || {
-            if endpoint.dns_name.is_none() || endpoint.targets.is_none() {
-                Err(eyre::eyre!("ExternalDNS did not give enough data"))?;
-            }
-            let targets = endpoint.targets.as_ref().unwrap();
-            let dns_name = endpoint.dns_name.as_ref().unwrap();
 
-            f.write_fmt(format_args!("# {}\n", serde_json::to_string(&endpoint)?))?;
-            match endpoint.record_type {
-                Some(RecordType::A) => {
-                    for target in targets {
-                        f.write_fmt(format_args!("address=/{dns_name}/{target}\n"))?;
-                    }
-                }
-                Some(RecordType::CNAME) => {
-                    let ttl = endpoint
-                        .record_ttl
-                        .map(|ttl| format!(",{ttl}"))
-                        .unwrap_or_default();
-                    for target in targets {
-                        f.write_fmt(format_args!("cname={dns_name},{target}{ttl}\n"))?;
-                    }
-                }
-                Some(RecordType::TXT) => {
-                    let targets = targets
-                        .iter()
-                        .map(|t| format!(",{t}"))
-                        .collect::<Vec<_>>()
-                        .concat();
-                    f.write_fmt(format_args!("txt-record={dns_name}{targets}"))?;
-                }
-                Some(RecordType::PTR) => f.write_fmt(format_args!(
-                    "ptr-record={},{}",
-                    dns_name,
-                    targets
-                        .first()
-                        .ok_or_else(|| eyre!("No target found in PTR request"))?
-                ))?,
-                _ => {
-                    Err(eyre!("Unsupported ExternalDNS endpoint: {endpoint:?}"))?;
-                }
-            }
-            f.write_str("\n")?;
-            Ok(())
-        };
-        let y: eyre::Result<()> = try_block();
-        match y {
-            Ok(()) => Ok(()),
-            Err(e) => {
-                error!(target: "EndpointED: Display", message = format!("{e:?}"));
-                Err(std::fmt::Error)
-            }
-        }
-    }
-}
-impl FromStr for EndpointED {
-    type Err = eyre::Error;
-
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        let mut lines = s.lines();
-        let first_line = lines
-            .next()
-            .and_then(|l| l.strip_prefix("# "))
-            .ok_or_else(|| eyre!("Input does not contain a commented first line: {s}"))?;
-        let endpoint = serde_json::from_str(first_line)?;
-        Ok(Self(endpoint))
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        self.inner.adjust_endpoints(endpoints).await
     }
 }
 
@@ -326,10 +279,7 @@ impl PushMetricExporter for DebugMetricExporter {
         Ok(())
     }
 
-    fn shutdown_with_timeout(
-        &self,
-        _timeout: std::time::Duration,
-    ) -> opentelemetry_sdk::error::OTelSdkResult {
+    fn shutdown_with_timeout(&self, _timeout: Duration) -> opentelemetry_sdk::error::OTelSdkResult {
         Ok(())
     }
 
@@ -0,0 +1,366 @@
+#![warn(clippy::cargo)]
+#![warn(clippy::complexity)]
+#![warn(clippy::correctness)]
+#![warn(clippy::nursery)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::perf)]
+#![warn(clippy::style)]
+#![warn(clippy::suspicious)]
+#![allow(clippy::future_not_send)]
+#![allow(clippy::multiple_crate_versions)]
+#![allow(clippy::wildcard_dependencies)]
+
+//! Inspect and drive a running webhook provider, without hand-crafting curl calls.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use externaldns_webhook::{
+    Provider,
+    changes::Changes,
+    client::WebhookClient,
+    domain_filter::DomainFilter,
+    endpoint::{Endpoint, RecordType},
+    providers::memory::InMemoryProvider,
+    recorder::{Exchange, Recorder, replay},
+};
+use eyre::{Context, Result, eyre};
+use serde::de::DeserializeOwned;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Base URL of the webhook provider
+    #[arg(long, default_value = "http://127.0.0.1:8888")]
+    url: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show the `DomainFilter` of the provider
+    Filter,
+    /// List the records of the provider
+    Records {
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+    },
+    /// Send a `Changes` JSON file, as External-DNS posts to `/records`
+    Apply { file: PathBuf },
+    /// Run `/adjustendpoints` on a JSON file of endpoints
+    Adjust { file: PathBuf },
+    /// Compare a JSON file of desired endpoints to the records of the provider
+    Diff {
+        file: PathBuf,
+        /// Print the `Changes` that would be posted, as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Replay a recording, into an in-memory provider seeded with the first recorded
+    /// `GET /records`, and compare the responses
+    Replay {
+        file: PathBuf,
+        /// Replay into the provider at this URL instead, applying the recorded changes to it
+        #[arg(long)]
+        against: Option<String>,
+        /// Confirm the recorded changes may be applied to the provider of `--against`
+        #[arg(long, requires = "against")]
+        yes: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    Table,
+    Json,
+    Zone,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let client = WebhookClient::new(&args.url);
+
+    match args.command {
+        Command::Filter => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&client.domain_filter().await?)?
+            );
+        }
+        Command::Records { format } => {
+            let records = client.records().await?;
+            match format {
+                Format::Table => print!("{}", table(&records)),
+                Format::Json => println!("{}", serde_json::to_string_pretty(&records)?),
+                Format::Zone => print!("{}", zone(&records)),
+            }
+        }
+        Command::Apply { file } => {
+            let changes: Changes = read_json(&file)?;
+            client.apply_changes(changes).await?;
+        }
+        Command::Adjust { file } => {
+            let endpoints: Vec<Endpoint> = read_json(&file)?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&client.adjust_endpoints(endpoints).await?)?
+            );
+        }
+        Command::Diff { file, json } => {
+            let desired: Vec<Endpoint> = read_json(&file)?;
+            let changes = Changes::between(&client.records().await?, &desired)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&changes)?);
+            } else {
+                print!("{}", diff(&changes));
+            }
+        }
+        Command::Replay { file, against, yes } => {
+            let exchanges = Recorder::load(&file)?;
+            let provider: Arc<dyn Provider> = match against {
+                Some(url) if yes => Arc::new(WebhookClient::new(&url)),
+                Some(url) => {
+                    return Err(eyre!(
+                        "Replaying applies the recorded changes to {url}, confirm with --yes"
+                    ));
+                }
+                None => Arc::new(recorded_state(&exchanges)?),
+            };
+            let replayed = replay(exchanges, provider).await?;
+            let mismatches = replayed.iter().filter(|r| !r.matches()).count();
+            for r in replayed.iter().filter(|r| !r.matches()) {
+                println!(
+                    "{} {}\n  recorded: {} {}\n  replayed: {} {}",
+                    r.exchange.method,
+                    r.exchange.uri,
+                    r.exchange.status,
+                    r.exchange.response_body,
+                    r.status,
+                    r.response_body
+                );
+            }
+            if mismatches > 0 {
+                return Err(eyre!(
+                    "{mismatches} of {} responses differ from the recording",
+                    replayed.len()
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// The provider as the recording starts:
+// the domain filter of the first `GET /` and the records of the first `GET /records`.
+fn recorded_state(exchanges: &[Exchange]) -> Result<InMemoryProvider> {
+    let first = |uri: &str| {
+        exchanges
+            .iter()
+            .find(|e| e.method == "GET" && e.uri == uri && e.status == 200)
+    };
+    let domain_filter = match first("/") {
+        Some(e) => serde_json::from_str(&e.response_body)
+            .wrap_err("Cannot parse the recorded domain filter")?,
+        None => DomainFilter::Strings {
+            include: None,
+            exclude: None,
+        },
+    };
+    let provider = InMemoryProvider::new(domain_filter);
+    if let Some(e) = first("/records") {
+        provider
+            .seed_json(&e.response_body)
+            .wrap_err("Cannot parse the recorded records")?;
+    }
+    Ok(provider)
+}
+
+fn read_json<T: DeserializeOwned>(file: &Path) -> Result<T> {
+    let content = std::fs::read_to_string(file)
+        .wrap_err_with(|| format!("Cannot read {}", file.display()))?;
+    serde_json::from_str(&content).wrap_err_with(|| format!("Cannot parse {}", file.display()))
+}
+
+fn record_type(ep: &Endpoint) -> String {
+    ep.record_type
+        .as_ref()
+        .map(|t| format!("{t:?}"))
+        .unwrap_or_default()
+}
+
+fn one_line(ep: &Endpoint) -> String {
+    format!(
+        "{} {} {}",
+        ep.dns_name.as_deref().unwrap_or_default(),
+        record_type(ep),
+        ep.targets.as_deref().unwrap_or_default().join(",")
+    )
+}
+
+fn table(records: &[Endpoint]) -> String {
+    let rows: Vec<[String; 4]> = records
+        .iter()
+        .map(|ep| {
+            [
+                ep.dns_name.clone().unwrap_or_default(),
+                record_type(ep),
+                ep.record_ttl.map(|t| t.to_string()).unwrap_or_default(),
+                ep.targets.as_deref().unwrap_or_default().join(","),
+            ]
+        })
+        .collect();
+    let header = ["NAME", "TYPE", "TTL", "TARGETS"].map(ToString::to_string);
+    let widths: Vec<usize> = (0..4)
+        .map(|i| {
+            rows.iter()
+                .chain([&header])
+                .map(|r| r[i].len())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let mut out = String::new();
+    for r in std::iter::once(&header).chain(&rows) {
+        let _ = writeln!(
+            out,
+            "{:w0$}  {:w1$}  {:w2$}  {}",
+            r[0],
+            r[1],
+            r[2],
+            r[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+    }
+    out
+}
+
+// One line per target, in master file format.
+// Targets naming a host get the trailing dot External-DNS leaves out.
+fn zone(records: &[Endpoint]) -> String {
+    let mut out = String::new();
+    for ep in records {
+        let name = ep.dns_name.as_deref().unwrap_or_default();
+        let ttl = ep.record_ttl.map(|t| format!("{t} ")).unwrap_or_default();
+        for target in ep.targets.as_deref().unwrap_or_default() {
+            let target = match ep.record_type {
+                Some(RecordType::CNAME | RecordType::NS | RecordType::PTR) => fqdn(target),
+                Some(RecordType::MX | RecordType::SRV) => match target.rsplit_once(' ') {
+                    Some((params, host)) => format!("{params} {}", fqdn(host)),
+                    None => target.clone(),
+                },
+                _ => target.clone(),
+            };
+            let _ = writeln!(
+                out,
+                "{}. {ttl}IN {} {target}",
+                name.trim_end_matches('.'),
+                record_type(ep)
+            );
+        }
+    }
+    out
+}
+
+fn fqdn(name: &str) -> String {
+    if name.ends_with('.') {
+        name.to_string()
+    } else {
+        format!("{name}.")
+    }
+}
+
+fn diff(changes: &Changes) -> String {
+    let mut out = String::new();
+    for ep in &changes.create {
+        let _ = writeln!(out, "+ {}", one_line(ep));
+    }
+    for ft in &changes.update {
+        let _ = writeln!(out, "~ {}\n  -> {}", one_line(&ft.from), one_line(&ft.to));
+    }
+    for ep in &changes.delete {
+        let _ = writeln!(out, "- {}", one_line(ep));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use externaldns_webhook::recorder::Replayed;
+
+    const MEDIATYPE: &str = "application/external.dns.webhook+json;version=1";
+
+    #[test]
+    fn it_works() {
+        let records: Vec<Endpoint> = serde_json::from_str(
+            r#"[
+    {"dnsName": "nextcloud.magicloud.lan", "targets": ["192.168.0.102"], "recordType": "A", "recordTTL": 300},
+    {"dnsName": "gitea.magicloud.lan", "targets": ["nextcloud.magicloud.lan"], "recordType": "CNAME"},
+    {"dnsName": "magicloud.lan", "targets": ["10 mail.magicloud.lan"], "recordType": "MX"}
+]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            zone(&records),
+            "nextcloud.magicloud.lan. 300 IN A 192.168.0.102
+gitea.magicloud.lan. IN CNAME nextcloud.magicloud.lan.
+magicloud.lan. IN MX 10 mail.magicloud.lan.
+"
+        );
+    }
+
+    fn exchange(
+        method: &str,
+        uri: &str,
+        request_body: &str,
+        status: u16,
+        response_body: &str,
+    ) -> Exchange {
+        Exchange {
+            timestamp: 0,
+            duration: 0,
+            method: method.to_string(),
+            uri: uri.to_string(),
+            request_headers: [("accept", MEDIATYPE), ("content-type", MEDIATYPE)]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .into(),
+            request_body: request_body.to_string(),
+            status,
+            response_body: response_body.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_state() {
+        let exchanges = vec![
+            exchange("GET", "/", "", 200, r#"{"include": ["magicloud.lan"]}"#),
+            exchange(
+                "GET",
+                "/records",
+                "",
+                200,
+                r#"[{"dnsName": "nextcloud.magicloud.lan", "targets": ["192.168.0.102"], "recordType": "A"}]"#,
+            ),
+            exchange(
+                "POST",
+                "/records",
+                r#"{"create": [{"dnsName": "gitea.magicloud.lan", "targets": ["192.168.0.103"], "recordType": "A"}]}"#,
+                204,
+                "",
+            ),
+        ];
+        let provider = Arc::new(recorded_state(&exchanges).unwrap());
+        let replayed = replay(exchanges, provider.clone()).await.unwrap();
+        assert!(replayed.iter().all(Replayed::matches), "{replayed:?}");
+        assert_eq!(provider.len(), 2);
+    }
+}
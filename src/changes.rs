@@ -1,10 +1,16 @@
-use crate::endpoint::Endpoint;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+};
+
+use crate::endpoint::{Endpoint, RecordKey, RecordType};
+use eyre::Result;
 use serde::{Deserialize, Serialize};
 use serde_with::{DefaultOnNull, serde_as};
 
 /// Pair with direction
 #[serde_as]
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct FromTo<T> {
     pub from: T,
     pub to: T,
@@ -15,7 +21,7 @@ pub struct FromTo<T> {
 /// It is not certain that all fields would be filled in one request.
 // TODO: Could be an Enum, if only one field is filled at a time?
 #[serde_as]
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
 #[serde(rename_all = "camelCase", default)]
 pub struct Changes {
     // Funny enough, when removing records, this field is `null`,
@@ -27,6 +33,216 @@ pub struct Changes {
     pub delete: Vec<Endpoint>,
 }
 
+impl Changes {
+    /// If there is nothing to change.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.create.is_empty() && self.update.is_empty() && self.delete.is_empty()
+    }
+
+    /// The changes turning `current` records into `desired` ones, as External-DNS plans them,
+    /// without its ownership rules.
+    ///
+    /// Records are matched by `RecordKey`, and targets are compared regardless of their order.
+    /// # Errors
+    ///
+    /// When an endpoint has no name or no type.
+    pub fn between(current: &[Endpoint], desired: &[Endpoint]) -> Result<Self> {
+        let existing = current
+            .iter()
+            .map(|ep| RecordKey::of(ep).map(|key| (key, ep)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        let mut wanted = HashSet::new();
+        let mut ret = Self::default();
+        for ep in desired {
+            let key = RecordKey::of(ep)?;
+            match existing.get(&key) {
+                Some(&old) if !same_record(old, ep) => ret.update.push(FromTo {
+                    from: old.clone(),
+                    to: ep.clone(),
+                }),
+                Some(_) => {}
+                None => ret.create.push(ep.clone()),
+            }
+            wanted.insert(key);
+        }
+        for ep in current {
+            if !wanted.contains(&RecordKey::of(ep)?) {
+                ret.delete.push(ep.clone());
+            }
+        }
+        Ok(ret)
+    }
+
+    /// The same changes, compacted:
+    /// - updates not changing anything are removed,
+    /// - a create and a delete of the same record cancel out, or become an update when the
+    ///   targets differ,
+    /// - duplicated entries are kept once.
+    ///
+    /// Endpoints without name or type are left as they are.
+    #[must_use]
+    pub fn normalize(self) -> Self {
+        let mut ret = Self::default();
+        let mut seen = HashSet::new();
+        let mut first = |list: &str, ep: &Endpoint| {
+            let mut targets = ep.targets.clone().unwrap_or_default();
+            targets.sort();
+            seen.insert((
+                list.to_string(),
+                RecordKey::of(ep).ok(),
+                targets,
+                ep.record_ttl,
+            ))
+        };
+
+        // Deletes still unmatched, in order. Distinct ones may share a key.
+        let mut deleted = vec![];
+        for ep in self.delete {
+            if first("delete", &ep) {
+                deleted.push((RecordKey::of(&ep).ok(), ep));
+            }
+        }
+        for ep in self.create {
+            if !first("create", &ep) {
+                continue;
+            }
+            // The same record if it is deleted, or else the first one with its key.
+            let key = RecordKey::of(&ep).ok();
+            let mut found = None;
+            for (i, (k, old)) in deleted.iter().enumerate() {
+                if key.is_none() || *k != key {
+                    continue;
+                }
+                if same_record(old, &ep) && old.labels == ep.labels {
+                    found = Some((i, true));
+                    break;
+                }
+                found.get_or_insert((i, false));
+            }
+            match found {
+                Some((i, same)) => {
+                    let (_, old) = deleted.remove(i);
+                    if !same {
+                        ret.update.push(FromTo { from: old, to: ep });
+                    }
+                }
+                None => ret.create.push(ep),
+            }
+        }
+        ret.delete.extend(deleted.into_iter().map(|(_, ep)| ep));
+        for ft in self.update {
+            let noop = RecordKey::of(&ft.from).ok() == RecordKey::of(&ft.to).ok()
+                && same_record(&ft.from, &ft.to)
+                && ft.from.labels == ft.to.labels
+                && ft.from.provider_specific == ft.to.provider_specific;
+            if !noop && first("update-from", &ft.from) | first("update-to", &ft.to) {
+                ret.update.push(ft);
+            }
+        }
+        ret
+    }
+
+    /// The changes, one by one, in an order safe to apply sequentially:
+    /// deletes freeing a name for a conflicting create (a CNAME cannot live with
+    /// other records of the same name), then creates, updates, and the other deletes.
+    #[must_use]
+    pub fn operations(&self) -> Vec<Operation> {
+        let name = |ep: &Endpoint| {
+            ep.dns_name
+                .as_deref()
+                .unwrap_or_default()
+                .trim_end_matches('.')
+                .to_lowercase()
+        };
+        let is_cname = |ep: &Endpoint| ep.record_type == Some(RecordType::CNAME);
+        let created: Vec<_> = self
+            .create
+            .iter()
+            .chain(self.update.iter().map(|ft| &ft.to))
+            .collect();
+        let conflicts = |deleted: &Endpoint| {
+            created.iter().any(|ep| {
+                name(ep) == name(deleted)
+                    && RecordKey::of(ep).ok() != RecordKey::of(deleted).ok()
+                    && (is_cname(ep) || is_cname(deleted))
+            })
+        };
+        let (early, late): (Vec<_>, Vec<_>) = self.delete.iter().partition(|ep| conflicts(ep));
+        early
+            .into_iter()
+            .map(|ep| Operation::Delete(ep.clone()))
+            .chain(self.create.iter().map(|ep| Operation::Create(ep.clone())))
+            .chain(self.update.iter().map(|ft| Operation::Update(ft.clone())))
+            .chain(late.into_iter().map(|ep| Operation::Delete(ep.clone())))
+            .collect()
+    }
+
+    /// Counts of changes per record type, for logging.
+    #[must_use]
+    pub fn summary(&self) -> Summary {
+        let mut ret = Summary::default();
+        let mut count = |ep: &Endpoint, f: fn(&mut Counts)| {
+            if let Some(t) = &ep.record_type {
+                f(ret.0.entry(t.clone()).or_default());
+            }
+        };
+        for ep in &self.create {
+            count(ep, |c| c.create += 1);
+        }
+        for ft in &self.update {
+            count(&ft.to, |c| c.update += 1);
+        }
+        for ep in &self.delete {
+            count(ep, |c| c.delete += 1);
+        }
+        ret
+    }
+}
+
+/// One change of `Changes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Create(Endpoint),
+    Update(FromTo<Endpoint>),
+    Delete(Endpoint),
+}
+
+/// Counts of changes of one record type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub create: usize,
+    pub update: usize,
+    pub delete: usize,
+}
+
+/// Counts of changes per record type. Displays as `A: +1 ~0 -2, CNAME: +1 ~0 -0`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary(pub BTreeMap<RecordType, Counts>);
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("no changes");
+        }
+        for (i, (t, c)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{t:?}: +{} ~{} -{}", c.create, c.update, c.delete)?;
+        }
+        Ok(())
+    }
+}
+
+fn same_record(a: &Endpoint, b: &Endpoint) -> bool {
+    let sorted = |ep: &Endpoint| {
+        let mut targets = ep.targets.clone().unwrap_or_default();
+        targets.sort();
+        targets
+    };
+    a.record_ttl == b.record_ttl && sorted(a) == sorted(b)
+}
+
 mod serde_fromto {
     use super::FromTo;
     use serde::de::Error;
@@ -88,6 +304,7 @@ mod serde_fromto {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::endpoint::ep;
 
     #[test]
     fn it_works() {
@@ -120,4 +337,116 @@ mod tests {
         );
         eprintln!("{json:?}");
     }
+
+    #[test]
+    fn plans_between() {
+        let ep = |name: &str, targets: &[&str]| ep(name, RecordType::A, targets);
+        let current = [
+            ep("a.magicloud.lan", &["192.168.0.1", "192.168.0.2"]),
+            ep("b.magicloud.lan", &["192.168.0.3"]),
+            ep("c.magicloud.lan", &["192.168.0.4"]),
+        ];
+        let desired = [
+            ep("a.magicloud.lan", &["192.168.0.2", "192.168.0.1"]),
+            ep("b.magicloud.lan", &["192.168.0.5"]),
+            ep("d.magicloud.lan", &["192.168.0.6"]),
+        ];
+        let changes = Changes::between(&current, &desired).unwrap();
+        assert_eq!(changes.create, desired[2..]);
+        assert_eq!(
+            changes.update,
+            vec![FromTo {
+                from: current[1].clone(),
+                to: desired[1].clone()
+            }]
+        );
+        assert_eq!(changes.delete, current[2..]);
+        assert!(Changes::between(&desired, &desired).unwrap().is_empty());
+    }
+
+    #[test]
+    fn normalizes() {
+        let ep =
+            |name: &str, record_type: RecordType, target: &str| ep(name, record_type, &[target]);
+        let a = |name: &str, target: &str| ep(name, RecordType::A, target);
+        let changes = Changes {
+            create: vec![
+                a("a.magicloud.lan", "192.168.0.1"),
+                a("a.magicloud.lan", "192.168.0.1"),
+                a("b.magicloud.lan", "192.168.0.2"),
+                a("c.magicloud.lan", "192.168.0.9"),
+                ep("d.magicloud.lan", RecordType::CNAME, "a.magicloud.lan"),
+            ],
+            update: vec![FromTo {
+                from: a("e.magicloud.lan", "192.168.0.5"),
+                to: a("e.magicloud.lan", "192.168.0.5"),
+            }],
+            delete: vec![
+                a("b.magicloud.lan", "192.168.0.2"),
+                a("c.magicloud.lan", "192.168.0.3"),
+                a("d.magicloud.lan", "192.168.0.4"),
+                a("f.magicloud.lan", "192.168.0.6"),
+            ],
+        }
+        .normalize();
+        assert_eq!(
+            changes.create,
+            vec![
+                a("a.magicloud.lan", "192.168.0.1"),
+                ep("d.magicloud.lan", RecordType::CNAME, "a.magicloud.lan"),
+            ]
+        );
+        assert_eq!(
+            changes.update,
+            vec![FromTo {
+                from: a("c.magicloud.lan", "192.168.0.3"),
+                to: a("c.magicloud.lan", "192.168.0.9"),
+            }]
+        );
+        assert_eq!(
+            changes.delete,
+            vec![
+                a("d.magicloud.lan", "192.168.0.4"),
+                a("f.magicloud.lan", "192.168.0.6"),
+            ]
+        );
+
+        let operations = changes.operations();
+        assert_eq!(
+            operations[0],
+            Operation::Delete(a("d.magicloud.lan", "192.168.0.4"))
+        );
+        assert_eq!(
+            operations[4],
+            Operation::Delete(a("f.magicloud.lan", "192.168.0.6"))
+        );
+        assert_eq!(
+            changes.summary().to_string(),
+            "A: +1 ~1 -2, CNAME: +1 ~0 -0"
+        );
+        assert_eq!(Changes::default().summary().to_string(), "no changes");
+
+        // Distinct deletes of one key are all kept, and a create cancels the same one.
+        let changes = Changes {
+            create: vec![a("g.magicloud.lan", "192.168.0.8")],
+            delete: vec![
+                a("g.magicloud.lan", "192.168.0.7"),
+                a("g.magicloud.lan", "192.168.0.8"),
+                a("h.magicloud.lan", "192.168.0.9"),
+                a("h.magicloud.lan", "192.168.0.10"),
+                a("h.magicloud.lan", "192.168.0.9"),
+            ],
+            ..Changes::default()
+        }
+        .normalize();
+        assert!(changes.create.is_empty() && changes.update.is_empty());
+        assert_eq!(
+            changes.delete,
+            vec![
+                a("g.magicloud.lan", "192.168.0.7"),
+                a("h.magicloud.lan", "192.168.0.9"),
+                a("h.magicloud.lan", "192.168.0.10"),
+            ]
+        );
+    }
 }
@@ -0,0 +1,236 @@
+//! Client side of the webhook protocol, to talk to a running webhook provider from Rust.
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use reqwest::{
+    Client, Method, RequestBuilder, StatusCode,
+    header::{ACCEPT, CONTENT_TYPE},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use tracing::instrument;
+
+use crate::{
+    MEDIATYPE, changes::Changes, domain_filter::DomainFilter, endpoint::Endpoint,
+    provider::Provider,
+};
+
+/// Speaks the webhook protocol to a provider, as External-DNS does.
+///
+/// It implements `Provider` itself, so a remote webhook can be served again, or wrapped,
+/// like a local implementor.
+#[derive(Debug, Clone)]
+pub struct WebhookClient {
+    base_url: String,
+    http: Client,
+}
+impl WebhookClient {
+    /// Constructor of `WebhookClient`.
+    /// `base_url` is where the provider listens, such as `http://127.0.0.1:8888`.
+    #[must_use]
+    pub fn new(base_url: &str) -> Self {
+        Self::with_client(base_url, Client::new())
+    }
+
+    /// Constructor of `WebhookClient`, with a configured `reqwest::Client` (timeouts, TLS, ...).
+    #[must_use]
+    pub fn with_client(base_url: &str, http: Client) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http,
+        }
+    }
+
+    /// `GET /`, checking the media type as External-DNS does at start up.
+    /// # Errors
+    ///
+    /// When the request fails, or the response is not a 200 with the webhook media type
+    /// and a `DomainFilter`.
+    #[instrument(skip_all)]
+    pub async fn negotiate(&self) -> Result<DomainFilter> {
+        let res = self.request(Method::GET, "/").send().await?;
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string);
+        let body = expect_status(StatusCode::OK, res).await?;
+        if content_type.as_deref() != Some(MEDIATYPE) {
+            return Err(eyre!(
+                "Wrong content type in negotiation: {content_type:?}, expected {MEDIATYPE}"
+            ));
+        }
+        parse(&body)
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}{path}", self.base_url))
+            .header(ACCEPT, MEDIATYPE)
+    }
+
+    async fn post(&self, path: &str, payload: &impl Serialize) -> Result<reqwest::Response> {
+        Ok(self
+            .request(Method::POST, path)
+            .header(CONTENT_TYPE, MEDIATYPE)
+            .body(serde_json::to_vec(payload)?)
+            .send()
+            .await?)
+    }
+}
+
+#[async_trait]
+impl Provider for WebhookClient {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        self.negotiate().await
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        let res = self.request(Method::GET, "/records").send().await?;
+        parse(&expect_status(StatusCode::OK, res).await?)
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        let res = self.post("/records", &changes).await?;
+        expect_status(StatusCode::NO_CONTENT, res).await.map(|_| ())
+    }
+
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        let res = self.post("/adjustendpoints", &endpoints).await?;
+        parse(&expect_status(StatusCode::OK, res).await?)
+    }
+}
+
+async fn expect_status(expected: StatusCode, res: reqwest::Response) -> Result<Vec<u8>> {
+    let status = res.status();
+    let url = res.url().clone();
+    let body = res.bytes().await?.to_vec();
+    if status == expected {
+        Ok(body)
+    } else {
+        Err(eyre!(
+            "{url}: expected status {expected}, got {status}: {}",
+            String::from_utf8_lossy(&body)
+        ))
+    }
+}
+
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T> {
+    serde_json::from_slice(body).map_err(|e| {
+        eyre!(
+            "Cannot parse response ({e}): {}",
+            String::from_utf8_lossy(body)
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::ep;
+    use crate::{
+        changes::FromTo, endpoint::RecordType, providers::memory::InMemoryProvider,
+        webhook::provider_services,
+    };
+    use actix_web::{App, HttpServer};
+    use std::{net::TcpListener, sync::Arc};
+
+    // Serve `provider` on a random local port, returning the base URL.
+    fn serve(provider: Arc<dyn Provider>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server =
+            HttpServer::new(move || App::new().configure(provider_services(provider.clone())))
+                .workers(1)
+                .listen(listener)
+                .unwrap()
+                .run();
+        tokio::spawn(server);
+        url
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_works() {
+        let remote = Arc::new(InMemoryProvider::new(DomainFilter::Strings {
+            include: Some(vec!["magicloud.lan".to_string()]),
+            exclude: None,
+        }));
+        let client = WebhookClient::new(&serve(remote.clone()));
+
+        assert!(matches!(
+            client.domain_filter().await.unwrap(),
+            DomainFilter::Strings { include: Some(x), .. } if x == ["magicloud.lan"]
+        ));
+        client
+            .apply_changes(Changes {
+                create: vec![ep(
+                    "nextcloud.magicloud.lan",
+                    RecordType::A,
+                    &["192.168.0.102"],
+                )],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        client
+            .apply_changes(Changes {
+                update: vec![FromTo {
+                    from: ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]),
+                    to: ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.103"]),
+                }],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            client.records().await.unwrap(),
+            vec![ep(
+                "nextcloud.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.103"]
+            )]
+        );
+        assert_eq!(remote.len(), 1);
+
+        let adjusted = client
+            .adjust_endpoints(vec![ep(
+                "gitea.magicloud.lan.",
+                RecordType::A,
+                &["192.168.0.104"],
+            )])
+            .await
+            .unwrap();
+        assert_eq!(
+            adjusted,
+            vec![ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.104"])]
+        );
+
+        // Errors of the remote provider come back as errors.
+        assert!(
+            client
+                .apply_changes(Changes {
+                    delete: vec![ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.104"])],
+                    ..Changes::default()
+                })
+                .await
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn conforms() {
+        use crate::testing::conformance::ConformanceSuite;
+
+        // The client, served again in process, in front of the remote provider.
+        let client = WebhookClient::new(&serve(Arc::new(InMemoryProvider::default())));
+        ConformanceSuite::for_domain("magicloud.lan")
+            .run(Arc::new(client))
+            .await
+            .into_result()
+            .unwrap();
+    }
+}
@@ -23,6 +23,45 @@ pub enum DomainFilter {
     },
 }
 
+impl DomainFilter {
+    /// If the filter accepts `dns_name`, as External-DNS matches it.
+    ///
+    /// A domain of `Strings` accepts itself and its subdomains, or only its subdomains
+    /// when it starts with a dot. No includes accept everything. Excludes take precedence.
+    /// Names are compared case insensitively, without the trailing dot.
+    #[must_use]
+    pub fn matches(&self, dns_name: &str) -> bool {
+        let name = dns_name.trim_end_matches('.').to_lowercase();
+        match self {
+            Self::Strings { include, exclude } => {
+                let any = |domains: &Option<Vec<String>>| {
+                    domains.iter().flatten().any(|d| domain_matches(&name, d))
+                };
+                (include.as_ref().is_none_or(Vec::is_empty) || any(include)) && !any(exclude)
+            }
+            Self::Regex {
+                regex_include,
+                regex_exclude,
+            } => {
+                regex_include.as_ref().is_none_or(|r| r.is_match(&name))
+                    && !regex_exclude.as_ref().is_some_and(|r| r.is_match(&name))
+            }
+        }
+    }
+}
+
+fn domain_matches(name: &str, domain: &str) -> bool {
+    let domain = domain.trim_end_matches('.').to_lowercase();
+    if domain.is_empty() {
+        return true;
+    }
+    if domain.starts_with('.') {
+        name.ends_with(&domain)
+    } else {
+        name == domain || name.ends_with(&format!(".{domain}"))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,4 +80,29 @@ mod tests {
         });
         assert_eq!(json.unwrap(), r#"{"regexInclude":"[0-9]a"}"#);
     }
+
+    #[test]
+    fn matches() {
+        let filter = DomainFilter::Strings {
+            include: Some(vec![
+                "magicloud.lan".to_string(),
+                ".example.org".to_string(),
+            ]),
+            exclude: Some(vec!["private.magicloud.lan".to_string()]),
+        };
+        assert!(filter.matches("magicloud.lan."));
+        assert!(filter.matches("Nextcloud.magicloud.lan"));
+        assert!(!filter.matches("notmagicloud.lan"));
+        assert!(!filter.matches("db.private.magicloud.lan"));
+        assert!(!filter.matches("example.org"));
+        assert!(filter.matches("www.example.org"));
+
+        let filter = DomainFilter::Regex {
+            regex_include: Some(Regex::new(r"^magicloud\.(lan|local)$").unwrap()),
+            regex_exclude: None,
+        };
+        assert!(filter.matches("magicloud.lan"));
+        assert!(filter.matches("magicloud.local"));
+        assert!(!filter.matches("www.magicloud.lan"));
+    }
 }
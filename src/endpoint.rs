@@ -1,5 +1,6 @@
 use std::{collections::HashMap, hash::Hash};
 
+use eyre::{Result, eyre};
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 
@@ -38,8 +39,36 @@ impl Hash for Endpoint {
     }
 }
 
+/// The identity External-DNS uses for a record: name, type and set identifier.
+/// Two endpoints with the same key are the same record, whatever their targets.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordKey {
+    pub dns_name: String,
+    pub record_type: RecordType,
+    pub set_identifier: Option<String>,
+}
+impl RecordKey {
+    /// Build the key of an endpoint.
+    /// # Errors
+    ///
+    /// When the endpoint has no name or no type.
+    pub fn of(endpoint: &Endpoint) -> Result<Self> {
+        Ok(Self {
+            dns_name: endpoint
+                .dns_name
+                .clone()
+                .ok_or_else(|| eyre!("Endpoint without dnsName: {endpoint:?}"))?,
+            record_type: endpoint
+                .record_type
+                .clone()
+                .ok_or_else(|| eyre!("Endpoint without recordType: {endpoint:?}"))?,
+            set_identifier: endpoint.set_identifier.clone(),
+        })
+    }
+}
+
 /// DNS records types
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
 pub enum RecordType {
     A,
     AAAA,
@@ -52,6 +81,33 @@ pub enum RecordType {
     NAPTR,
 }
 
+/// A record with only a name, a type and targets, for tests.
+#[cfg(test)]
+pub(crate) fn ep(dns_name: &str, record_type: RecordType, targets: &[&str]) -> Endpoint {
+    Endpoint {
+        dns_name: Some(dns_name.to_string()),
+        targets: Some(targets.iter().map(ToString::to_string).collect()),
+        record_type: Some(record_type),
+        set_identifier: None,
+        record_ttl: None,
+        labels: None,
+        provider_specific: None,
+    }
+}
+
+#[cfg(test)]
+impl Endpoint {
+    /// The same record with `ttl` set, for tests.
+    #[cfg_attr(
+        not(any(feature = "powerdns", feature = "rest", feature = "rfc2136")),
+        allow(dead_code)
+    )]
+    pub(crate) const fn with_ttl(mut self, ttl: i64) -> Self {
+        self.record_ttl = Some(ttl);
+        self
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
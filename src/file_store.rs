@@ -0,0 +1,345 @@
+//! Safe reads and writes of the files file-based providers keep their records in.
+//!
+//! Writers hold an advisory lock on a `<file>.lock` companion file, write a temporary file
+//! `<file>.tmp-<pid>` created with the mode of the file, give it the owner of the file, sync it
+//! and rename it over the file, so readers such as the DNS daemon never see a truncated file. Former versions are kept as
+//! `<file>.1` (the latest) to `<file>.N`.
+//!
+//! When the file is a symlink, the file it points to is replaced, and the symlink kept.
+//! When the file cannot be renamed over, as a single file bind mount (`EBUSY`), it is
+//! written in place instead, which readers may see half written.
+//!
+//! The companion files sit next to the file: a daemon reading a whole directory, such as
+//! dnsmasq `conf-dir`, should only read the file's extension (`conf-dir=/etc/dnsmasq.d/,*.conf`),
+//! and watchers should look for the rename (`moved_to`) rather than the creation of files.
+//!
+//! All of it blocks: async callers run it through [`unblock`].
+
+use std::{
+    fs::{self, File, Metadata},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use eyre::{Result, eyre};
+use tracing::warn;
+
+/// A file, written atomically under an advisory lock.
+///
+/// The store remembers the hash of the content it last read or wrote, to notice
+/// when someone else changed the file in between. Clones share it.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    path: PathBuf,
+    backups: usize,
+    last_hash: Arc<Mutex<Option<u64>>>,
+}
+impl FileStore {
+    /// Constructor of `FileStore`, keeping no backups.
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            backups: 0,
+            last_hash: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Keep that many former versions of the file.
+    #[must_use]
+    pub const fn with_backups(mut self, backups: usize) -> Self {
+        self.backups = backups;
+        self
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The content of the file, empty when it does not exist.
+    /// # Errors
+    ///
+    /// When the file cannot be locked or read.
+    pub fn read(&self) -> Result<String> {
+        let lock = self.lock(false)?;
+        let content = self.read_unlocked()?;
+        drop(lock);
+        self.remember(&content);
+        Ok(content)
+    }
+
+    /// Replace the content of the file.
+    /// # Errors
+    ///
+    /// When the file changed since this store last read or wrote it,
+    /// or it cannot be locked or written.
+    pub fn write(&self, content: &str) -> Result<()> {
+        let lock = self.lock(true)?;
+        let current = self.read_unlocked()?;
+        let last = *self
+            .last_hash
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if last.is_some_and(|h| h != hash(&current)) {
+            return Err(eyre!(
+                "{} was modified by someone else, not overwriting it",
+                self.path.display()
+            ));
+        }
+        self.replace(&current, content)?;
+        drop(lock);
+        self.remember(content);
+        Ok(())
+    }
+
+    /// Read, change and write the file under one lock.
+    /// Changes made by someone else since the last read or write are kept, and logged.
+    /// # Errors
+    ///
+    /// When `change` fails, or the file cannot be locked, read or written.
+    pub fn update(&self, change: impl FnOnce(&str) -> Result<String>) -> Result<()> {
+        let lock = self.lock(true)?;
+        let current = self.read_unlocked()?;
+        let last = *self
+            .last_hash
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if last.is_some_and(|h| h != hash(&current)) {
+            warn!(target: "file_store", message = format!(
+                "{} was modified by someone else since last read", self.path.display()
+            ));
+        }
+        let content = change(&current)?;
+        if content != current {
+            self.replace(&current, &content)?;
+        }
+        drop(lock);
+        self.remember(&content);
+        Ok(())
+    }
+
+    fn read_unlocked(&self) -> Result<String> {
+        match fs::read_to_string(&self.path) {
+            Ok(x) => Ok(x),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(eyre!("Cannot read {}: {e}", self.path.display())),
+        }
+    }
+
+    fn remember(&self, content: &str) {
+        *self
+            .last_hash
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(hash(content));
+    }
+
+    fn with_suffix(&self, suffix: &str) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(suffix);
+        PathBuf::from(name)
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<File> {
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.with_suffix(".lock"))?;
+        if exclusive {
+            file.lock()?;
+        } else {
+            file.lock_shared()?;
+        }
+        Ok(file)
+    }
+
+    // Rotate the backups, then swap in the new content. Called under the exclusive lock.
+    fn replace(&self, current: &str, content: &str) -> Result<()> {
+        let metadata = fs::metadata(&self.path).ok();
+        if self.backups > 0 && metadata.is_some() {
+            for i in (1..self.backups).rev() {
+                let from = self.with_suffix(&format!(".{i}"));
+                if from.exists() {
+                    fs::rename(&from, self.with_suffix(&format!(".{}", i + 1)))?;
+                }
+            }
+            let mut backup = create(&self.with_suffix(".1"), metadata.as_ref())?;
+            backup.write_all(current.as_bytes())?;
+        }
+
+        // Through symlinks, so that they stay.
+        let target = match fs::symlink_metadata(&self.path) {
+            Ok(m) if m.file_type().is_symlink() => fs::canonicalize(&self.path)
+                .map_err(|e| eyre!("Cannot resolve {}: {e}", self.path.display()))?,
+            _ => self.path.clone(),
+        };
+        let mut tmp = target.clone().into_os_string();
+        tmp.push(format!(".tmp-{}", std::process::id()));
+        let tmp = PathBuf::from(tmp);
+        let mut file = create(&tmp, metadata.as_ref())?;
+        if let Some(metadata) = &metadata {
+            keep_owner(&file, metadata)?;
+        }
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        drop(file);
+        match fs::rename(&tmp, &target) {
+            Ok(()) => {
+                // The rename itself is durable once the directory is synced.
+                #[cfg(unix)]
+                if let Some(dir) = target.parent().filter(|d| !d.as_os_str().is_empty()) {
+                    File::open(dir)?.sync_all()?;
+                }
+            }
+            // A bind mounted file, or a temporary file on another file system.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::ResourceBusy | ErrorKind::CrossesDevices
+                ) =>
+            {
+                warn!(target: "file_store", message = format!(
+                    "Cannot rename over {} ({e}), writing it in place", target.display()
+                ));
+                fs::remove_file(&tmp)?;
+                let mut file = File::options().write(true).truncate(true).open(&target)?;
+                file.write_all(content.as_bytes())?;
+                file.sync_all()?;
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                return Err(eyre!("Cannot replace {}: {e}", target.display()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run blocking file I/O, such as that of a `FileStore`, off the async workers.
+/// # Errors
+///
+/// When `f` fails or panics.
+pub async fn unblock<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| Err(eyre!("File I/O panicked: {e}")))
+}
+
+// Create a file afresh with the mode of the former version of a file, if any,
+// so that it is never more readable than that one.
+fn create(path: &Path, metadata: Option<&Metadata>) -> Result<File> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let mut options = File::options();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if let Some(metadata) = metadata {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(metadata.permissions().mode() & 0o7777);
+    }
+    let file = options.open(path)?;
+    // The mode given at creation is masked by the umask.
+    if let Some(metadata) = metadata {
+        file.set_permissions(metadata.permissions())?;
+    }
+    Ok(file)
+}
+
+// Give the new version of a file the owner, when allowed, of the former one.
+fn keep_owner(file: &File, metadata: &Metadata) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, fchown};
+        match fchown(file, Some(metadata.uid()), Some(metadata.gid())) {
+            Err(e) if e.kind() != ErrorKind::PermissionDenied => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let dir = std::env::temp_dir().join(format!("file-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("records.conf");
+        let store = FileStore::new(&path).with_backups(2);
+
+        assert_eq!(store.read().unwrap(), "");
+        for i in 1..=3 {
+            store.write(&format!("v{i}")).unwrap();
+        }
+        assert_eq!(store.read().unwrap(), "v3");
+        assert_eq!(
+            fs::read_to_string(dir.join("records.conf.1")).unwrap(),
+            "v2"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("records.conf.2")).unwrap(),
+            "v1"
+        );
+        assert!(!dir.join("records.conf.3").exists());
+
+        // Someone else edits the file: blind writes are refused, updates keep the edit.
+        fs::write(&path, "edited").unwrap();
+        assert!(store.write("v4").is_err());
+        store.update(|current| Ok(format!("{current}+v4"))).unwrap();
+        assert_eq!(store.read().unwrap(), "edited+v4");
+        store.write("v5").unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_mode_and_symlinks() {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        let dir = std::env::temp_dir().join(format!("file-store-link-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("records.conf");
+        fs::write(&path, "v1").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        let link = dir.join("link.conf");
+        symlink(&path, &link).unwrap();
+
+        let store = FileStore::new(&link).with_backups(1);
+        store.update(|_| Ok("v2".to_string())).unwrap();
+        assert!(
+            fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "v2");
+        // Neither the new version nor the backup are more readable than the file was.
+        for file in [&path, &dir.join("link.conf.1")] {
+            assert_eq!(
+                fs::metadata(file).unwrap().permissions().mode() & 0o777,
+                0o600
+            );
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
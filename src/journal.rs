@@ -0,0 +1,437 @@
+//! Journal of the changes applied to a `Provider`, with snapshots of its records to restore.
+//!
+//! `Journaled` wraps a `Provider`, appending every `Changes` it is given to a `JournalBackend`
+//! before applying them, and the outcome after. It also saves snapshots of `records()`, periodically or on demand,
+//! and restores the backend to any of them by applying the needed `Changes`.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    changes::Changes, domain_filter::DomainFilter, endpoint::Endpoint, file_store::unblock,
+    provider::Provider,
+};
+
+/// One `apply_changes` call, before or after the changes are applied.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// In milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Not the id of the HTTP request, but of the call within the journal: its timestamp and
+    /// a sequence number, the same in the entry before and the entry after the changes.
+    pub request_id: String,
+    /// The changes, in the entry before only.
+    #[serde(default, skip_serializing_if = "Changes::is_empty")]
+    pub changes: Changes,
+    /// If the entry is the one before the changes. Without an entry after, the process
+    /// stopped while applying them.
+    #[serde(default)]
+    pub pending: bool,
+    /// The error, when the changes failed.
+    pub error: Option<String>,
+}
+
+/// The records of the backend at some point.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// In milliseconds since the Unix epoch. It identifies the snapshot.
+    pub timestamp: u64,
+    pub records: Vec<Endpoint>,
+}
+
+/// Where journal entries and snapshots are kept.
+#[async_trait]
+pub trait JournalBackend: Send + Sync + std::fmt::Debug {
+    /// Append an entry. Entries are never changed afterwards.
+    async fn append(&self, entry: &JournalEntry) -> Result<()>;
+    /// All entries, oldest first.
+    async fn entries(&self) -> Result<Vec<JournalEntry>>;
+    async fn save_snapshot(&self, snapshot: &Snapshot) -> Result<()>;
+    /// The timestamps of the snapshots, oldest first.
+    async fn snapshots(&self) -> Result<Vec<u64>>;
+    async fn load_snapshot(&self, timestamp: u64) -> Result<Snapshot>;
+}
+
+/// A `JournalBackend` in a local directory: `journal.jsonl`, and one
+/// `snapshot-<timestamp>.json` per snapshot.
+#[derive(Debug)]
+pub struct FileJournal {
+    dir: PathBuf,
+    journal: Arc<Mutex<File>>,
+}
+impl FileJournal {
+    /// Constructor of `FileJournal`, creating the directory if needed.
+    /// # Errors
+    ///
+    /// When the directory or the journal file cannot be created.
+    pub fn create(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join("journal.jsonl"))?;
+        Ok(Self {
+            dir,
+            journal: Arc::new(Mutex::new(journal)),
+        })
+    }
+
+    fn snapshot_path(&self, timestamp: u64) -> PathBuf {
+        self.dir.join(format!("snapshot-{timestamp}.json"))
+    }
+}
+#[async_trait]
+impl JournalBackend for FileJournal {
+    async fn append(&self, entry: &JournalEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let journal = self.journal.clone();
+        unblock(move || {
+            let mut file = journal.lock().unwrap_or_else(PoisonError::into_inner);
+            file.write_all(&line)?;
+            file.sync_data()?;
+            drop(file);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn entries(&self) -> Result<Vec<JournalEntry>> {
+        let path = self.dir.join("journal.jsonl");
+        unblock(move || {
+            BufReader::new(File::open(path)?)
+                .lines()
+                .filter(|l| l.as_ref().map_or(true, |l| !l.trim().is_empty()))
+                .map(|l| Ok(serde_json::from_str(&l?)?))
+                .collect()
+        })
+        .await
+    }
+
+    async fn save_snapshot(&self, snapshot: &Snapshot) -> Result<()> {
+        // Written aside then renamed, so a snapshot is never half written.
+        let path = self.snapshot_path(snapshot.timestamp);
+        let timestamp = snapshot.timestamp;
+        let content = serde_json::to_vec(snapshot)?;
+        unblock(move || {
+            if path.exists() {
+                return Err(eyre!("Snapshot {timestamp} exists already"));
+            }
+            let tmp = path.with_extension("json.tmp");
+            fs::write(&tmp, content)?;
+            fs::rename(tmp, path)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn snapshots(&self) -> Result<Vec<u64>> {
+        let dir = self.dir.clone();
+        unblock(move || {
+            let mut ret = Vec::new();
+            for entry in fs::read_dir(dir)? {
+                let name = entry?.file_name();
+                if let Some(timestamp) = name
+                    .to_str()
+                    .and_then(|n| n.strip_prefix("snapshot-"))
+                    .and_then(|n| n.strip_suffix(".json"))
+                    .and_then(|n| n.parse().ok())
+                {
+                    ret.push(timestamp);
+                }
+            }
+            ret.sort_unstable();
+            Ok(ret)
+        })
+        .await
+    }
+
+    async fn load_snapshot(&self, timestamp: u64) -> Result<Snapshot> {
+        let path = self.snapshot_path(timestamp);
+        let content = unblock(move || {
+            fs::read(&path).map_err(|e| eyre!("Cannot read snapshot {}: {e}", path.display()))
+        })
+        .await?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+}
+
+/// A `Provider` journaling the changes applied to the wrapped one.
+#[derive(Debug)]
+pub struct Journaled {
+    inner: Arc<dyn Provider>,
+    backend: Arc<dyn JournalBackend>,
+    sequence: AtomicU64,
+    /// The timestamp of the last snapshot, so that the next one is later.
+    last_snapshot: AtomicU64,
+}
+impl Journaled {
+    /// Constructor of `Journaled`.
+    #[must_use]
+    pub fn new(inner: Arc<dyn Provider>, backend: Arc<dyn JournalBackend>) -> Self {
+        Self {
+            inner,
+            backend,
+            sequence: AtomicU64::new(0),
+            last_snapshot: AtomicU64::new(0),
+        }
+    }
+
+    /// Save a snapshot of the current records, returning its timestamp.
+    /// # Errors
+    ///
+    /// When the records cannot be read or the snapshot saved.
+    #[instrument(skip_all)]
+    pub async fn snapshot(&self) -> Result<u64> {
+        let records = self.inner.records().await?;
+        // Two snapshots within a millisecond get distinct timestamps.
+        let now = now();
+        let last = self
+            .last_snapshot
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or_default();
+        let snapshot = Snapshot {
+            timestamp: now.max(last + 1),
+            records,
+        };
+        self.backend.save_snapshot(&snapshot).await?;
+        info!(target: "journal", message = format!(
+            "Snapshot {} of {} records", snapshot.timestamp, snapshot.records.len()
+        ));
+        Ok(snapshot.timestamp)
+    }
+
+    /// Take a snapshot every `period`, until the returned task is aborted.
+    /// Failures are logged, and do not stop the task.
+    #[must_use]
+    pub fn spawn_snapshots(self: Arc<Self>, period: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.snapshot().await {
+                    warn!(target: "journal", message = format!("Cannot snapshot: {e:?}"));
+                }
+            }
+        })
+    }
+
+    /// Bring the records back to the snapshot of `timestamp`, returning the changes applied.
+    /// The restore is journaled as any other change.
+    /// # Errors
+    ///
+    /// When the snapshot cannot be loaded, or the changes fail.
+    #[instrument(skip_all)]
+    pub async fn restore(&self, timestamp: u64) -> Result<Changes> {
+        let snapshot = self.backend.load_snapshot(timestamp).await?;
+        let changes = Changes::between(&self.inner.records().await?, &snapshot.records)?;
+        if !changes.is_empty() {
+            self.apply_changes(changes.clone()).await?;
+        }
+        Ok(changes)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+#[async_trait]
+impl Provider for Journaled {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        self.inner.domain_filter().await
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        self.inner.records().await
+    }
+
+    /// The changes are journaled before they are applied, and the outcome after.
+    /// Failing to journal either fails the request, the changes not being applied on the
+    /// first failure.
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        let timestamp = now();
+        let request_id = format!(
+            "{timestamp}-{}",
+            self.sequence.fetch_add(1, Ordering::Relaxed)
+        );
+        let pending = JournalEntry {
+            timestamp,
+            request_id: request_id.clone(),
+            changes: changes.clone(),
+            pending: true,
+            error: None,
+        };
+        self.backend
+            .append(&pending)
+            .await
+            .map_err(|e| eyre!("Cannot journal {request_id}, not applying it: {e:?}"))?;
+        let result = self.inner.apply_changes(changes).await;
+        let outcome = JournalEntry {
+            timestamp: now(),
+            request_id,
+            changes: Changes::default(),
+            pending: false,
+            error: result.as_ref().err().map(|e| format!("{e:?}")),
+        };
+        self.backend.append(&outcome).await.map_err(|e| {
+            eyre!(
+                "Cannot journal the outcome of {}: {e:?}",
+                outcome.request_id
+            )
+        })?;
+        result
+    }
+
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        self.inner.adjust_endpoints(endpoints).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::ep;
+    use crate::{endpoint::RecordType, providers::memory::InMemoryProvider};
+
+    #[tokio::test]
+    async fn it_works() {
+        let dir = std::env::temp_dir().join(format!("journal-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let inner = Arc::new(InMemoryProvider::default());
+        inner
+            .seed([ep(
+                "nextcloud.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.102"],
+            )])
+            .unwrap();
+        let backend = Arc::new(FileJournal::create(&dir).unwrap());
+        let journaled = Journaled::new(inner.clone(), backend.clone());
+
+        let snapshot = journaled.snapshot().await.unwrap();
+        // Snapshots within a millisecond do not overwrite each other.
+        let second = journaled.snapshot().await.unwrap();
+        assert!(second > snapshot);
+        journaled
+            .apply_changes(Changes {
+                create: vec![ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"])],
+                delete: vec![ep(
+                    "nextcloud.magicloud.lan",
+                    RecordType::A,
+                    &["192.168.0.102"],
+                )],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        // Deleting a missing record fails, and is journaled as such.
+        assert!(
+            journaled
+                .apply_changes(Changes {
+                    delete: vec![ep("wiki.magicloud.lan", RecordType::A, &["192.168.0.104"])],
+                    ..Changes::default()
+                })
+                .await
+                .is_err()
+        );
+
+        let restored = journaled.restore(snapshot).await.unwrap();
+        assert_eq!(
+            restored.create,
+            vec![ep(
+                "nextcloud.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.102"]
+            )]
+        );
+        assert_eq!(
+            restored.delete,
+            vec![ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"])]
+        );
+        assert_eq!(
+            inner.endpoints(),
+            vec![ep(
+                "nextcloud.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.102"]
+            )]
+        );
+
+        // Each call is journaled before, with the changes, and after, with the outcome.
+        let entries = backend.entries().await.unwrap();
+        assert_eq!(entries.len(), 6);
+        assert!(entries[0].pending && !entries[0].changes.is_empty());
+        assert!(!entries[1].pending && entries[1].changes.is_empty());
+        assert_eq!(entries[0].request_id, entries[1].request_id);
+        assert!(entries[1].error.is_none());
+        assert!(entries[3].error.is_some());
+        assert_ne!(entries[0].request_id, entries[2].request_id);
+        assert_eq!(entries[4].changes, restored);
+        assert_eq!(backend.snapshots().await.unwrap(), vec![snapshot, second]);
+        let existing = backend.load_snapshot(snapshot).await.unwrap();
+        assert!(backend.save_snapshot(&existing).await.is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // A backend nothing can be written to.
+    #[derive(Debug)]
+    struct Full;
+    #[async_trait]
+    impl JournalBackend for Full {
+        async fn append(&self, _: &JournalEntry) -> Result<()> {
+            Err(eyre!("No space left on device"))
+        }
+        async fn entries(&self) -> Result<Vec<JournalEntry>> {
+            Ok(vec![])
+        }
+        async fn save_snapshot(&self, _: &Snapshot) -> Result<()> {
+            Err(eyre!("No space left on device"))
+        }
+        async fn snapshots(&self) -> Result<Vec<u64>> {
+            Ok(vec![])
+        }
+        async fn load_snapshot(&self, timestamp: u64) -> Result<Snapshot> {
+            Err(eyre!("No snapshot {timestamp}"))
+        }
+    }
+
+    #[tokio::test]
+    async fn not_applied_without_journal() {
+        let inner = Arc::new(InMemoryProvider::default());
+        let journaled = Journaled::new(inner.clone(), Arc::new(Full));
+        assert!(
+            journaled
+                .apply_changes(Changes {
+                    create: vec![ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"])],
+                    ..Changes::default()
+                })
+                .await
+                .is_err()
+        );
+        assert!(inner.endpoints().is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+//! Typed access to the `labels` External-DNS puts on endpoints.
+
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    endpoint::Endpoint,
+    registry::{OWNER_LABEL, RESOURCE_LABEL},
+};
+
+/// Label of the TXT registry records, naming the record they own.
+pub const OWNED_RECORD_LABEL: &str = "ownedRecord";
+/// Label of the AWS provider, enabling target health evaluation of alias records.
+pub const AWS_EVALUATE_TARGET_HEALTH_LABEL: &str = "aws/evaluate-target-health";
+
+/// The labels of an endpoint, with accessors for the keys External-DNS knows.
+/// Other keys are kept as they are.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Labels(HashMap<String, String>);
+impl Labels {
+    /// Constructor of `Labels`, with no labels.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `--txt-owner-id` of the External-DNS instance owning the record.
+    #[must_use]
+    pub fn owner(&self) -> Option<&str> {
+        self.get(OWNER_LABEL)
+    }
+
+    /// Set the owner, as `--txt-owner-id`.
+    pub fn set_owner(&mut self, owner: &str) {
+        self.insert(OWNER_LABEL, owner);
+    }
+
+    /// The resource the record comes from.
+    /// # Errors
+    ///
+    /// When the label is not in `kind/namespace/name` form.
+    pub fn resource(&self) -> Result<Option<Resource>> {
+        self.get(RESOURCE_LABEL).map(str::parse).transpose()
+    }
+
+    /// Set the resource the record comes from.
+    pub fn set_resource(&mut self, resource: &Resource) {
+        self.insert(RESOURCE_LABEL, &resource.to_string());
+    }
+
+    /// The name of the record a TXT registry record owns.
+    #[must_use]
+    pub fn owned_record(&self) -> Option<&str> {
+        self.get(OWNED_RECORD_LABEL)
+    }
+
+    /// Set the name of the record a TXT registry record owns.
+    pub fn set_owned_record(&mut self, dns_name: &str) {
+        self.insert(OWNED_RECORD_LABEL, dns_name);
+    }
+
+    /// `aws/evaluate-target-health`.
+    /// # Errors
+    ///
+    /// When the label is neither `true` nor `false`.
+    pub fn evaluate_target_health(&self) -> Result<Option<bool>> {
+        self.get(AWS_EVALUATE_TARGET_HEALTH_LABEL)
+            .map(|v| {
+                v.parse()
+                    .map_err(|_| eyre!("Bad {AWS_EVALUATE_TARGET_HEALTH_LABEL} label: {v}"))
+            })
+            .transpose()
+    }
+
+    /// Set `aws/evaluate-target-health`.
+    pub fn set_evaluate_target_health(&mut self, evaluate: bool) {
+        self.insert(AWS_EVALUATE_TARGET_HEALTH_LABEL, &evaluate.to_string());
+    }
+
+    /// Any label, known or not.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// Set any label, known or not.
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.0.insert(key.to_string(), value.to_string());
+    }
+
+    /// Remove any label, giving its value.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.0.remove(key)
+    }
+
+    /// If there are no labels.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The raw map, as in `Endpoint::labels`.
+    #[must_use]
+    pub fn into_inner(self) -> HashMap<String, String> {
+        self.0
+    }
+
+    /// All labels, known or not.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+impl From<HashMap<String, String>> for Labels {
+    fn from(labels: HashMap<String, String>) -> Self {
+        Self(labels)
+    }
+}
+
+impl Endpoint {
+    /// The labels of the endpoint, empty when there are none.
+    #[must_use]
+    pub fn typed_labels(&self) -> Labels {
+        self.labels.clone().map(Labels::from).unwrap_or_default()
+    }
+
+    /// Replace the labels of the endpoint. No labels are sent when empty.
+    pub fn set_typed_labels(&mut self, labels: Labels) {
+        self.labels = (!labels.is_empty()).then(|| labels.into_inner());
+    }
+}
+
+/// The Kubernetes resource a record comes from, as in `ingress/nextcloud/nextcloud`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Resource {
+    /// Such as `ingress`, `service` or `crd`.
+    pub kind: String,
+    pub namespace: String,
+    pub name: String,
+}
+impl FromStr for Resource {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.splitn(3, '/').collect::<Vec<_>>()[..] {
+            [kind, namespace, name] if !kind.is_empty() && !name.is_empty() => Ok(Self {
+                kind: kind.to_string(),
+                namespace: namespace.to_string(),
+                name: name.to_string(),
+            }),
+            _ => Err(eyre!("Resource not in kind/namespace/name form: {s}")),
+        }
+    }
+}
+impl Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}", self.kind, self.namespace, self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let mut ep: Endpoint = serde_json::from_str(
+            r#"{
+            "dnsName": "nextcloud.magicloud.lan",
+            "targets": ["192.168.0.102"],
+            "recordType": "A",
+            "labels": {
+                "owner": "default",
+                "resource": "ingress/nextcloud/nextcloud",
+                "aws/evaluate-target-health": "true",
+                "custom": "kept"
+            }
+}"#,
+        )
+        .unwrap();
+        let mut labels = ep.typed_labels();
+        assert_eq!(labels.owner(), Some("default"));
+        let resource = labels.resource().unwrap().unwrap();
+        assert_eq!(
+            (resource.kind.as_str(), resource.namespace.as_str()),
+            ("ingress", "nextcloud")
+        );
+        assert_eq!(labels.evaluate_target_health().unwrap(), Some(true));
+        assert_eq!(labels.owned_record(), None);
+
+        labels.set_owner("other");
+        labels.set_resource(&"service/default/gitea".parse().unwrap());
+        ep.set_typed_labels(labels);
+        let json = serde_json::to_value(&ep).unwrap();
+        assert_eq!(json["labels"]["owner"], "other");
+        assert_eq!(json["labels"]["resource"], "service/default/gitea");
+        assert_eq!(json["labels"]["custom"], "kept");
+
+        assert!("ingress".parse::<Resource>().is_err());
+        ep.set_typed_labels(Labels::new());
+        assert_eq!(ep.labels, None);
+    }
+}
@@ -12,15 +12,26 @@
 #![doc = include_str!("../README.md")]
 
 pub mod changes;
+#[cfg(feature = "client")]
+pub mod client;
 pub mod domain_filter;
 pub mod endpoint;
+pub mod file_store;
+pub mod journal;
+pub mod labels;
 mod provider;
+pub mod providers;
+pub mod recorder;
+pub mod registry;
 mod status;
+#[cfg(feature = "testing")]
+pub mod testing;
 mod webhook;
 mod webhook_json;
+pub mod wrappers;
 
 const MEDIATYPE: &str = "application/external.dns.webhook+json;version=1";
 
 pub use provider::Provider;
 pub use status::Status;
-pub use webhook::Webhook;
+pub use webhook::{Rejected, Webhook};
@@ -0,0 +1,349 @@
+//! A `Provider` managing the DNS rewrites of `AdGuard` Home, through its REST API.
+//!
+//! Each rewrite maps a domain to one answer: an address makes an A or AAAA record, a domain
+//! a CNAME record. The special answers `A` and `AAAA`, which keep the upstream records, and
+//! disabled rewrites are not listed. Rewrites are added and deleted one by one, with HTTP basic authentication.
+
+use std::fmt::{self, Debug, Formatter};
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use reqwest::{Client, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument};
+
+use super::rules::{self, Rule, Step};
+use crate::{
+    changes::Changes, domain_filter::DomainFilter, endpoint::Endpoint, provider::Provider,
+};
+
+/// A `Provider` calling the `AdGuard` Home API.
+#[derive(Clone)]
+pub struct AdGuardProvider {
+    domain_filter: DomainFilter,
+    base_url: String,
+    username: String,
+    password: String,
+    http: Client,
+}
+impl Debug for AdGuardProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdGuardProvider")
+            .field("domain_filter", &self.domain_filter)
+            .field("base_url", &self.base_url)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+impl AdGuardProvider {
+    /// Constructor of `AdGuardProvider`.
+    /// `base_url` is the web interface, such as `http://192.168.0.1:3000`.
+    #[must_use]
+    pub fn new(
+        domain_filter: DomainFilter,
+        base_url: &str,
+        username: &str,
+        password: &str,
+    ) -> Self {
+        Self {
+            domain_filter,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+            http: Client::new(),
+        }
+    }
+
+    /// Use a configured `reqwest::Client` (timeouts, TLS, ...).
+    #[must_use]
+    pub fn with_client(mut self, http: Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    fn request(
+        &self,
+        builder: fn(&Client, String) -> RequestBuilder,
+        path: &str,
+    ) -> RequestBuilder {
+        builder(
+            &self.http,
+            format!("{}/control/rewrite/{path}", self.base_url),
+        )
+        .basic_auth(&self.username, Some(&self.password))
+    }
+
+    async fn rewrites(&self) -> Result<Vec<Rewrite>> {
+        let body = check(self.request(Client::get, "list").send().await?).await?;
+        serde_json::from_slice(&body).map_err(|e| {
+            eyre!(
+                "Cannot parse AdGuard Home rewrites ({e}): {}",
+                String::from_utf8_lossy(&body)
+            )
+        })
+    }
+
+    async fn post(&self, path: &str, rewrite: &Rewrite) -> Result<()> {
+        check(
+            self.request(Client::post, path)
+                .json(rewrite)
+                .send()
+                .await?,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Rewrite {
+    domain: String,
+    answer: String,
+    // Only recent versions have it, and match it on delete.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    enabled: Option<bool>,
+}
+impl Rewrite {
+    fn rule(&self) -> Option<Rule> {
+        (self.answer != "A" && self.answer != "AAAA" && self.enabled != Some(false))
+            .then(|| Rule::of(&self.domain, &self.answer))
+    }
+}
+
+// AdGuard Home explains errors in plain text.
+async fn check(res: Response) -> Result<Vec<u8>> {
+    let status = res.status();
+    let url = res.url().clone();
+    let body = res.bytes().await?.to_vec();
+    if status.is_success() {
+        return Ok(body);
+    }
+    Err(eyre!(
+        "{url}: {status}: {}",
+        String::from_utf8_lossy(&body).trim()
+    ))
+}
+
+#[async_trait]
+impl Provider for AdGuardProvider {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        Ok(self.domain_filter.clone())
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        Ok(rules::endpoints(
+            self.rewrites().await?.iter().filter_map(Rewrite::rule),
+        ))
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        let steps = rules::steps(&changes)?;
+        if steps.is_empty() {
+            return Ok(());
+        }
+        info!(target: "adguard", message = format!("Applying {}", changes.summary()));
+        let mut rewrites = self.rewrites().await?;
+        for step in steps {
+            let (Step::Remove(rule) | Step::Add(rule)) = &step;
+            let found: Vec<Rewrite> = rewrites
+                .iter()
+                .filter(|r| r.rule().as_ref() == Some(rule))
+                .cloned()
+                .collect();
+            match step {
+                Step::Remove(_) => {
+                    for rewrite in found {
+                        self.post("delete", &rewrite).await?;
+                        rewrites.retain(|r| *r != rewrite);
+                    }
+                }
+                Step::Add(rule) if found.is_empty() => {
+                    let rewrite = Rewrite {
+                        domain: rule.name,
+                        answer: rule.target,
+                        enabled: None,
+                    };
+                    self.post("add", &rewrite).await?;
+                    rewrites.push(rewrite);
+                }
+                Step::Add(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        Ok(rules::adjust("adguard", endpoints))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::ep;
+    use crate::{changes::FromTo, endpoint::RecordType};
+    use actix_web::{
+        App, HttpRequest, HttpResponse, HttpServer,
+        web::{self, Data, Json},
+    };
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    // admin:secret
+    const AUTHORIZATION: &str = "Basic YWRtaW46c2VjcmV0";
+
+    type Rewrites = Arc<Mutex<Vec<Rewrite>>>;
+
+    fn authorized(req: &HttpRequest) -> bool {
+        req.headers()
+            .get("Authorization")
+            .is_some_and(|a| a == AUTHORIZATION)
+    }
+
+    async fn list(req: HttpRequest, rewrites: Data<Rewrites>) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().body("Unauthorized\n");
+        }
+        // Recent versions tell whether each rewrite is enabled.
+        HttpResponse::Ok().json(&*rewrites.lock().unwrap())
+    }
+
+    async fn add(req: HttpRequest, body: Json<Rewrite>, rewrites: Data<Rewrites>) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().body("Unauthorized\n");
+        }
+        let mut body = body.into_inner();
+        body.enabled.get_or_insert(true);
+        let mut rewrites = rewrites.lock().unwrap();
+        if rewrites.contains(&body) {
+            return HttpResponse::BadRequest().body("rewrite already exists\n");
+        }
+        rewrites.push(body);
+        drop(rewrites);
+        HttpResponse::Ok().finish()
+    }
+
+    async fn delete(
+        req: HttpRequest,
+        body: Json<Rewrite>,
+        rewrites: Data<Rewrites>,
+    ) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().body("Unauthorized\n");
+        }
+        let mut rewrites = rewrites.lock().unwrap();
+        if !rewrites.contains(&body) {
+            return HttpResponse::BadRequest().body("rewrite not found\n");
+        }
+        rewrites.retain(|r| *r != *body);
+        drop(rewrites);
+        HttpResponse::Ok().finish()
+    }
+
+    fn serve(rewrites: Rewrites) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(Data::new(rewrites.clone()))
+                .route("/control/rewrite/list", web::get().to(list))
+                .route("/control/rewrite/add", web::post().to(add))
+                .route("/control/rewrite/delete", web::post().to(delete))
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        tokio::spawn(server);
+        url
+    }
+
+    fn rewrite(domain: &str, answer: &str) -> Rewrite {
+        Rewrite {
+            domain: domain.to_string(),
+            answer: answer.to_string(),
+            enabled: Some(true),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_works() {
+        let rewrites: Rewrites = Arc::new(Mutex::new(vec![
+            rewrite("cloud.magicloud.lan", "192.168.0.102"),
+            rewrite("cloud.magicloud.lan", "192.168.0.103"),
+            rewrite("www.magicloud.lan", "cloud.magicloud.lan"),
+            rewrite("ads.magicloud.lan", "AAAA"),
+            Rewrite {
+                enabled: Some(false),
+                ..rewrite("cloud.magicloud.lan", "192.168.0.105")
+            },
+        ]));
+        let url = serve(rewrites.clone());
+        let domain_filter = DomainFilter::Strings {
+            include: Some(vec!["magicloud.lan".to_string()]),
+            exclude: None,
+        };
+        let provider = AdGuardProvider::new(domain_filter.clone(), &url, "admin", "secret");
+
+        let cloud = ep(
+            "cloud.magicloud.lan",
+            RecordType::A,
+            &["192.168.0.102", "192.168.0.103"],
+        );
+        let www = ep(
+            "www.magicloud.lan",
+            RecordType::CNAME,
+            &["cloud.magicloud.lan"],
+        );
+        assert_eq!(
+            provider.records().await.unwrap(),
+            vec![cloud.clone(), www.clone()]
+        );
+
+        let moved = ep(
+            "cloud.magicloud.lan",
+            RecordType::A,
+            &["192.168.0.103", "192.168.0.104"],
+        );
+        let cloud6 = ep("cloud.magicloud.lan", RecordType::AAAA, &["fd00::102"]);
+        provider
+            .apply_changes(Changes {
+                create: vec![cloud6.clone()],
+                update: vec![FromTo {
+                    from: cloud,
+                    to: moved.clone(),
+                }],
+                delete: vec![www],
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            *rewrites.lock().unwrap(),
+            [
+                rewrite("cloud.magicloud.lan", "192.168.0.103"),
+                rewrite("ads.magicloud.lan", "AAAA"),
+                Rewrite {
+                    enabled: Some(false),
+                    ..rewrite("cloud.magicloud.lan", "192.168.0.105")
+                },
+                rewrite("cloud.magicloud.lan", "fd00::102"),
+                rewrite("cloud.magicloud.lan", "192.168.0.104"),
+            ]
+        );
+        assert_eq!(provider.records().await.unwrap(), vec![moved, cloud6]);
+
+        let wrong = AdGuardProvider::new(domain_filter, &url, "admin", "wrong");
+        assert!(!format!("{wrong:?}").contains("wrong"));
+        let error = wrong.records().await.unwrap_err();
+        assert!(
+            format!("{error}").ends_with("401 Unauthorized: Unauthorized"),
+            "{error}"
+        );
+    }
+}
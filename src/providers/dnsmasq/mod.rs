@@ -0,0 +1,703 @@
+//! A `Provider` writing records into a dnsmasq configuration file.
+//!
+//! # Configuration format
+//!
+//! The file is a list of blocks separated by blank lines. Each block managed by the provider
+//! starts with a comment holding the endpoint as JSON, followed by the dnsmasq directives
+//! serving it. The comment is what `records` reads back; the directives are what dnsmasq reads.
+//!
+//! ```text
+//! # {"dnsName":"nextcloud.magicloud.lan","targets":["192.168.0.102"],"recordType":"A"}
+//! address=/nextcloud.magicloud.lan/192.168.0.102
+//! ```
+//!
+//! Directives per record type:
+//! - A, AAAA: `address=/name/ip`, or `host-record=name,ip[,ttl]` with `with_host_records`
+//! - CNAME: `cname=name,target[,ttl]`
+//! - TXT: `txt-record=name,text[,text...]`
+//! - PTR: `ptr-record=name,target`, one line per target
+//! - MX: `mx-host=name,host,preference`
+//! - SRV: `srv-host=name,target,port,priority,weight`
+//!
+//! dnsmasq only takes TTLs on `host-record` and `cname`. There, records without a TTL
+//! get the default one, if set.
+//!
+//! A managed block only spans the directives the provider would write for its endpoint,
+//! with its current options: any other line, even without a blank line before it, is
+//! hand-written, as are blocks not starting with such a comment. The records their directives
+//! serve (see `parser`) are listed by `records`, but are read-only: changes touching them fail.
+//! When the file is rewritten, hand-written blocks and blank lines are kept verbatim, and
+//! managed blocks stay where they are; new ones are appended.
+//!
+//! The file is read and rewritten through a [`FileStore`], so dnsmasq never reads a partial
+//! file, and hand edits made while changes are applied are not lost.
+
+use std::{collections::BTreeSet, fmt::Write, path::PathBuf};
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use tracing::{info, instrument};
+
+use crate::{
+    changes::Changes,
+    domain_filter::DomainFilter,
+    endpoint::{Endpoint, RecordKey, RecordType},
+    file_store::{FileStore, unblock},
+    provider::Provider,
+};
+
+pub mod parser;
+
+/// The record types the provider writes.
+pub const SUPPORTED_TYPES: [RecordType; 7] = [
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::CNAME,
+    RecordType::TXT,
+    RecordType::PTR,
+    RecordType::MX,
+    RecordType::SRV,
+];
+
+/// A `Provider` keeping records in a dnsmasq configuration file,
+/// such as one in the `conf-dir` of dnsmasq.
+#[derive(Debug, Clone)]
+pub struct DnsmasqProvider {
+    domain_filter: DomainFilter,
+    store: FileStore,
+    default_ttl: Option<i64>,
+    host_records: bool,
+}
+impl DnsmasqProvider {
+    /// Constructor of `DnsmasqProvider`.
+    #[must_use]
+    pub fn new(domain_filter: DomainFilter, conf_filename: impl Into<PathBuf>) -> Self {
+        Self {
+            domain_filter,
+            store: FileStore::new(conf_filename),
+            default_ttl: None,
+            host_records: false,
+        }
+    }
+
+    /// The TTL of records without one, on directives taking a TTL.
+    #[must_use]
+    pub const fn with_default_ttl(mut self, ttl: i64) -> Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    /// Serve A and AAAA records with `host-record`, which takes a TTL and answers
+    /// for the exact name only, instead of `address`, which answers for subdomains too.
+    #[must_use]
+    pub const fn with_host_records(mut self, host_records: bool) -> Self {
+        self.host_records = host_records;
+        self
+    }
+
+    /// Keep that many former versions of the configuration file, see [`FileStore`].
+    #[must_use]
+    pub fn with_backups(mut self, backups: usize) -> Self {
+        self.store = self.store.with_backups(backups);
+        self
+    }
+
+    fn read(&self) -> Result<Conf> {
+        Ok(Conf::parse(&self.store.read()?, |ep| self.directives(ep)))
+    }
+
+    /// The directives of one endpoint, without the JSON comment.
+    /// # Errors
+    ///
+    /// When the endpoint misses data, or its type is not supported.
+    pub fn directives(&self, endpoint: &Endpoint) -> Result<String> {
+        let dns_name = endpoint
+            .dns_name
+            .as_deref()
+            .ok_or_else(|| eyre!("No dnsName in {endpoint:?}"))?;
+        let targets = endpoint
+            .targets
+            .as_deref()
+            .filter(|t| !t.is_empty())
+            .ok_or_else(|| eyre!("No targets in {endpoint:?}"))?;
+        let ttl = endpoint
+            .record_ttl
+            .filter(|t| *t > 0)
+            .or(self.default_ttl)
+            .map(|t| format!(",{t}"))
+            .unwrap_or_default();
+        let mut out = String::new();
+        match endpoint.record_type {
+            // dnsmasq keeps one address per family of a `host-record`: one line per target.
+            Some(RecordType::A | RecordType::AAAA) if self.host_records => {
+                for target in targets {
+                    let _ = writeln!(out, "host-record={dns_name},{target}{ttl}");
+                }
+            }
+            Some(RecordType::A | RecordType::AAAA) => {
+                for target in targets {
+                    let _ = writeln!(out, "address=/{dns_name}/{target}");
+                }
+            }
+            Some(RecordType::CNAME) => {
+                for target in targets {
+                    let _ = writeln!(out, "cname={dns_name},{target}{ttl}");
+                }
+            }
+            Some(RecordType::TXT) => {
+                let _ = writeln!(out, "txt-record={dns_name},{}", targets.join(","));
+            }
+            Some(RecordType::PTR) => {
+                for target in targets {
+                    let _ = writeln!(out, "ptr-record={dns_name},{target}");
+                }
+            }
+            Some(RecordType::MX) => {
+                for target in targets {
+                    let (preference, host) = target
+                        .split_once(' ')
+                        .ok_or_else(|| eyre!("MX target not as `preference host`: {target}"))?;
+                    let _ = writeln!(out, "mx-host={dns_name},{host},{preference}");
+                }
+            }
+            Some(RecordType::SRV) => {
+                for target in targets {
+                    let [priority, weight, port, host] = target
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .try_into()
+                        .map_err(|_| {
+                            eyre!("SRV target not as `priority weight port target`: {target}")
+                        })?;
+                    let _ = writeln!(out, "srv-host={dns_name},{host},{port},{priority},{weight}");
+                }
+            }
+            _ => return Err(eyre!("Unsupported record type: {endpoint:?}")),
+        }
+        Ok(out)
+    }
+
+    /// The block of one endpoint: the JSON comment and the directives.
+    /// # Errors
+    ///
+    /// When the endpoint cannot be written.
+    pub fn block(&self, endpoint: &Endpoint) -> Result<String> {
+        Ok(format!(
+            "# {}\n{}",
+            serde_json::to_string(endpoint)?,
+            self.directives(endpoint)?
+        ))
+    }
+
+    fn render(&self, conf: &Conf) -> Result<String> {
+        let mut ret = String::new();
+        for block in &conf.blocks {
+            match block {
+                Block::Managed(ep) => ret.push_str(&self.block(ep)?),
+                Block::HandWritten(text) | Block::Blank(text) => ret.push_str(text),
+            }
+        }
+        Ok(ret)
+    }
+
+    fn apply(&self, mut conf: Conf, changes: Changes) -> Result<String> {
+        let read_only = conf
+            .read_only
+            .iter()
+            .map(RecordKey::of)
+            .collect::<Result<BTreeSet<_>>>()?;
+        let touched = changes
+            .create
+            .iter()
+            .chain(changes.update.iter().flat_map(|ft| [&ft.from, &ft.to]))
+            .chain(&changes.delete);
+        for ep in touched {
+            if read_only.contains(&RecordKey::of(ep)?) {
+                return Err(eyre!("Hand-written record is read-only: {ep:?}"));
+            }
+        }
+        for ep in changes.delete {
+            conf.remove(&[&RecordKey::of(&ep)?], None);
+        }
+        for ft in changes.update {
+            let (from, to) = (RecordKey::of(&ft.from)?, RecordKey::of(&ft.to)?);
+            if from != to && conf.find(&to).is_some() {
+                return Err(eyre!("Record already exists: {:?}", ft.to));
+            }
+            conf.put(&from, ft.to)?;
+        }
+        for ep in changes.create {
+            let key = RecordKey::of(&ep)?;
+            if conf.find(&key).is_some() {
+                return Err(eyre!("Record already exists: {ep:?}"));
+            }
+            conf.put(&key, ep)?;
+        }
+        self.render(&conf)
+    }
+}
+
+/// A part of the configuration file.
+#[derive(Debug)]
+enum Block {
+    /// Written by the provider, for one endpoint.
+    Managed(Endpoint),
+    /// Not written by the provider, verbatim.
+    HandWritten(String),
+    /// The blank lines between blocks, verbatim.
+    Blank(String),
+}
+
+/// The content of the configuration file.
+#[derive(Debug, Default)]
+struct Conf {
+    /// In the order of the file.
+    blocks: Vec<Block>,
+    /// The records served by the hand-written blocks.
+    read_only: Vec<Endpoint>,
+}
+impl Conf {
+    /// `directives` tells the lines the provider writes for an endpoint.
+    fn parse(content: &str, directives: impl Fn(&Endpoint) -> Result<String>) -> Self {
+        let mut ret = Self::default();
+        let mut block: Vec<&str> = vec![];
+        for line in content.split_inclusive('\n').chain([""]) {
+            if !line.trim().is_empty() {
+                block.push(line);
+                continue;
+            }
+            ret.push_lines(&block, &directives);
+            block.clear();
+            // Not the end of the content.
+            if !line.is_empty() {
+                if let Some(Block::Blank(blank)) = ret.blocks.last_mut() {
+                    blank.push_str(&with_newline(line.to_string()));
+                } else {
+                    ret.blocks
+                        .push(Block::Blank(with_newline(line.to_string())));
+                }
+            }
+        }
+        ret
+    }
+
+    // Lines without blank ones between them. A managed block is the JSON comment and the
+    // directives written for it that follow; any other line is hand-written.
+    fn push_lines(
+        &mut self,
+        mut lines: &[&str],
+        directives: &impl Fn(&Endpoint) -> Result<String>,
+    ) {
+        let line = |l: &&str| l.trim_end_matches(['\r', '\n']).to_string();
+        while let Some(first) = lines.first() {
+            if let Some(ep) = managed(first) {
+                let written = directives(&ep).unwrap_or_default();
+                let len = written
+                    .lines()
+                    .zip(&lines[1..])
+                    .take_while(|(w, l)| *w == line(l))
+                    .count();
+                self.blocks.push(Block::Managed(ep));
+                lines = &lines[1 + len..];
+            } else {
+                let len = lines
+                    .iter()
+                    .position(|l| managed(l).is_some())
+                    .unwrap_or(lines.len());
+                let hand_written: Vec<_> = lines[..len].iter().map(line).collect();
+                self.read_only
+                    .extend(parser::endpoints(hand_written.iter().map(String::as_str)));
+                self.blocks
+                    .push(Block::HandWritten(with_newline(lines[..len].concat())));
+                lines = &lines[len..];
+            }
+        }
+    }
+
+    fn managed(&self) -> impl Iterator<Item = &Endpoint> {
+        self.blocks.iter().filter_map(|b| match b {
+            Block::Managed(ep) => Some(ep),
+            _ => None,
+        })
+    }
+
+    fn find(&self, key: &RecordKey) -> Option<usize> {
+        self.blocks
+            .iter()
+            .position(|b| matches!(b, Block::Managed(ep) if is(ep, key)))
+    }
+
+    /// Put the endpoint in place of the managed block of `from`, or of its own key,
+    /// or else at the end.
+    fn put(&mut self, from: &RecordKey, ep: Endpoint) -> Result<()> {
+        let key = RecordKey::of(&ep)?;
+        if let Some(at) = self.find(from).or_else(|| self.find(&key)) {
+            self.blocks[at] = Block::Managed(ep);
+            self.remove(&[from, &key], Some(at));
+        } else {
+            if !matches!(self.blocks.last(), None | Some(Block::Blank(_))) {
+                self.blocks.push(Block::Blank("\n".to_string()));
+            }
+            self.blocks.push(Block::Managed(ep));
+        }
+        Ok(())
+    }
+
+    /// Drop the managed blocks of the keys but the one `at`, with the blank lines
+    /// following them, or preceding them at the end.
+    fn remove(&mut self, keys: &[&RecordKey], at: Option<usize>) {
+        let mut dropped = false;
+        let mut blocks = vec![];
+        for (i, block) in std::mem::take(&mut self.blocks).into_iter().enumerate() {
+            match block {
+                Block::Managed(ep) if Some(i) != at && keys.iter().any(|k| is(&ep, k)) => {
+                    dropped = true;
+                }
+                Block::Blank(_) if dropped => dropped = false,
+                block => {
+                    dropped = false;
+                    blocks.push(block);
+                }
+            }
+        }
+        if dropped && matches!(blocks.last(), Some(Block::Blank(_))) {
+            blocks.pop();
+        }
+        self.blocks = blocks;
+    }
+}
+
+// The endpoint of the JSON comment starting a managed block.
+fn managed(line: &str) -> Option<Endpoint> {
+    line.trim_end()
+        .strip_prefix("# ")
+        .and_then(|json| serde_json::from_str(json).ok())
+}
+
+fn is(ep: &Endpoint, key: &RecordKey) -> bool {
+    RecordKey::of(ep).is_ok_and(|k| k == *key)
+}
+
+fn with_newline(mut text: String) -> String {
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+#[async_trait]
+impl Provider for DnsmasqProvider {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        Ok(self.domain_filter.clone())
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        let this = self.clone();
+        let conf = unblock(move || this.read()).await?;
+        Ok(conf.managed().chain(&conf.read_only).cloned().collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        info!(target: "dnsmasq", message = format!("Applying {}", changes.summary()));
+        // Under one lock, so that hand edits made meanwhile are kept.
+        let this = self.clone();
+        unblock(move || {
+            this.store.update(|content| {
+                let conf = Conf::parse(content, |ep| this.directives(ep));
+                this.apply(conf, changes)
+            })
+        })
+        .await
+    }
+
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        Ok(endpoints
+            .into_iter()
+            .filter(|ep| {
+                ep.record_type
+                    .as_ref()
+                    .is_some_and(|t| SUPPORTED_TYPES.contains(t))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::FromTo;
+    use crate::endpoint::ep;
+
+    #[test]
+    fn directives() {
+        let provider = DnsmasqProvider::new(
+            DomainFilter::Strings {
+                include: None,
+                exclude: None,
+            },
+            "unused",
+        )
+        .with_default_ttl(300);
+        let cases = [
+            (
+                ep("a.magicloud.lan", RecordType::A, &["192.168.0.1"]),
+                "address=/a.magicloud.lan/192.168.0.1\n",
+            ),
+            (
+                ep("a.magicloud.lan", RecordType::AAAA, &["fd00::1"]),
+                "address=/a.magicloud.lan/fd00::1\n",
+            ),
+            (
+                ep("c.magicloud.lan", RecordType::CNAME, &["a.magicloud.lan"]),
+                "cname=c.magicloud.lan,a.magicloud.lan,300\n",
+            ),
+            (
+                ep("magicloud.lan", RecordType::MX, &["10 mail.magicloud.lan"]),
+                "mx-host=magicloud.lan,mail.magicloud.lan,10\n",
+            ),
+            (
+                ep(
+                    "_imap._tcp.magicloud.lan",
+                    RecordType::SRV,
+                    &["0 5 143 mail.magicloud.lan"],
+                ),
+                "srv-host=_imap._tcp.magicloud.lan,mail.magicloud.lan,143,0,5\n",
+            ),
+            (
+                ep("magicloud.lan", RecordType::TXT, &["\"v=spf1 mx -all\""]),
+                "txt-record=magicloud.lan,\"v=spf1 mx -all\"\n",
+            ),
+            (
+                ep(
+                    "1.0.168.192.in-addr.arpa",
+                    RecordType::PTR,
+                    &["a.magicloud.lan", "b.magicloud.lan"],
+                ),
+                "ptr-record=1.0.168.192.in-addr.arpa,a.magicloud.lan\n\
+                 ptr-record=1.0.168.192.in-addr.arpa,b.magicloud.lan\n",
+            ),
+        ];
+        for (endpoint, expected) in cases {
+            assert_eq!(provider.directives(&endpoint).unwrap(), expected);
+        }
+
+        let provider = provider.with_host_records(true);
+        let mut both = ep("a.magicloud.lan", RecordType::AAAA, &["fd00::1", "fd00::2"]);
+        both.record_ttl = Some(60);
+        assert_eq!(
+            provider.directives(&both).unwrap(),
+            "host-record=a.magicloud.lan,fd00::1,60\nhost-record=a.magicloud.lan,fd00::2,60\n"
+        );
+        assert!(
+            provider
+                .directives(&ep("magicloud.lan", RecordType::NS, &["ns.magicloud.lan"]))
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn it_works() {
+        let path = std::env::temp_dir().join(format!("dnsmasq-{}.conf", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let provider = DnsmasqProvider::new(
+            DomainFilter::Strings {
+                include: Some(vec!["magicloud.lan".to_string()]),
+                exclude: None,
+            },
+            &path,
+        );
+        assert!(provider.records().await.unwrap().is_empty());
+
+        let nextcloud = ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]);
+        let mail = ep("magicloud.lan", RecordType::MX, &["10 mail.magicloud.lan"]);
+        provider
+            .apply_changes(Changes {
+                create: vec![nextcloud.clone(), mail.clone()],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            provider.records().await.unwrap(),
+            vec![nextcloud.clone(), mail.clone()]
+        );
+        let mut again = nextcloud.clone();
+        again.targets = Some(vec!["192.168.0.103".to_string()]);
+        for changes in [
+            Changes {
+                create: vec![again.clone()],
+                ..Changes::default()
+            },
+            Changes {
+                update: vec![FromTo {
+                    from: mail.clone(),
+                    to: again.clone(),
+                }],
+                ..Changes::default()
+            },
+        ] {
+            assert!(provider.apply_changes(changes).await.is_err());
+        }
+        provider
+            .apply_changes(Changes {
+                delete: vec![mail],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(content.contains("address=/nextcloud.magicloud.lan/192.168.0.102"));
+        assert!(!content.contains("mx-host"));
+    }
+
+    #[tokio::test]
+    async fn keeps_hand_written() {
+        let path = std::env::temp_dir().join(format!("dnsmasq-hand-{}.conf", std::process::id()));
+        let hand_written =
+            "# Home lab\naddress=/nas.magicloud.lan/192.168.0.10\nserver=192.168.0.1";
+        std::fs::write(&path, format!("{hand_written}\n")).unwrap();
+        let provider = DnsmasqProvider::new(
+            DomainFilter::Strings {
+                include: None,
+                exclude: None,
+            },
+            &path,
+        );
+        let nas = ep("nas.magicloud.lan", RecordType::A, &["192.168.0.10"]);
+        assert_eq!(provider.records().await.unwrap(), vec![nas.clone()]);
+
+        assert!(
+            provider
+                .apply_changes(Changes {
+                    delete: vec![nas.clone()],
+                    ..Changes::default()
+                })
+                .await
+                .is_err()
+        );
+        let wiki = ep("wiki.magicloud.lan", RecordType::A, &["192.168.0.11"]);
+        provider
+            .apply_changes(Changes {
+                create: vec![wiki.clone()],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(content.starts_with(&format!("{hand_written}\n\n# ")));
+        let conf = Conf::parse(&content, |ep| provider.directives(ep));
+        assert_eq!(conf.managed().collect::<Vec<_>>(), [&wiki]);
+    }
+
+    #[tokio::test]
+    async fn keeps_order() {
+        let path = std::env::temp_dir().join(format!("dnsmasq-order-{}.conf", std::process::id()));
+        let provider = DnsmasqProvider::new(
+            DomainFilter::Strings {
+                include: None,
+                exclude: None,
+            },
+            &path,
+        );
+        let wiki = ep("wiki.magicloud.lan", RecordType::A, &["192.168.0.11"]);
+        let git = ep("git.magicloud.lan", RecordType::A, &["192.168.0.12"]);
+        let moved = ep("wiki.magicloud.lan", RecordType::A, &["192.168.0.13"]);
+        let new = ep("new.magicloud.lan", RecordType::A, &["192.168.0.14"]);
+        let content = |blocks: &[&str]| blocks.concat();
+        let (wiki_block, git_block) = (
+            provider.block(&wiki).unwrap(),
+            provider.block(&git).unwrap(),
+        );
+        std::fs::write(
+            &path,
+            content(&[
+                "# Upstream\nserver=192.168.0.1\n\n",
+                &wiki_block,
+                "\n\n\n# NAS\naddress=/nas.magicloud.lan/192.168.0.10\n\n",
+                &git_block,
+                "\n# Last\nlocal=/magicloud.lan/",
+            ]),
+        )
+        .unwrap();
+
+        provider
+            .apply_changes(Changes {
+                create: vec![new.clone()],
+                update: vec![FromTo {
+                    from: wiki,
+                    to: moved.clone(),
+                }],
+                delete: vec![git],
+            })
+            .await
+            .unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written,
+            content(&[
+                "# Upstream\nserver=192.168.0.1\n\n",
+                &provider.block(&moved).unwrap(),
+                "\n\n\n# NAS\naddress=/nas.magicloud.lan/192.168.0.10\n\n",
+                "# Last\nlocal=/magicloud.lan/\n\n",
+                &provider.block(&new).unwrap(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_adjacent_hand_written() {
+        let path = std::env::temp_dir().join(format!("dnsmasq-adj-{}.conf", std::process::id()));
+        let provider = DnsmasqProvider::new(
+            DomainFilter::Strings {
+                include: None,
+                exclude: None,
+            },
+            &path,
+        );
+        let wiki = ep("wiki.magicloud.lan", RecordType::A, &["192.168.0.11"]);
+        let git = ep("git.magicloud.lan", RecordType::A, &["192.168.0.12"]);
+        let nas = ep("nas.magicloud.lan", RecordType::A, &["192.168.0.10"]);
+        let hand_written = "address=/nas.magicloud.lan/192.168.0.10\n";
+        let (wiki_block, git_block) = (
+            provider.block(&wiki).unwrap(),
+            provider.block(&git).unwrap(),
+        );
+        // No blank lines between the blocks.
+        std::fs::write(
+            &path,
+            [wiki_block.as_str(), hand_written, &git_block].concat(),
+        )
+        .unwrap();
+        assert_eq!(
+            provider.records().await.unwrap(),
+            vec![wiki.clone(), git, nas.clone()]
+        );
+        assert!(
+            provider
+                .apply_changes(Changes {
+                    delete: vec![nas],
+                    ..Changes::default()
+                })
+                .await
+                .is_err()
+        );
+
+        provider
+            .apply_changes(Changes {
+                delete: vec![wiki],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, [hand_written, &git_block].concat());
+    }
+}
@@ -0,0 +1,227 @@
+//! Parser of the dnsmasq directives serving records, for hand-written configuration.
+
+use std::{collections::BTreeMap, net::IpAddr};
+
+use crate::endpoint::{Endpoint, RecordType};
+
+/// One record served by a directive line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Served {
+    pub dns_name: String,
+    pub record_type: RecordType,
+    pub target: String,
+    pub ttl: Option<i64>,
+}
+
+/// The records served by one line of dnsmasq configuration.
+/// Other directives, comments, and lines dnsmasq would refuse give nothing.
+#[must_use]
+pub fn parse_line(line: &str) -> Vec<Served> {
+    let Some((directive, value)) = line.trim().split_once('=') else {
+        return vec![];
+    };
+    let served =
+        |dns_name: &str, record_type: RecordType, target: String, ttl: Option<i64>| Served {
+            dns_name: dns_name.trim_end_matches('.').to_string(),
+            record_type,
+            target,
+            ttl,
+        };
+    let args = split_args(value);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match (directive.trim(), &args[..]) {
+        // address=/name[/name...]/ip
+        ("address", [value]) => {
+            let parts: Vec<&str> = value.split('/').collect();
+            match &parts[..] {
+                ["", names @ .., ip] if !names.is_empty() => ip
+                    .parse::<IpAddr>()
+                    .map(|ip| {
+                        names
+                            .iter()
+                            .filter(|n| !n.is_empty())
+                            .map(|n| served(n, ip_type(&ip), ip.to_string(), None))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                _ => vec![],
+            }
+        }
+        // host-record=name[,name...],ip[,ip...][,ttl]
+        ("host-record", args) => {
+            let (args, ttl) = match args {
+                [rest @ .., ttl] if ttl.parse::<i64>().is_ok() => (rest, ttl.parse().ok()),
+                _ => (args, None),
+            };
+            let (ips, names): (Vec<&str>, Vec<&str>) =
+                args.iter().partition(|a| a.parse::<IpAddr>().is_ok());
+            names
+                .iter()
+                .flat_map(|n| {
+                    ips.iter().filter_map(move |ip| {
+                        let ip: IpAddr = ip.parse().ok()?;
+                        Some(served(n, ip_type(&ip), ip.to_string(), ttl))
+                    })
+                })
+                .collect()
+        }
+        // cname=name[,name...],target[,ttl]
+        ("cname", args) => {
+            let (args, ttl) = match args {
+                [rest @ .., ttl] if rest.len() >= 2 && ttl.parse::<i64>().is_ok() => {
+                    (rest, ttl.parse().ok())
+                }
+                _ => (args, None),
+            };
+            match args {
+                [names @ .., target] if !names.is_empty() => names
+                    .iter()
+                    .map(|n| served(n, RecordType::CNAME, (*target).to_string(), ttl))
+                    .collect(),
+                _ => vec![],
+            }
+        }
+        // txt-record=name[,text...]
+        ("txt-record", [name, texts @ ..]) if !texts.is_empty() => texts
+            .iter()
+            .map(|t| served(name, RecordType::TXT, (*t).to_string(), None))
+            .collect(),
+        // ptr-record=name[,target]
+        ("ptr-record", [name, target]) => {
+            vec![served(
+                name,
+                RecordType::PTR,
+                target.trim_matches('"').to_string(),
+                None,
+            )]
+        }
+        // mx-host=name[[,host],preference], dnsmasq defaults the preference to 1.
+        ("mx-host", [name, host, rest @ ..]) if rest.len() <= 1 => {
+            let preference = rest.first().copied().unwrap_or("1");
+            vec![served(
+                name,
+                RecordType::MX,
+                format!("{preference} {host}"),
+                None,
+            )]
+        }
+        // srv-host=name[,target[,port[,priority[,weight]]]]
+        ("srv-host", [name, target, rest @ ..]) if rest.len() <= 3 => {
+            let arg = |i: usize| rest.get(i).copied().unwrap_or("0");
+            vec![served(
+                name,
+                RecordType::SRV,
+                format!("{} {} {} {target}", arg(1), arg(2), arg(0)),
+                None,
+            )]
+        }
+        _ => vec![],
+    }
+}
+
+/// Endpoints of the records served by `lines`, one per name and type, in order of appearance.
+#[must_use]
+pub fn endpoints<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<Endpoint> {
+    let mut order = Vec::new();
+    let mut found: BTreeMap<(String, RecordType), Endpoint> = BTreeMap::new();
+    for served in lines.into_iter().flat_map(parse_line) {
+        let key = (served.dns_name.clone(), served.record_type.clone());
+        let ep = found.entry(key.clone()).or_insert_with(|| {
+            order.push(key);
+            Endpoint {
+                dns_name: Some(served.dns_name),
+                targets: Some(vec![]),
+                record_type: Some(served.record_type),
+                set_identifier: None,
+                record_ttl: None,
+                labels: None,
+                provider_specific: None,
+            }
+        });
+        let targets = ep.targets.get_or_insert_default();
+        if !targets.contains(&served.target) {
+            targets.push(served.target);
+        }
+        ep.record_ttl = ep.record_ttl.or(served.ttl);
+    }
+    order
+        .into_iter()
+        .filter_map(|key| found.remove(&key))
+        .collect()
+}
+
+const fn ip_type(ip: &IpAddr) -> RecordType {
+    match ip {
+        IpAddr::V4(_) => RecordType::A,
+        IpAddr::V6(_) => RecordType::AAAA,
+    }
+}
+
+// Comma separated arguments, commas within double quotes not separating.
+fn split_args(value: &str) -> Vec<String> {
+    let mut ret = vec![];
+    let mut arg = String::new();
+    let mut quoted = false;
+    for c in value.trim().chars() {
+        if c == ',' && !quoted {
+            ret.push(arg.trim().to_string());
+            arg.clear();
+        } else {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            arg.push(c);
+        }
+    }
+    ret.push(arg.trim().to_string());
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let conf = r#"# Hand written
+address=/nas.magicloud.lan/printer.magicloud.lan/192.168.0.10
+address=/nas.magicloud.lan/fd00::10
+address=/ads.example.com/
+host-record=router.magicloud.lan,192.168.0.1,fd00::1,600
+cname=www.magicloud.lan,web.magicloud.lan,nas.magicloud.lan
+txt-record=magicloud.lan,"v=spf1 mx -all","a, b"
+ptr-record=10.0.168.192.in-addr.arpa,"nas.magicloud.lan"
+mx-host=magicloud.lan,mail.magicloud.lan,10
+srv-host=_imap._tcp.magicloud.lan,mail.magicloud.lan,143,0,5
+server=192.168.0.1
+"#;
+        let found: Vec<_> = endpoints(conf.lines())
+            .into_iter()
+            .map(|ep| {
+                format!(
+                    "{} {:?} {} {:?}",
+                    ep.dns_name.unwrap(),
+                    ep.record_type.unwrap(),
+                    ep.targets.unwrap().join("|"),
+                    ep.record_ttl
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "nas.magicloud.lan A 192.168.0.10 None",
+                "printer.magicloud.lan A 192.168.0.10 None",
+                "nas.magicloud.lan AAAA fd00::10 None",
+                "router.magicloud.lan A 192.168.0.1 Some(600)",
+                "router.magicloud.lan AAAA fd00::1 Some(600)",
+                "www.magicloud.lan CNAME nas.magicloud.lan None",
+                "web.magicloud.lan CNAME nas.magicloud.lan None",
+                "magicloud.lan TXT \"v=spf1 mx -all\"|\"a, b\" None",
+                "10.0.168.192.in-addr.arpa PTR nas.magicloud.lan None",
+                "magicloud.lan MX 10 mail.magicloud.lan None",
+                "_imap._tcp.magicloud.lan SRV 0 5 143 mail.magicloud.lan None",
+            ]
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! A `Provider` writing A and AAAA records into a hosts file, as read by the `CoreDNS` `hosts`
+//! plugin, dnsmasq `addn-hosts` and many appliances.
+//!
+//! The records live between `# BEGIN external-dns` and `# END external-dns` marker lines,
+//! one line per address with all the names resolving to it:
+//!
+//! ```text
+//! # BEGIN external-dns
+//! 192.168.0.102 cloud.magicloud.lan nextcloud.magicloud.lan
+//! fd00::102 nextcloud.magicloud.lan
+//! # END external-dns
+//! ```
+//!
+//! Lines outside the markers are kept as they are, and are not listed by `records`. So are
+//! lines between the markers that are not an address and names, such as hand-written
+//! comments, which move to the top of the block.
+//! Hosts files have no TTL, so records are listed without one. Only A and AAAA records
+//! can be written: `adjust_endpoints` drops the others.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    path::PathBuf,
+};
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    changes::Changes,
+    domain_filter::DomainFilter,
+    endpoint::{Endpoint, RecordKey, RecordType},
+    file_store::{FileStore, unblock},
+    provider::Provider,
+};
+
+const BEGIN: &str = "# BEGIN external-dns";
+const END: &str = "# END external-dns";
+
+/// A `Provider` keeping A and AAAA records in a hosts file.
+#[derive(Debug, Clone)]
+pub struct HostsProvider {
+    domain_filter: DomainFilter,
+    store: FileStore,
+}
+impl HostsProvider {
+    /// Constructor of `HostsProvider`.
+    #[must_use]
+    pub fn new(domain_filter: DomainFilter, hosts_filename: impl Into<PathBuf>) -> Self {
+        Self {
+            domain_filter,
+            store: FileStore::new(hosts_filename),
+        }
+    }
+
+    /// Keep that many former versions of the hosts file, see [`FileStore`].
+    #[must_use]
+    pub fn with_backups(mut self, backups: usize) -> Self {
+        self.store = self.store.with_backups(backups);
+        self
+    }
+}
+
+/// The content of the hosts file.
+#[derive(Debug, Default)]
+struct Hosts {
+    /// The lines before the managed block, all of them when there is none yet.
+    before: Vec<String>,
+    found: bool,
+    /// The lines of the managed block that are not records.
+    kept: Vec<String>,
+    managed: Vec<Endpoint>,
+    after: Vec<String>,
+}
+impl Hosts {
+    fn parse(content: &str) -> Result<Self> {
+        let mut ret = Self::default();
+        let mut lines = content.lines();
+        for line in lines.by_ref() {
+            if line.trim() == BEGIN {
+                ret.found = true;
+                break;
+            }
+            ret.before.push(line.to_string());
+        }
+        let mut names: BTreeMap<(String, RecordType), Vec<String>> = BTreeMap::new();
+        let mut ended = !ret.found;
+        for line in lines.by_ref() {
+            if line.trim() == END {
+                ended = true;
+                break;
+            }
+            let fields = line.split('#').next().unwrap_or_default();
+            let mut fields = fields.split_whitespace();
+            let Some(address) = fields.next() else {
+                if !line.trim().is_empty() {
+                    ret.kept.push(line.to_string());
+                }
+                continue;
+            };
+            let Ok(ip) = address.parse::<IpAddr>() else {
+                warn!(target: "hosts", message = format!("Keeping invalid hosts line: {line}"));
+                ret.kept.push(line.to_string());
+                continue;
+            };
+            let record_type = if ip.is_ipv4() {
+                RecordType::A
+            } else {
+                RecordType::AAAA
+            };
+            for name in fields {
+                let targets = names
+                    .entry((name.trim_end_matches('.').to_string(), record_type.clone()))
+                    .or_default();
+                if !targets.contains(&ip.to_string()) {
+                    targets.push(ip.to_string());
+                }
+            }
+        }
+        if !ended {
+            return Err(eyre!("No `{END}` after `{BEGIN}`"));
+        }
+        ret.after = lines.map(ToString::to_string).collect();
+        ret.managed = names
+            .into_iter()
+            .map(|((name, record_type), targets)| Endpoint {
+                dns_name: Some(name),
+                targets: Some(targets),
+                record_type: Some(record_type),
+                set_identifier: None,
+                record_ttl: None,
+                labels: None,
+                provider_specific: None,
+            })
+            .collect();
+        Ok(ret)
+    }
+
+    fn render(&self) -> Result<String> {
+        let mut addresses: BTreeMap<IpAddr, BTreeSet<&str>> = BTreeMap::new();
+        for ep in &self.managed {
+            let name = ep
+                .dns_name
+                .as_deref()
+                .ok_or_else(|| eyre!("No dnsName in {ep:?}"))?;
+            for target in ep.targets.iter().flatten() {
+                let ip = target
+                    .parse::<IpAddr>()
+                    .map_err(|e| eyre!("Invalid address {target} for {name}: {e}"))?;
+                addresses.entry(ip).or_default().insert(name);
+            }
+        }
+        let mut lines = self.before.clone();
+        if !self.found && lines.last().is_some_and(|l| !l.trim().is_empty()) {
+            lines.push(String::new());
+        }
+        lines.push(BEGIN.to_string());
+        lines.extend(self.kept.iter().cloned());
+        for (ip, names) in addresses {
+            lines.push(format!(
+                "{ip} {}",
+                names.into_iter().collect::<Vec<_>>().join(" ")
+            ));
+        }
+        lines.push(END.to_string());
+        lines.extend(self.after.iter().cloned());
+        Ok(lines.join("\n") + "\n")
+    }
+
+    fn apply(mut self, changes: Changes) -> Result<String> {
+        let touched = changes
+            .create
+            .iter()
+            .chain(changes.update.iter().map(|ft| &ft.to));
+        for ep in touched {
+            if !supported(ep) {
+                return Err(eyre!("Only A and AAAA records can be written: {ep:?}"));
+            }
+        }
+        let mut endpoints = std::mem::take(&mut self.managed)
+            .into_iter()
+            .map(|ep| RecordKey::of(&ep).map(|key| (key, ep)))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        for ep in changes.delete {
+            endpoints.remove(&RecordKey::of(&ep)?);
+        }
+        for ft in changes.update {
+            endpoints.remove(&RecordKey::of(&ft.from)?);
+            endpoints.insert(RecordKey::of(&ft.to)?, ft.to);
+        }
+        for ep in changes.create {
+            endpoints.insert(RecordKey::of(&ep)?, ep);
+        }
+        self.managed = endpoints.into_values().collect();
+        self.render()
+    }
+}
+
+const fn supported(endpoint: &Endpoint) -> bool {
+    matches!(endpoint.record_type, Some(RecordType::A | RecordType::AAAA))
+}
+
+#[async_trait]
+impl Provider for HostsProvider {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        Ok(self.domain_filter.clone())
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        let store = self.store.clone();
+        Ok(Hosts::parse(&unblock(move || store.read()).await?)?.managed)
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        info!(target: "hosts", message = format!("Applying {}", changes.summary()));
+        let store = self.store.clone();
+        unblock(move || store.update(|content| Hosts::parse(content)?.apply(changes))).await
+    }
+
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        Ok(endpoints
+            .into_iter()
+            .filter(|ep| {
+                let ok = supported(ep);
+                if !ok {
+                    warn!(target: "hosts", message = format!("Unsupported record type: {ep:?}"));
+                }
+                ok
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::ep;
+
+    #[tokio::test]
+    async fn it_works() {
+        let path = std::env::temp_dir().join(format!("hosts-{}", std::process::id()));
+        std::fs::write(&path, "127.0.0.1 localhost\n::1 localhost\n").unwrap();
+        let provider = HostsProvider::new(
+            DomainFilter::Strings {
+                include: Some(vec!["magicloud.lan".to_string()]),
+                exclude: None,
+            },
+            &path,
+        );
+        let cloud = ep("cloud.magicloud.lan", RecordType::A, &["192.168.0.102"]);
+        let nextcloud = ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]);
+        let nextcloud6 = ep("nextcloud.magicloud.lan", RecordType::AAAA, &["fd00::102"]);
+        let nas = ep(
+            "nas.magicloud.lan",
+            RecordType::A,
+            &["192.168.0.10", "192.168.0.11"],
+        );
+        provider
+            .apply_changes(Changes {
+                create: vec![
+                    nextcloud.clone(),
+                    cloud.clone(),
+                    nextcloud6.clone(),
+                    nas.clone(),
+                ],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "127.0.0.1 localhost
+::1 localhost
+
+# BEGIN external-dns
+192.168.0.10 nas.magicloud.lan
+192.168.0.11 nas.magicloud.lan
+192.168.0.102 cloud.magicloud.lan nextcloud.magicloud.lan
+fd00::102 nextcloud.magicloud.lan
+# END external-dns
+"
+        );
+        assert_eq!(
+            provider.records().await.unwrap(),
+            vec![cloud.clone(), nas, nextcloud, nextcloud6]
+        );
+
+        // Lines after the block stay after it, invalid lines in the block stay in it.
+        let mut content = std::fs::read_to_string(&path)
+            .unwrap()
+            .replace(BEGIN, &format!("{BEGIN}\nnot-an-address oops"));
+        content.push_str("10.0.0.1 vpn\n");
+        std::fs::write(&path, content).unwrap();
+        provider
+            .apply_changes(Changes {
+                delete: vec![cloud],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("192.168.0.102 nextcloud.magicloud.lan\n")
+        );
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("# BEGIN external-dns\nnot-an-address oops\n")
+        );
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .ends_with("# END external-dns\n10.0.0.1 vpn\n")
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let adjusted = provider
+            .adjust_endpoints(vec![
+                ep(
+                    "www.magicloud.lan",
+                    RecordType::CNAME,
+                    &["nextcloud.magicloud.lan"],
+                ),
+                ep("www.magicloud.lan", RecordType::A, &["192.168.0.102"]),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(adjusted.len(), 1);
+        assert_eq!(adjusted[0].record_type, Some(RecordType::A));
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use tracing::instrument;
+
+use crate::{
+    changes::Changes,
+    domain_filter::DomainFilter,
+    endpoint::{Endpoint, RecordKey, RecordType},
+    provider::Provider,
+};
+
+/// A `Provider` keeping records in memory.
+///
+/// Mostly for tests, it follows the rules of the in-memory provider of External-DNS:
+/// creating an existing record, updating or deleting a missing one, updating one onto
+/// another existing record, or touching the same record twice in one `Changes`
+/// fails the whole batch, leaving the store untouched.
+#[derive(Debug)]
+pub struct InMemoryProvider {
+    domain_filter: DomainFilter,
+    records: RwLock<BTreeMap<RecordKey, Endpoint>>,
+}
+impl Default for InMemoryProvider {
+    fn default() -> Self {
+        Self::new(DomainFilter::Strings {
+            include: None,
+            exclude: None,
+        })
+    }
+}
+impl InMemoryProvider {
+    /// Constructor of `InMemoryProvider`, with no records.
+    #[must_use]
+    pub const fn new(domain_filter: DomainFilter) -> Self {
+        Self {
+            domain_filter,
+            records: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Constructor of `InMemoryProvider`, seeded with a JSON array of endpoints,
+    /// as returned by `GET /records`.
+    /// # Errors
+    ///
+    /// When the JSON is not a list of valid endpoints.
+    pub fn from_json(domain_filter: DomainFilter, json: &str) -> Result<Self> {
+        let ret = Self::new(domain_filter);
+        ret.seed_json(json)?;
+        Ok(ret)
+    }
+
+    /// Insert or replace records, without the checks of `apply_changes`.
+    /// # Errors
+    ///
+    /// When an endpoint has no name or no type.
+    pub fn seed(&self, endpoints: impl IntoIterator<Item = Endpoint>) -> Result<()> {
+        let endpoints = endpoints
+            .into_iter()
+            .map(|ep| RecordKey::of(&ep).map(|key| (key, ep)))
+            .collect::<Result<Vec<_>>>()?;
+        self.write().extend(endpoints);
+        Ok(())
+    }
+
+    /// Same as `seed`, from a JSON array of endpoints.
+    /// # Errors
+    ///
+    /// When the JSON is not a list of valid endpoints.
+    pub fn seed_json(&self, json: &str) -> Result<()> {
+        self.seed(serde_json::from_str::<Vec<Endpoint>>(json)?)
+    }
+
+    /// All records, ordered by name, type and set identifier.
+    #[must_use]
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        self.read().values().cloned().collect()
+    }
+
+    /// Records of the name and type, any set identifier.
+    #[must_use]
+    pub fn get(&self, dns_name: &str, record_type: &RecordType) -> Vec<Endpoint> {
+        self.read()
+            .iter()
+            .filter(|(k, _)| k.dns_name == dns_name && &k.record_type == record_type)
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+
+    /// If any record of the name and type exists.
+    #[must_use]
+    pub fn contains(&self, dns_name: &str, record_type: &RecordType) -> bool {
+        self.read()
+            .keys()
+            .any(|k| k.dns_name == dns_name && &k.record_type == record_type)
+    }
+
+    /// Count of records.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// If there is no record.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    /// Remove all records.
+    pub fn clear(&self) {
+        self.write().clear();
+    }
+
+    // A panic while holding the lock cannot leave the map half updated,
+    // since `apply_changes` validates everything before touching it.
+    fn read(&self) -> RwLockReadGuard<'_, BTreeMap<RecordKey, Endpoint>> {
+        self.records.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, BTreeMap<RecordKey, Endpoint>> {
+        self.records.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl Provider for InMemoryProvider {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        Ok(self.domain_filter.clone())
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        Ok(self.endpoints())
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        let mut records = self.write();
+
+        let mut touched = HashSet::new();
+        let mut touch = |key: &RecordKey| {
+            if touched.insert(key.clone()) {
+                Ok(())
+            } else {
+                Err(eyre!("Record {key:?} is changed more than once"))
+            }
+        };
+        let mut create = Vec::with_capacity(changes.create.len());
+        for ep in changes.create {
+            let key = RecordKey::of(&ep)?;
+            touch(&key)?;
+            if records.contains_key(&key) {
+                return Err(eyre!("Cannot create existing record {key:?}"));
+            }
+            create.push((key, ep));
+        }
+        let mut update = Vec::with_capacity(changes.update.len());
+        for ft in changes.update {
+            let from = RecordKey::of(&ft.from)?;
+            let to = RecordKey::of(&ft.to)?;
+            touch(&to)?;
+            if !records.contains_key(&from) {
+                return Err(eyre!("Cannot update missing record {from:?}"));
+            }
+            if from != to && records.contains_key(&to) {
+                return Err(eyre!("Cannot update {from:?} onto existing record {to:?}"));
+            }
+            update.push((from, to, ft.to));
+        }
+        let mut delete = Vec::with_capacity(changes.delete.len());
+        for ep in changes.delete {
+            let key = RecordKey::of(&ep)?;
+            touch(&key)?;
+            if !records.contains_key(&key) {
+                return Err(eyre!("Cannot delete missing record {key:?}"));
+            }
+            delete.push(key);
+        }
+
+        for key in delete {
+            records.remove(&key);
+        }
+        for (from, to, ep) in update {
+            records.remove(&from);
+            records.insert(to, ep);
+        }
+        records.extend(create);
+        drop(records);
+        Ok(())
+    }
+
+    /// Trim the trailing dot of names, and sort and dedup the targets,
+    /// since External-DNS compares targets as a set.
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        Ok(endpoints
+            .into_iter()
+            .map(|mut ep| {
+                if let Some(name) = ep.dns_name.as_mut()
+                    && name.ends_with('.')
+                {
+                    name.pop();
+                }
+                if let Some(targets) = ep.targets.as_mut() {
+                    targets.sort();
+                    targets.dedup();
+                }
+                ep
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::FromTo;
+    use crate::endpoint::ep;
+
+    const SEED: &str = r#"[
+    {
+        "dnsName": "nextcloud.magicloud.lan",
+        "targets": ["192.168.0.102"],
+        "recordType": "A",
+        "labels": {"owner": "default", "resource": "ingress/nextcloud/nextcloud"}
+    },
+    {
+        "dnsName": "a-nextcloud.magicloud.lan",
+        "targets": ["\"heritage=external-dns,external-dns/owner=default,external-dns/resource=ingress/nextcloud/nextcloud\""],
+        "recordType": "TXT",
+        "labels": {"ownedRecord": "nextcloud.magicloud.lan"}
+    }
+]"#;
+
+    #[tokio::test]
+    async fn it_works() {
+        let provider = InMemoryProvider::from_json(
+            DomainFilter::Strings {
+                include: Some(vec!["magicloud.lan".to_string()]),
+                exclude: None,
+            },
+            SEED,
+        )
+        .unwrap();
+        assert_eq!(provider.len(), 2);
+        assert!(provider.contains("a-nextcloud.magicloud.lan", &RecordType::TXT));
+
+        provider
+            .apply_changes(Changes {
+                create: vec![ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"])],
+                update: vec![FromTo {
+                    from: ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]),
+                    to: ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.104"]),
+                }],
+                delete: vec![],
+            })
+            .await
+            .unwrap();
+        assert_eq!(provider.len(), 3);
+        assert_eq!(
+            provider.get("nextcloud.magicloud.lan", &RecordType::A),
+            vec![ep(
+                "nextcloud.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.104"]
+            )]
+        );
+
+        provider
+            .apply_changes(Changes {
+                delete: vec![ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"])],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        assert!(!provider.contains("gitea.magicloud.lan", &RecordType::A));
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_batches() {
+        let provider = InMemoryProvider::from_json(
+            DomainFilter::Strings {
+                include: None,
+                exclude: None,
+            },
+            SEED,
+        )
+        .unwrap();
+
+        // Existing record.
+        let r = provider
+            .apply_changes(Changes {
+                create: vec![
+                    ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"]),
+                    ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]),
+                ],
+                ..Changes::default()
+            })
+            .await;
+        assert!(r.is_err());
+        // Nothing of the failed batch is applied.
+        assert!(!provider.contains("gitea.magicloud.lan", &RecordType::A));
+
+        // Missing record.
+        let r = provider
+            .apply_changes(Changes {
+                delete: vec![ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"])],
+                ..Changes::default()
+            })
+            .await;
+        assert!(r.is_err());
+
+        // Update onto another existing record.
+        let r = provider
+            .apply_changes(Changes {
+                update: vec![FromTo {
+                    from: ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]),
+                    to: serde_json::from_value(serde_json::json!({
+                        "dnsName": "a-nextcloud.magicloud.lan",
+                        "targets": ["192.168.0.102"],
+                        "recordType": "TXT",
+                    }))
+                    .unwrap(),
+                }],
+                ..Changes::default()
+            })
+            .await;
+        assert!(r.is_err());
+        assert!(provider.contains("nextcloud.magicloud.lan", &RecordType::A));
+
+        // Same record twice.
+        let r = provider
+            .apply_changes(Changes {
+                create: vec![ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"])],
+                delete: vec![ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"])],
+                ..Changes::default()
+            })
+            .await;
+        assert!(r.is_err());
+        assert_eq!(provider.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn adjusts_endpoints() {
+        let provider = InMemoryProvider::default();
+        let mut ep = ep("gitea.magicloud.lan.", RecordType::A, &["192.168.0.103"]);
+        ep.targets = Some(vec![
+            "192.168.0.104".to_string(),
+            "192.168.0.103".to_string(),
+            "192.168.0.104".to_string(),
+        ]);
+        let adjusted = provider.adjust_endpoints(vec![ep]).await.unwrap();
+        assert_eq!(adjusted[0].dns_name.as_deref(), Some("gitea.magicloud.lan"));
+        assert_eq!(
+            adjusted[0].targets.as_deref(),
+            Some(&["192.168.0.103".to_string(), "192.168.0.104".to_string()][..])
+        );
+    }
+}
@@ -0,0 +1,21 @@
+//! Ready to use `Provider` implementations.
+
+#[cfg(feature = "adguard")]
+pub mod adguard;
+#[cfg(feature = "dnsmasq")]
+pub mod dnsmasq;
+#[cfg(feature = "hosts")]
+pub mod hosts;
+pub mod memory;
+#[cfg(feature = "pihole")]
+pub mod pihole;
+#[cfg(feature = "powerdns")]
+pub mod powerdns;
+#[cfg(feature = "rest")]
+pub mod rest;
+#[cfg(feature = "rfc2136")]
+pub mod rfc2136;
+#[cfg(any(feature = "adguard", feature = "pihole"))]
+mod rules;
+#[cfg(feature = "zonefile")]
+pub mod zonefile;
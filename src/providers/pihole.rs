@@ -0,0 +1,552 @@
+//! A `Provider` managing the local DNS records of Pi-hole (v6), through its REST API.
+//!
+//! A and AAAA records are the `dns.hosts` entries (`"<address> <name>..."`), CNAME records
+//! the `dns.cnameRecords` entries (`"<name>,<target>[,<ttl>]"`). Entries are added and
+//! removed one by one; an entry with several names loses only the removed one.
+//!
+//! When Pi-hole has a password, the provider logs in and keeps the session, logging in again
+//! when it expires.
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    net::IpAddr,
+    sync::{Mutex, PoisonError},
+};
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use reqwest::{Client, Method, Response, StatusCode, Url};
+use serde::{Deserialize, de::DeserializeOwned};
+use tracing::{info, instrument};
+
+use super::rules::{self, Rule, Step};
+use crate::{
+    changes::Changes,
+    domain_filter::DomainFilter,
+    endpoint::{Endpoint, RecordType},
+    provider::Provider,
+};
+
+/// A `Provider` calling the Pi-hole API.
+pub struct PiholeProvider {
+    domain_filter: DomainFilter,
+    base_url: Url,
+    password: Option<String>,
+    http: Client,
+    sid: Mutex<Option<String>>,
+}
+impl Debug for PiholeProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PiholeProvider")
+            .field("domain_filter", &self.domain_filter)
+            .field("base_url", &self.base_url)
+            .finish_non_exhaustive()
+    }
+}
+impl PiholeProvider {
+    /// Constructor of `PiholeProvider`.
+    /// `base_url` is the web interface, such as `http://pi.hole`, and `password` its password if any.
+    /// # Errors
+    ///
+    /// When `base_url` is not a valid URL.
+    pub fn new(
+        domain_filter: DomainFilter,
+        base_url: &str,
+        password: Option<&str>,
+    ) -> Result<Self> {
+        Ok(Self {
+            domain_filter,
+            base_url: Url::parse(base_url)?,
+            password: password.map(ToString::to_string),
+            http: Client::new(),
+            sid: Mutex::default(),
+        })
+    }
+
+    /// Use a configured `reqwest::Client` (timeouts, TLS, ...).
+    #[must_use]
+    pub fn with_client(mut self, http: Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    fn url(&self, path: &[&str]) -> Result<Url> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .map_err(|()| eyre!("Invalid base URL {}", self.base_url))?
+            .pop_if_empty()
+            .extend(std::iter::once(&"api").chain(path));
+        Ok(url)
+    }
+
+    async fn login(&self) -> Result<Option<String>> {
+        let Some(password) = &self.password else {
+            return Ok(None);
+        };
+        let res = self
+            .http
+            .post(self.url(&["auth"])?)
+            .json(&serde_json::json!({ "password": password }))
+            .send()
+            .await?;
+        let auth: Auth = parse(res).await?;
+        if !auth.session.valid {
+            return Err(eyre!("Pi-hole refused the password"));
+        }
+        let sid = auth.session.sid;
+        self.sid
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone_from(&sid);
+        Ok(sid)
+    }
+
+    // Sends a request in the session, logging in again once when it expired.
+    async fn send(&self, method: Method, path: &[&str]) -> Result<Response> {
+        let sid = self
+            .sid
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let mut sid = match sid {
+            Some(sid) => Some(sid),
+            None => self.login().await?,
+        };
+        let mut retried = false;
+        loop {
+            let mut req = self.http.request(method.clone(), self.url(path)?);
+            if let Some(sid) = &sid {
+                req = req.header("X-FTL-SID", sid);
+            }
+            let res = req.send().await?;
+            if res.status() != StatusCode::UNAUTHORIZED || retried || self.password.is_none() {
+                return Ok(res);
+            }
+            retried = true;
+            sid = self.login().await?;
+        }
+    }
+
+    async fn entries(&self, key: &str) -> Result<Vec<String>> {
+        let config: serde_json::Value =
+            parse(self.send(Method::GET, &["config", "dns", key]).await?).await?;
+        serde_json::from_value(config["config"]["dns"][key].clone())
+            .map_err(|e| eyre!("Unexpected Pi-hole config for {key} ({e}): {config}"))
+    }
+
+    async fn put(&self, key: &str, entry: &str) -> Result<()> {
+        check(
+            self.send(Method::PUT, &["config", "dns", key, entry])
+                .await?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str, entry: &str) -> Result<()> {
+        check(
+            self.send(Method::DELETE, &["config", "dns", key, entry])
+                .await?,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// The answer of `POST /api/auth`.
+#[derive(Debug, Deserialize)]
+struct Auth {
+    session: Session,
+}
+#[derive(Debug, Deserialize)]
+struct Session {
+    valid: bool,
+    sid: Option<String>,
+}
+
+const HOSTS: &str = "hosts";
+const CNAMES: &str = "cnameRecords";
+
+/// How Pi-hole explains errors.
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    error: ErrorDetail,
+}
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    message: String,
+    hint: Option<String>,
+}
+
+async fn check(res: Response) -> Result<Vec<u8>> {
+    let status = res.status();
+    let url = res.url().clone();
+    let body = res.bytes().await?.to_vec();
+    if status.is_success() {
+        return Ok(body);
+    }
+    let reason = serde_json::from_slice::<ApiError>(&body).map_or_else(
+        |_| String::from_utf8_lossy(&body).into_owned(),
+        |e| match e.error.hint {
+            Some(hint) => format!("{} ({hint})", e.error.message),
+            None => e.error.message,
+        },
+    );
+    Err(eyre!("{url}: {status}: {reason}"))
+}
+
+async fn parse<T: DeserializeOwned>(res: Response) -> Result<T> {
+    let body = check(res).await?;
+    serde_json::from_slice(&body).map_err(|e| {
+        eyre!(
+            "Cannot parse Pi-hole response ({e}): {}",
+            String::from_utf8_lossy(&body)
+        )
+    })
+}
+
+fn host(entry: &str) -> Option<(IpAddr, Vec<&str>)> {
+    let mut fields = entry.split_whitespace();
+    let ip = fields.next()?.parse().ok()?;
+    Some((ip, fields.collect()))
+}
+
+fn cname(entry: &str) -> Option<(&str, &str)> {
+    let mut fields = entry.split(',').map(str::trim);
+    Some((fields.next()?, fields.next()?))
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.')
+        .eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+#[async_trait]
+impl Provider for PiholeProvider {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        Ok(self.domain_filter.clone())
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        let hosts = self.entries(HOSTS).await?;
+        let cnames = self.entries(CNAMES).await?;
+        let host_rules = hosts
+            .iter()
+            .filter_map(|e| host(e))
+            .flat_map(|(ip, names)| {
+                names
+                    .into_iter()
+                    .map(move |name| Rule::of(name, &ip.to_string()))
+            });
+        let cname_rules = cnames
+            .iter()
+            .filter_map(|e| cname(e))
+            .map(|(name, target)| Rule::of(name, target));
+        Ok(rules::endpoints(host_rules.chain(cname_rules)))
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        let steps = rules::steps(&changes)?;
+        if steps.is_empty() {
+            return Ok(());
+        }
+        info!(target: "pihole", message = format!("Applying {}", changes.summary()));
+        let mut hosts = self.entries(HOSTS).await?;
+        let mut cnames = self.entries(CNAMES).await?;
+        for step in steps {
+            match step {
+                Step::Remove(rule) if rule.record_type == RecordType::CNAME => {
+                    let found: Vec<String> = cnames
+                        .iter()
+                        .filter(|e| {
+                            cname(e).is_some_and(|(name, target)| {
+                                same_name(name, &rule.name) && same_name(target, &rule.target)
+                            })
+                        })
+                        .cloned()
+                        .collect();
+                    for entry in found {
+                        self.delete(CNAMES, &entry).await?;
+                        cnames.retain(|e| *e != entry);
+                    }
+                }
+                Step::Remove(rule) => {
+                    let ip: IpAddr = rule.target.parse()?;
+                    let found: Vec<(String, Vec<String>)> = hosts
+                        .iter()
+                        .filter_map(|e| {
+                            let (address, names) = host(e)?;
+                            (address == ip && names.iter().any(|n| same_name(n, &rule.name))).then(
+                                || {
+                                    let rest = names
+                                        .into_iter()
+                                        .filter(|n| !same_name(n, &rule.name))
+                                        .map(ToString::to_string)
+                                        .collect();
+                                    (e.clone(), rest)
+                                },
+                            )
+                        })
+                        .collect();
+                    for (entry, rest) in found {
+                        self.delete(HOSTS, &entry).await?;
+                        hosts.retain(|e| *e != entry);
+                        if !rest.is_empty() {
+                            let entry = format!("{ip} {}", rest.join(" "));
+                            self.put(HOSTS, &entry).await?;
+                            hosts.push(entry);
+                        }
+                    }
+                }
+                Step::Add(rule) if rule.record_type == RecordType::CNAME => {
+                    let present = cnames.iter().any(|e| {
+                        cname(e).is_some_and(|(name, target)| {
+                            same_name(name, &rule.name) && same_name(target, &rule.target)
+                        })
+                    });
+                    if !present {
+                        let entry = format!("{},{}", rule.name, rule.target);
+                        self.put(CNAMES, &entry).await?;
+                        cnames.push(entry);
+                    }
+                }
+                Step::Add(rule) => {
+                    let ip: IpAddr = rule.target.parse()?;
+                    let present = hosts.iter().any(|e| {
+                        host(e).is_some_and(|(address, names)| {
+                            address == ip && names.iter().any(|n| same_name(n, &rule.name))
+                        })
+                    });
+                    if !present {
+                        let entry = format!("{ip} {}", rule.name);
+                        self.put(HOSTS, &entry).await?;
+                        hosts.push(entry);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        Ok(rules::adjust("pihole", endpoints))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::FromTo;
+    use crate::endpoint::ep;
+    use actix_web::{
+        App, HttpRequest, HttpResponse, HttpServer,
+        web::{self, Data, Json, Path},
+    };
+    use std::{
+        collections::BTreeMap,
+        net::TcpListener,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    const PASSWORD: &str = "secret";
+
+    /// The mock Pi-hole: entries per key, and the logins so far.
+    #[derive(Default)]
+    struct Pihole {
+        entries: Mutex<BTreeMap<String, Vec<String>>>,
+        logins: AtomicUsize,
+    }
+
+    fn error(key: &str, message: &str) -> serde_json::Value {
+        serde_json::json!({"error": {"key": key, "message": message, "hint": null}, "took": 0.0001})
+    }
+
+    fn sid(pihole: &Pihole) -> String {
+        format!("sid{}", pihole.logins.load(Ordering::SeqCst))
+    }
+
+    fn authorized(req: &HttpRequest, pihole: &Pihole) -> bool {
+        req.headers()
+            .get("X-FTL-SID")
+            .is_some_and(|s| *s == *sid(pihole))
+    }
+
+    async fn auth(body: Json<serde_json::Value>, pihole: Data<Arc<Pihole>>) -> HttpResponse {
+        if body["password"] != PASSWORD {
+            return HttpResponse::Unauthorized().json(serde_json::json!({
+                "session": {"valid": false, "totp": false, "sid": null, "validity": -1, "message": "password incorrect"},
+                "took": 0.0001
+            }));
+        }
+        pihole.logins.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::Ok().json(serde_json::json!({
+            "session": {"valid": true, "totp": false, "sid": sid(&pihole), "csrf": "csrf", "validity": 1800, "message": "password correct"},
+            "took": 0.0001
+        }))
+    }
+
+    async fn get(req: HttpRequest, key: Path<String>, pihole: Data<Arc<Pihole>>) -> HttpResponse {
+        if !authorized(&req, &pihole) {
+            return HttpResponse::Unauthorized().json(error("unauthorized", "Unauthorized"));
+        }
+        let entries = pihole
+            .entries
+            .lock()
+            .unwrap()
+            .get(key.as_str())
+            .cloned()
+            .unwrap_or_default();
+        HttpResponse::Ok()
+            .json(serde_json::json!({"config": {"dns": {key.as_str(): entries}}, "took": 0.0001}))
+    }
+
+    async fn put(
+        req: HttpRequest,
+        path: Path<(String, String)>,
+        pihole: Data<Arc<Pihole>>,
+    ) -> HttpResponse {
+        if !authorized(&req, &pihole) {
+            return HttpResponse::Unauthorized().json(error("unauthorized", "Unauthorized"));
+        }
+        let (key, entry) = path.into_inner();
+        let mut all = pihole.entries.lock().unwrap();
+        let entries = all.entry(key).or_default();
+        if entries.contains(&entry) {
+            return HttpResponse::BadRequest().json(error("bad_request", "Item already present"));
+        }
+        entries.push(entry);
+        drop(all);
+        HttpResponse::Created().json(serde_json::json!({"took": 0.0001}))
+    }
+
+    async fn delete(
+        req: HttpRequest,
+        path: Path<(String, String)>,
+        pihole: Data<Arc<Pihole>>,
+    ) -> HttpResponse {
+        if !authorized(&req, &pihole) {
+            return HttpResponse::Unauthorized().json(error("unauthorized", "Unauthorized"));
+        }
+        let (key, entry) = path.into_inner();
+        let mut all = pihole.entries.lock().unwrap();
+        let entries = all.entry(key).or_default();
+        if !entries.contains(&entry) {
+            return HttpResponse::NotFound().json(error("not_found", "Item not found"));
+        }
+        entries.retain(|e| *e != entry);
+        drop(all);
+        HttpResponse::NoContent().finish()
+    }
+
+    fn serve(pihole: Arc<Pihole>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/admin/", listener.local_addr().unwrap());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(Data::new(pihole.clone()))
+                .route("/admin/api/auth", web::post().to(auth))
+                .route("/admin/api/config/dns/{key}", web::get().to(get))
+                .route("/admin/api/config/dns/{key}/{entry}", web::put().to(put))
+                .route(
+                    "/admin/api/config/dns/{key}/{entry}",
+                    web::delete().to(delete),
+                )
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        tokio::spawn(server);
+        url
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_works() {
+        let pihole = Arc::new(Pihole::default());
+        pihole.entries.lock().unwrap().extend([
+            (
+                HOSTS.to_string(),
+                vec!["192.168.0.102 cloud.magicloud.lan nextcloud.magicloud.lan".to_string()],
+            ),
+            (
+                CNAMES.to_string(),
+                vec!["www.magicloud.lan,cloud.magicloud.lan,300".to_string()],
+            ),
+        ]);
+        let url = serve(pihole.clone());
+        let provider = PiholeProvider::new(
+            DomainFilter::Strings {
+                include: Some(vec!["magicloud.lan".to_string()]),
+                exclude: None,
+            },
+            &url,
+            Some(PASSWORD),
+        )
+        .unwrap();
+        let nowhere = || DomainFilter::Strings {
+            include: None,
+            exclude: None,
+        };
+
+        let cloud = ep("cloud.magicloud.lan", RecordType::A, &["192.168.0.102"]);
+        let nextcloud = ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]);
+        let www = ep(
+            "www.magicloud.lan",
+            RecordType::CNAME,
+            &["cloud.magicloud.lan"],
+        );
+        assert_eq!(
+            provider.records().await.unwrap(),
+            vec![cloud.clone(), nextcloud.clone(), www.clone()]
+        );
+
+        // The session expired: log in again.
+        pihole.logins.fetch_add(1, Ordering::SeqCst);
+        let nas = ep("nas.magicloud.lan", RecordType::AAAA, &["fd00::10"]);
+        let moved = ep(
+            "www.magicloud.lan",
+            RecordType::CNAME,
+            &["nextcloud.magicloud.lan"],
+        );
+        provider
+            .apply_changes(Changes {
+                create: vec![nas.clone()],
+                update: vec![FromTo {
+                    from: www,
+                    to: moved.clone(),
+                }],
+                delete: vec![cloud],
+            })
+            .await
+            .unwrap();
+        assert_eq!(pihole.logins.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            pihole.entries.lock().unwrap()[HOSTS],
+            [
+                "fd00::10 nas.magicloud.lan",
+                "192.168.0.102 nextcloud.magicloud.lan"
+            ]
+        );
+        assert_eq!(
+            provider.records().await.unwrap(),
+            vec![nas, nextcloud, moved]
+        );
+
+        assert!(!format!("{provider:?}").contains(PASSWORD));
+        let wrong = PiholeProvider::new(nowhere(), &url, Some("wrong")).unwrap();
+        assert!(wrong.records().await.is_err());
+        let anonymous = PiholeProvider::new(nowhere(), &url, None).unwrap();
+        let error = anonymous.records().await.unwrap_err();
+        assert!(
+            format!("{error}").contains("401 Unauthorized: Unauthorized"),
+            "{error}"
+        );
+    }
+}
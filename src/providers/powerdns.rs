@@ -0,0 +1,536 @@
+//! A `Provider` managing the zones of a `PowerDNS` Authoritative server, through its REST API.
+//!
+//! The zones are those the server has: they make the `DomainFilter`, and the changes to a
+//! record go to the zone with the longest name it belongs to. Each zone gets one PATCH
+//! per `apply_changes`, which `PowerDNS` applies entirely or not at all. Creates and
+//! updates replace the `RRset` of the name and type, deletes remove it.
+//!
+//! Targets are as in External-DNS, names without the trailing dot. TXT targets are sent
+//! as they are when they start with a double quote, and quoted otherwise.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+};
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use reqwest::{Client, Method, RequestBuilder, Response, header::CONTENT_TYPE};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tracing::{info, instrument};
+
+use crate::{
+    changes::{Changes, Operation},
+    domain_filter::DomainFilter,
+    endpoint::{Endpoint, RecordType},
+    provider::Provider,
+};
+
+/// A `Provider` calling the `PowerDNS` Authoritative API.
+#[derive(Clone)]
+pub struct PowerDnsProvider {
+    base_url: String,
+    api_key: String,
+    server_id: String,
+    http: Client,
+}
+impl Debug for PowerDnsProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PowerDnsProvider")
+            .field("base_url", &self.base_url)
+            .field("server_id", &self.server_id)
+            .finish_non_exhaustive()
+    }
+}
+impl PowerDnsProvider {
+    /// Constructor of `PowerDnsProvider`.
+    /// `base_url` is where the API listens, such as `http://127.0.0.1:8081`.
+    #[must_use]
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            server_id: "localhost".to_string(),
+            http: Client::new(),
+        }
+    }
+
+    /// The server to manage the zones of, `localhost` by default.
+    #[must_use]
+    pub fn with_server_id(mut self, server_id: &str) -> Self {
+        self.server_id = server_id.to_string();
+        self
+    }
+
+    /// Use a configured `reqwest::Client` (timeouts, TLS, ...).
+    #[must_use]
+    pub fn with_client(mut self, http: Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(
+                method,
+                format!("{}/api/v1/servers/{}{path}", self.base_url, self.server_id),
+            )
+            .header("X-API-Key", &self.api_key)
+    }
+
+    async fn zones(&self) -> Result<Vec<Zone>> {
+        parse(self.request(Method::GET, "/zones").send().await?).await
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Zone {
+    id: String,
+    name: String,
+    #[serde(default)]
+    rrsets: Vec<RrSet>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RrSet {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    changetype: Option<String>,
+    #[serde(default)]
+    records: Vec<Record>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Record {
+    content: String,
+    #[serde(default)]
+    disabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Patch {
+    rrsets: Vec<RrSet>,
+}
+
+/// How `PowerDNS` explains errors.
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    error: String,
+}
+
+async fn check(res: Response) -> Result<Vec<u8>> {
+    let status = res.status();
+    let url = res.url().clone();
+    let body = res.bytes().await?.to_vec();
+    if status.is_success() {
+        return Ok(body);
+    }
+    let reason = serde_json::from_slice::<ApiError>(&body)
+        .map_or_else(|_| String::from_utf8_lossy(&body).into_owned(), |e| e.error);
+    Err(eyre!("{url}: {status}: {reason}"))
+}
+
+async fn parse<T: DeserializeOwned>(res: Response) -> Result<T> {
+    let body = check(res).await?;
+    serde_json::from_slice(&body).map_err(|e| {
+        eyre!(
+            "Cannot parse PowerDNS response ({e}): {}",
+            String::from_utf8_lossy(&body)
+        )
+    })
+}
+
+fn record_type(name: &str) -> Option<RecordType> {
+    Some(match name {
+        "A" => RecordType::A,
+        "AAAA" => RecordType::AAAA,
+        "CNAME" => RecordType::CNAME,
+        "TXT" => RecordType::TXT,
+        "SRV" => RecordType::SRV,
+        "NS" => RecordType::NS,
+        "PTR" => RecordType::PTR,
+        "MX" => RecordType::MX,
+        "NAPTR" => RecordType::NAPTR,
+        _ => return None,
+    })
+}
+
+fn fqdn(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+// The PowerDNS content of a target: names absolute, TXT quoted.
+fn content(record_type: &RecordType, target: &str) -> String {
+    let with_host = |target: &str, fields: usize| {
+        let mut parts: Vec<String> = target.split_whitespace().map(ToString::to_string).collect();
+        if parts.len() == fields
+            && let Some(host) = parts.last_mut()
+        {
+            *host = fqdn(host);
+        }
+        parts.join(" ")
+    };
+    match record_type {
+        RecordType::CNAME | RecordType::NS | RecordType::PTR => fqdn(target),
+        RecordType::MX => with_host(target, 2),
+        RecordType::SRV => with_host(target, 4),
+        RecordType::TXT if !target.starts_with('"') => {
+            format!("\"{}\"", target.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+        _ => target.to_string(),
+    }
+}
+
+// The target of a PowerDNS content.
+fn target(record_type: &RecordType, content: &str) -> String {
+    match record_type {
+        RecordType::CNAME | RecordType::NS | RecordType::PTR | RecordType::MX | RecordType::SRV => {
+            content.trim_end_matches('.').to_string()
+        }
+        _ => content.to_string(),
+    }
+}
+
+fn endpoint(rrset: &RrSet) -> Option<Endpoint> {
+    let record_type = record_type(&rrset.record_type)?;
+    let targets: Vec<String> = rrset
+        .records
+        .iter()
+        .filter(|r| !r.disabled)
+        .map(|r| target(&record_type, &r.content))
+        .collect();
+    (!targets.is_empty()).then(|| Endpoint {
+        dns_name: Some(rrset.name.trim_end_matches('.').to_string()),
+        targets: Some(targets),
+        record_type: Some(record_type),
+        set_identifier: None,
+        record_ttl: rrset.ttl,
+        labels: None,
+        provider_specific: None,
+    })
+}
+
+// The RRset replacing, or deleting without `replace`, the records of the endpoint.
+fn rrset(endpoint: &Endpoint, replace: bool) -> Result<RrSet> {
+    let name = endpoint
+        .dns_name
+        .as_deref()
+        .ok_or_else(|| eyre!("No dnsName in {endpoint:?}"))?;
+    let record_type = endpoint
+        .record_type
+        .as_ref()
+        .ok_or_else(|| eyre!("No recordType in {endpoint:?}"))?;
+    Ok(RrSet {
+        name: fqdn(name),
+        record_type: format!("{record_type:?}"),
+        ttl: replace.then(|| endpoint.record_ttl.filter(|t| *t > 0).unwrap_or(300)),
+        changetype: Some(if replace { "REPLACE" } else { "DELETE" }.to_string()),
+        records: if replace {
+            endpoint
+                .targets
+                .iter()
+                .flatten()
+                .map(|t| Record {
+                    content: content(record_type, t),
+                    disabled: false,
+                })
+                .collect()
+        } else {
+            vec![]
+        },
+    })
+}
+
+// The zone a name belongs to: the one with the longest name.
+fn zone_of<'a>(zones: &'a [Zone], name: &str) -> Option<&'a Zone> {
+    let name = fqdn(name);
+    zones
+        .iter()
+        .filter(|z| name == z.name || name.ends_with(&format!(".{}", z.name)))
+        .max_by_key(|z| z.name.len())
+}
+
+#[async_trait]
+impl Provider for PowerDnsProvider {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        Ok(DomainFilter::Strings {
+            include: Some(
+                self.zones()
+                    .await?
+                    .iter()
+                    .map(|z| z.name.trim_end_matches('.').to_string())
+                    .collect(),
+            ),
+            exclude: None,
+        })
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        let mut ret = vec![];
+        for zone in self.zones().await? {
+            let zone: Zone = parse(
+                self.request(Method::GET, &format!("/zones/{}", zone.id))
+                    .send()
+                    .await?,
+            )
+            .await?;
+            ret.extend(zone.rrsets.iter().filter_map(endpoint));
+        }
+        Ok(ret)
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        let changes = changes.normalize();
+        if changes.is_empty() {
+            return Ok(());
+        }
+        info!(target: "powerdns", message = format!("Applying {}", changes.summary()));
+        let zones = self.zones().await?;
+        // Per zone, the last operation on each RRset, as PowerDNS refuses duplicates.
+        let mut patches: BTreeMap<&str, BTreeMap<(String, String), RrSet>> = BTreeMap::new();
+        for operation in changes.operations() {
+            let (ep, replace) = match &operation {
+                Operation::Create(ep) => (ep, true),
+                Operation::Update(ft) => (&ft.to, true),
+                Operation::Delete(ep) => (ep, false),
+            };
+            let rrset = rrset(ep, replace)?;
+            let zone = zone_of(&zones, &rrset.name)
+                .ok_or_else(|| eyre!("No zone on the server for {}", rrset.name))?;
+            patches
+                .entry(&zone.id)
+                .or_default()
+                .insert((rrset.name.clone(), rrset.record_type.clone()), rrset);
+        }
+        for (zone_id, rrsets) in patches {
+            let patch = Patch {
+                rrsets: rrsets.into_values().collect(),
+            };
+            let res = self
+                .request(Method::PATCH, &format!("/zones/{zone_id}"))
+                .header(CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(&patch)?)
+                .send()
+                .await?;
+            check(res).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::FromTo;
+    use crate::endpoint::ep;
+    use actix_web::{
+        App, HttpRequest, HttpResponse, HttpServer,
+        web::{self, Data, Json, Path},
+    };
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    const API_KEY: &str = "secret";
+
+    // The zones of the mock server, as PowerDNS returns them.
+    type Zones = Arc<Mutex<BTreeMap<String, serde_json::Value>>>;
+
+    fn authorized(req: &HttpRequest) -> bool {
+        req.headers().get("X-API-Key").is_some_and(|k| k == API_KEY)
+    }
+
+    async fn list(req: HttpRequest, zones: Data<Zones>) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().json(serde_json::json!({"error": "Unauthorized"}));
+        }
+        let list: Vec<_> = zones
+            .lock()
+            .unwrap()
+            .values()
+            .map(|z| {
+                serde_json::json!({
+                    "id": z["id"], "name": z["name"], "kind": "Native",
+                    "url": format!("/api/v1/servers/localhost/zones/{}", z["id"].as_str().unwrap()),
+                    "serial": 2_024_010_101, "dnssec": false
+                })
+            })
+            .collect();
+        HttpResponse::Ok().json(list)
+    }
+
+    async fn get(req: HttpRequest, id: Path<(String, String)>, zones: Data<Zones>) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        let zone = zones.lock().unwrap().get(&id.1).cloned();
+        zone.map_or_else(
+            || HttpResponse::NotFound().json(serde_json::json!({"error": "Not Found"})),
+            |z| HttpResponse::Ok().json(z),
+        )
+    }
+
+    // Applies the RRsets as PowerDNS does, refusing duplicates.
+    async fn patch(
+        req: HttpRequest,
+        id: Path<(String, String)>,
+        body: Json<serde_json::Value>,
+        zones: Data<Zones>,
+    ) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        let mut zones = zones.lock().unwrap();
+        let Some(zone) = zones.get_mut(&id.1) else {
+            return HttpResponse::NotFound().finish();
+        };
+        let patch: Vec<RrSet> = serde_json::from_value(body["rrsets"].clone()).unwrap();
+        let mut rrsets: Vec<RrSet> = serde_json::from_value(zone["rrsets"].clone()).unwrap();
+        for (i, change) in patch.iter().enumerate() {
+            if patch[..i]
+                .iter()
+                .any(|c| c.name == change.name && c.record_type == change.record_type)
+            {
+                return HttpResponse::UnprocessableEntity()
+                    .json(serde_json::json!({"error": "Duplicate RRset"}));
+            }
+            rrsets.retain(|r| r.name != change.name || r.record_type != change.record_type);
+            if change.changetype.as_deref() == Some("REPLACE") {
+                rrsets.push(RrSet {
+                    changetype: None,
+                    ..change.clone()
+                });
+            }
+        }
+        zone["rrsets"] = serde_json::to_value(rrsets).unwrap();
+        drop(zones);
+        HttpResponse::NoContent().finish()
+    }
+
+    fn serve(zones: Zones) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(Data::new(zones.clone()))
+                .route("/api/v1/servers/localhost/zones", web::get().to(list))
+                .route("/api/v1/servers/{server}/zones/{id}", web::get().to(get))
+                .route(
+                    "/api/v1/servers/{server}/zones/{id}",
+                    web::patch().to(patch),
+                )
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        tokio::spawn(server);
+        url
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_works() {
+        let zones: Zones = Arc::default();
+        for (id, rrsets) in [
+            (
+                "magicloud.lan.",
+                serde_json::json!([
+                    {"name": "magicloud.lan.", "type": "SOA", "ttl": 3600, "records": [{"content": "ns1.magicloud.lan. hostmaster.magicloud.lan. 2024010101 10800 3600 604800 3600", "disabled": false}], "comments": []},
+                    {"name": "magicloud.lan.", "type": "MX", "ttl": 3600, "records": [{"content": "10 mail.magicloud.lan.", "disabled": false}], "comments": []},
+                    {"name": "old.magicloud.lan.", "type": "A", "ttl": 60, "records": [{"content": "192.168.0.9", "disabled": true}], "comments": []}
+                ]),
+            ),
+            ("lab.magicloud.lan.", serde_json::json!([])),
+        ] {
+            zones.lock().unwrap().insert(
+                id.to_string(),
+                serde_json::json!({"id": id, "name": id, "kind": "Native", "rrsets": rrsets}),
+            );
+        }
+        let url = serve(zones.clone());
+        let provider = PowerDnsProvider::new(&url, API_KEY);
+
+        assert!(matches!(
+            provider.domain_filter().await.unwrap(),
+            DomainFilter::Strings { include: Some(x), .. }
+                if x == ["lab.magicloud.lan", "magicloud.lan"]
+        ));
+        let mx = ep("magicloud.lan", RecordType::MX, &["10 mail.magicloud.lan"]).with_ttl(3600);
+        assert_eq!(provider.records().await.unwrap(), vec![mx.clone()]);
+
+        let www = ep(
+            "www.magicloud.lan",
+            RecordType::CNAME,
+            &["web.lab.magicloud.lan"],
+        )
+        .with_ttl(300);
+        let web = ep("web.lab.magicloud.lan", RecordType::A, &["192.168.0.102"]).with_ttl(300);
+        let txt = ep(
+            "web.lab.magicloud.lan",
+            RecordType::TXT,
+            &["heritage=external-dns"],
+        )
+        .with_ttl(300);
+        provider
+            .apply_changes(Changes {
+                create: vec![www.clone(), web.clone(), txt.clone()],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        let lab = zones.lock().unwrap()["lab.magicloud.lan."].clone();
+        assert_eq!(lab["rrsets"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            lab["rrsets"][1]["records"][0]["content"],
+            "\"heritage=external-dns\""
+        );
+
+        let moved = ep(
+            "web.lab.magicloud.lan",
+            RecordType::A,
+            &["192.168.0.103", "192.168.0.104"],
+        )
+        .with_ttl(600);
+        provider
+            .apply_changes(Changes {
+                update: vec![FromTo {
+                    from: web,
+                    to: moved.clone(),
+                }],
+                delete: vec![mx],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        let records = provider.records().await.unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(records.contains(&www) && records.contains(&moved));
+        assert_eq!(
+            records.iter().find(|r| **r == moved).unwrap().targets,
+            moved.targets
+        );
+
+        assert!(!format!("{provider:?}").contains(API_KEY));
+        let wrong = PowerDnsProvider::new(&url, "wrong");
+        let error = wrong.records().await.unwrap_err();
+        assert!(format!("{error}").contains("Unauthorized"), "{error}");
+        let outside = ep("www.example.com", RecordType::A, &["192.168.0.1"]).with_ttl(300);
+        assert!(
+            provider
+                .apply_changes(Changes {
+                    create: vec![outside],
+                    ..Changes::default()
+                })
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,592 @@
+//! A `Provider` calling a REST API described by configuration, for DNS services without a
+//! provider of their own.
+//!
+//! [`RestConfig`] holds a template per request (see [`RequestTemplate`] for the placeholders):
+//! - `list`, whose response is mapped back to endpoints with [`JsonPath`]s: `records` selects
+//!   the records, then the other paths are relative to each of them. Records of the same name,
+//!   type and set identifier are merged, so APIs with one record per target fit;
+//! - `create` and `delete`, sent for each endpoint, or each target with `eachTarget`;
+//! - `update`, optional: without it, updates delete the former record and create the new one.
+//!
+//! When a template uses `{id}`, the records are listed first to find the id of each of them.
+//!
+//! ```json
+//! {
+//!   "list": {
+//!     "method": "GET", "url": "https://dns.example.com/api/records",
+//!     "headers": {"Authorization": "Bearer token"},
+//!     "records": "$.data[*]", "dnsName": "$.name", "recordType": "$.type",
+//!     "targets": "$.content", "recordTTL": "$.ttl", "id": "$.id"
+//!   },
+//!   "create": {
+//!     "method": "POST", "url": "https://dns.example.com/api/records", "eachTarget": true,
+//!     "headers": {"Authorization": "Bearer token"},
+//!     "body": {"name": "{dnsName}", "type": "{recordType}", "content": "{target}", "ttl": "{recordTTL}"}
+//!   },
+//!   "delete": {
+//!     "method": "DELETE", "url": "https://dns.example.com/api/records/{id}", "eachTarget": true,
+//!     "headers": {"Authorization": "Bearer token"}
+//!   }
+//! }
+//! ```
+
+mod path;
+mod template;
+
+pub use path::JsonPath;
+pub use template::{PLACEHOLDERS, Rendered, RequestTemplate, Values};
+
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use reqwest::{Client, header::CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    changes::{Changes, FromTo, Operation},
+    domain_filter::DomainFilter,
+    endpoint::{Endpoint, RecordKey, RecordType},
+    provider::Provider,
+};
+
+/// The request listing the records, and how to read its response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListTemplate {
+    #[serde(flatten)]
+    pub request: RequestTemplate,
+    /// The records in the response.
+    pub records: JsonPath,
+    pub dns_name: JsonPath,
+    pub record_type: JsonPath,
+    /// A string or an array of them.
+    pub targets: JsonPath,
+    #[serde(default, rename = "recordTTL", skip_serializing_if = "Option::is_none")]
+    pub record_ttl: Option<JsonPath>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub set_identifier: Option<JsonPath>,
+    /// What `{id}` stands for in the other templates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<JsonPath>,
+}
+
+/// The requests of a `RestProvider`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestConfig {
+    pub list: ListTemplate,
+    pub create: RequestTemplate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update: Option<RequestTemplate>,
+    pub delete: RequestTemplate,
+}
+impl RestConfig {
+    /// If some template needs the ids of the current records.
+    #[must_use]
+    pub fn needs_ids(&self) -> bool {
+        self.create.needs_ids()
+            || self.delete.needs_ids()
+            || self.update.as_ref().is_some_and(RequestTemplate::needs_ids)
+    }
+}
+
+/// A `Provider` calling a REST API as configured.
+#[derive(Debug, Clone)]
+pub struct RestProvider {
+    domain_filter: DomainFilter,
+    config: RestConfig,
+    http: Client,
+}
+impl RestProvider {
+    /// Constructor of `RestProvider`.
+    /// # Errors
+    ///
+    /// When a template has an invalid method or an unknown placeholder, or `update` is sent
+    /// per target.
+    pub fn new(domain_filter: DomainFilter, config: RestConfig) -> Result<Self> {
+        config.list.request.validate(false)?;
+        config.create.validate(false)?;
+        config.delete.validate(false)?;
+        if let Some(update) = &config.update {
+            update.validate(true)?;
+            if update.each_target {
+                return Err(eyre!(
+                    "The update template cannot be sent per target: leave it out to delete and create instead"
+                ));
+            }
+        }
+        if config.needs_ids() && config.list.id.is_none() {
+            return Err(eyre!(
+                "Templates use {{id}}, but the list template maps no id"
+            ));
+        }
+        Ok(Self {
+            domain_filter,
+            config,
+            http: Client::new(),
+        })
+    }
+
+    /// Use a configured `reqwest::Client` (timeouts, TLS, ...).
+    #[must_use]
+    pub fn with_client(mut self, http: Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    async fn send(&self, template: &RequestTemplate, values: &Values<'_>) -> Result<Vec<u8>> {
+        let Rendered { url, headers, body } = template.render(values)?;
+        let mut req = self.http.request(template.method()?, &url);
+        for (name, value) in headers {
+            req = req.header(name, value);
+        }
+        if let Some(body) = body {
+            req = req
+                .header(CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(&body)?);
+        }
+        let res = req.send().await?;
+        let status = res.status();
+        let body = res.bytes().await?.to_vec();
+        if !status.is_success() {
+            return Err(eyre!(
+                "{} {url}: {status}: {}",
+                template.method,
+                String::from_utf8_lossy(&body).trim()
+            ));
+        }
+        Ok(body)
+    }
+
+    /// The records, with the id of each target.
+    async fn list(&self) -> Result<BTreeMap<RecordKey, Listed>> {
+        let list = &self.config.list;
+        let endpoint = Endpoint {
+            dns_name: None,
+            targets: None,
+            record_type: None,
+            set_identifier: None,
+            record_ttl: None,
+            labels: None,
+            provider_specific: None,
+        };
+        let values = Values {
+            endpoint: &endpoint,
+            target: None,
+            id: None,
+            from: None,
+        };
+        let body = self.send(&list.request, &values).await?;
+        let response: Value = serde_json::from_slice(&body).map_err(|e| {
+            eyre!(
+                "Cannot parse the list response ({e}): {}",
+                String::from_utf8_lossy(&body)
+            )
+        })?;
+        let mut ret: BTreeMap<RecordKey, Listed> = BTreeMap::new();
+        for record in list.records.select(&response) {
+            let Some(listed) = Listed::read(list, record)? else {
+                continue;
+            };
+            let key = RecordKey::of(&listed.endpoint)?;
+            match ret.get_mut(&key) {
+                Some(known) => known.merge(listed),
+                None => {
+                    ret.insert(key, listed);
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Send the template for the endpoint, or each of its targets.
+    async fn each(
+        &self,
+        template: &RequestTemplate,
+        endpoint: &Endpoint,
+        listed: Option<&BTreeMap<RecordKey, Listed>>,
+    ) -> Result<()> {
+        let listed = listed.filter(|_| template.needs_ids());
+        let id = |target: Option<&str>| -> Result<Option<&str>> {
+            let Some(listed) = listed else {
+                return Ok(None);
+            };
+            let key = RecordKey::of(endpoint)?;
+            listed
+                .get(&key)
+                .and_then(|l| l.id(target))
+                .map(Some)
+                .ok_or_else(|| eyre!("No id for {key:?} {} in the list", target.unwrap_or("")))
+        };
+        if template.each_target {
+            for target in endpoint.targets.iter().flatten() {
+                let values = Values {
+                    endpoint,
+                    target: Some(target),
+                    id: id(Some(target))?,
+                    from: None,
+                };
+                self.send(template, &values).await?;
+            }
+        } else {
+            let values = Values {
+                endpoint,
+                target: None,
+                id: id(None)?,
+                from: None,
+            };
+            self.send(template, &values).await?;
+        }
+        Ok(())
+    }
+
+    /// Send the update template, with the former record as `from.`.
+    async fn update(
+        &self,
+        template: &RequestTemplate,
+        ft: &FromTo<Endpoint>,
+        listed: Option<&BTreeMap<RecordKey, Listed>>,
+    ) -> Result<()> {
+        let placeholders = template.placeholders();
+        let id = |ep: &Endpoint, placeholder: &str| -> Result<Option<&str>> {
+            let Some(listed) = listed.filter(|_| placeholders.contains(&placeholder)) else {
+                return Ok(None);
+            };
+            let key = RecordKey::of(ep)?;
+            listed
+                .get(&key)
+                .and_then(|l| l.id(None))
+                .map(Some)
+                .ok_or_else(|| eyre!("No id for {key:?} in the list"))
+        };
+        let values = Values {
+            endpoint: &ft.to,
+            target: None,
+            id: id(&ft.to, "id")?,
+            from: Some((&ft.from, id(&ft.from, "from.id")?)),
+        };
+        self.send(template, &values).await?;
+        Ok(())
+    }
+}
+
+/// A listed record.
+#[derive(Debug, Clone)]
+struct Listed {
+    endpoint: Endpoint,
+    /// The id of each target.
+    ids: Vec<Option<String>>,
+}
+impl Listed {
+    fn read(list: &ListTemplate, record: &Value) -> Result<Option<Self>> {
+        let strings = |path: &JsonPath| -> Vec<String> {
+            path.select(record)
+                .into_iter()
+                .flat_map(|v| match v {
+                    Value::Array(a) => a.iter().collect(),
+                    v => vec![v],
+                })
+                .filter_map(|v| match v {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Number(n) => Some(n.to_string()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let first =
+            |path: &Option<JsonPath>| path.as_ref().and_then(|p| strings(p).into_iter().next());
+        let dns_name = strings(&list.dns_name)
+            .into_iter()
+            .next()
+            .ok_or_else(|| eyre!("No {} in listed record {record}", list.dns_name))?;
+        let Some(record_type) = strings(&list.record_type).into_iter().next() else {
+            return Err(eyre!("No {} in listed record {record}", list.record_type));
+        };
+        let Ok(record_type) =
+            serde_json::from_value::<RecordType>(Value::String(record_type.to_uppercase()))
+        else {
+            warn!(target: "rest", message = format!("Skipping record of type {record_type}: {record}"));
+            return Ok(None);
+        };
+        let targets = strings(&list.targets);
+        let id = first(&list.id);
+        Ok(Some(Self {
+            ids: vec![id; targets.len()],
+            endpoint: Endpoint {
+                dns_name: Some(dns_name.trim_end_matches('.').to_string()),
+                targets: Some(targets),
+                record_type: Some(record_type),
+                set_identifier: first(&list.set_identifier),
+                record_ttl: first(&list.record_ttl).and_then(|t| t.parse().ok()),
+                labels: None,
+                provider_specific: None,
+            },
+        }))
+    }
+
+    fn merge(&mut self, other: Self) {
+        let targets = self.endpoint.targets.get_or_insert_default();
+        for (target, id) in other
+            .endpoint
+            .targets
+            .unwrap_or_default()
+            .into_iter()
+            .zip(other.ids)
+        {
+            if !targets.contains(&target) {
+                targets.push(target);
+                self.ids.push(id);
+            }
+        }
+    }
+
+    /// The id of the target, or the first one.
+    fn id(&self, target: Option<&str>) -> Option<&str> {
+        let index = match target {
+            Some(target) => self
+                .endpoint
+                .targets
+                .iter()
+                .flatten()
+                .position(|t| t == target)?,
+            None => 0,
+        };
+        self.ids.get(index)?.as_deref()
+    }
+}
+
+#[async_trait]
+impl Provider for RestProvider {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        Ok(self.domain_filter.clone())
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        Ok(self
+            .list()
+            .await?
+            .into_values()
+            .map(|l| l.endpoint)
+            .collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        info!(target: "rest", message = format!("Applying {}", changes.summary()));
+        let config = &self.config;
+        let listed = if config.needs_ids() {
+            Some(self.list().await?)
+        } else {
+            None
+        };
+        for operation in changes.operations() {
+            match operation {
+                Operation::Create(ep) => self.each(&config.create, &ep, listed.as_ref()).await?,
+                Operation::Delete(ep) => self.each(&config.delete, &ep, listed.as_ref()).await?,
+                Operation::Update(ft) => {
+                    if let Some(update) = &config.update {
+                        self.update(update, &ft, listed.as_ref()).await?;
+                    } else {
+                        self.each(&config.delete, &ft.from, listed.as_ref()).await?;
+                        self.each(&config.create, &ft.to, listed.as_ref()).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::ep;
+    use actix_web::{
+        App, HttpRequest, HttpResponse, HttpServer,
+        web::{self, Data, Json, Path},
+    };
+    use serde_json::json;
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    /// The records of the mock API, one per target, and the next id.
+    type Records = Arc<Mutex<(Vec<Value>, u64)>>;
+
+    fn authorized(req: &HttpRequest) -> bool {
+        req.headers()
+            .get("Authorization")
+            .is_some_and(|a| a == "Bearer token")
+    }
+
+    async fn list(req: HttpRequest, records: Data<Records>) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().body("Unauthorized");
+        }
+        let records = records.lock().unwrap().0.clone();
+        HttpResponse::Ok().json(json!({"data": records, "page": 1}))
+    }
+
+    async fn create(req: HttpRequest, body: Json<Value>, records: Data<Records>) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().body("Unauthorized");
+        }
+        let mut record = body.into_inner();
+        if !record["ttl"].is_u64() {
+            return HttpResponse::BadRequest().body("ttl must be a number");
+        }
+        let mut records = records.lock().unwrap();
+        records.1 += 1;
+        record["id"] = json!(records.1);
+        records.0.push(record.clone());
+        drop(records);
+        HttpResponse::Created().json(record)
+    }
+
+    async fn delete(req: HttpRequest, id: Path<u64>, records: Data<Records>) -> HttpResponse {
+        if !authorized(&req) {
+            return HttpResponse::Unauthorized().body("Unauthorized");
+        }
+        let mut records = records.lock().unwrap();
+        let before = records.0.len();
+        records.0.retain(|r| r["id"] != *id);
+        if records.0.len() == before {
+            return HttpResponse::NotFound().body("No such record");
+        }
+        drop(records);
+        HttpResponse::NoContent().finish()
+    }
+
+    fn serve(records: Records) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(Data::new(records.clone()))
+                .route("/api/records", web::get().to(list))
+                .route("/api/records", web::post().to(create))
+                .route("/api/records/{id}", web::delete().to(delete))
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        tokio::spawn(server);
+        url
+    }
+
+    fn config(url: &str) -> RestConfig {
+        serde_json::from_value(json!({
+            "list": {
+                "method": "GET", "url": format!("{url}/api/records"),
+                "headers": {"Authorization": "Bearer token"},
+                "records": "$.data[*]", "dnsName": "$.name", "recordType": "$.type",
+                "targets": "$.content", "recordTTL": "$.ttl", "id": "$.id"
+            },
+            "create": {
+                "method": "POST", "url": format!("{url}/api/records"), "eachTarget": true,
+                "headers": {"Authorization": "Bearer token"},
+                "body": {"name": "{dnsName}", "type": "{recordType}", "content": "{target}", "ttl": "{recordTTL}"}
+            },
+            "delete": {
+                "method": "DELETE", "url": format!("{url}/api/records/{{id}}"), "eachTarget": true,
+                "headers": {"Authorization": "Bearer token"}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_works() {
+        let records: Records = Arc::new(Mutex::new((
+            vec![
+                json!({"id": 1, "name": "cloud.magicloud.lan", "type": "A", "content": "192.168.0.102", "ttl": 300}),
+                json!({"id": 2, "name": "cloud.magicloud.lan", "type": "A", "content": "192.168.0.103", "ttl": 300}),
+                json!({"id": 3, "name": "magicloud.lan", "type": "SOA", "content": "ns1.magicloud.lan. hostmaster.magicloud.lan. 1 3600 600 86400 300", "ttl": 3600}),
+            ],
+            3,
+        )));
+        let url = serve(records.clone());
+        let domain_filter = DomainFilter::Strings {
+            include: Some(vec!["magicloud.lan".to_string()]),
+            exclude: None,
+        };
+        let provider = RestProvider::new(domain_filter.clone(), config(&url)).unwrap();
+
+        let cloud = ep(
+            "cloud.magicloud.lan",
+            RecordType::A,
+            &["192.168.0.102", "192.168.0.103"],
+        )
+        .with_ttl(300);
+        assert_eq!(provider.records().await.unwrap(), vec![cloud.clone()]);
+
+        let moved = ep("cloud.magicloud.lan", RecordType::A, &["192.168.0.104"]).with_ttl(300);
+        let www = ep(
+            "www.magicloud.lan",
+            RecordType::CNAME,
+            &["cloud.magicloud.lan"],
+        )
+        .with_ttl(60);
+        provider
+            .apply_changes(Changes {
+                create: vec![www.clone()],
+                update: vec![FromTo {
+                    from: cloud,
+                    to: moved.clone(),
+                }],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(provider.records().await.unwrap(), vec![moved, www.clone()]);
+        assert_eq!(
+            records.lock().unwrap().0[1],
+            json!({"id": 4, "name": "www.magicloud.lan", "type": "CNAME", "content": "cloud.magicloud.lan", "ttl": 60})
+        );
+
+        // A record the API does not have.
+        let missing = ep("nas.magicloud.lan", RecordType::A, &["192.168.0.10"]).with_ttl(300);
+        let error = provider
+            .apply_changes(Changes {
+                delete: vec![missing.clone()],
+                ..Changes::default()
+            })
+            .await
+            .unwrap_err();
+        assert!(format!("{error}").starts_with("No id for"), "{error}");
+        let mut updating = config(&url);
+        updating.update = Some(RequestTemplate::new(
+            "PUT",
+            &format!("{url}/api/records/{{from.id}}"),
+        ));
+        let error = RestProvider::new(domain_filter.clone(), updating)
+            .unwrap()
+            .apply_changes(Changes {
+                update: vec![FromTo {
+                    from: missing.clone(),
+                    to: missing,
+                }],
+                ..Changes::default()
+            })
+            .await
+            .unwrap_err();
+        assert!(format!("{error}").starts_with("No id for"), "{error}");
+
+        // Mistakes in the configuration are found early.
+        let mut wrong = config(&url);
+        wrong.create.url.push_str("/{name}");
+        assert!(RestProvider::new(domain_filter.clone(), wrong).is_err());
+        let mut wrong = config(&url);
+        wrong.list.id = None;
+        assert!(RestProvider::new(domain_filter, wrong).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! The subset of `JSONPath` response mappings need: `$`, `.field`, `['field']`, `[n]` and
+//! the wildcards `.*` and `[*]`.
+
+use std::{fmt::Display, str::FromStr};
+
+use eyre::{Error, Result, eyre};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Field(String),
+    Index(usize),
+    Wildcard,
+}
+
+/// A parsed `JSONPath`, such as `$.data.records[*]`. Serialized as its source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct JsonPath {
+    source: String,
+    segments: Vec<Segment>,
+}
+impl JsonPath {
+    /// The values at the path, none when it leads nowhere.
+    #[must_use]
+    pub fn select<'a>(&self, value: &'a Value) -> Vec<&'a Value> {
+        let mut current = vec![value];
+        for segment in &self.segments {
+            current = current
+                .into_iter()
+                .flat_map(|v| -> Vec<&Value> {
+                    match (segment, v) {
+                        (Segment::Field(f), Value::Object(o)) => o.get(f).into_iter().collect(),
+                        (Segment::Index(i), Value::Array(a)) => a.get(*i).into_iter().collect(),
+                        (Segment::Wildcard, Value::Array(a)) => a.iter().collect(),
+                        (Segment::Wildcard, Value::Object(o)) => o.values().collect(),
+                        _ => vec![],
+                    }
+                })
+                .collect();
+        }
+        current
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self> {
+        let invalid = |why: &str| eyre!("Invalid JSONPath {source:?}: {why}");
+        let mut rest = source
+            .strip_prefix('$')
+            .ok_or_else(|| invalid("it must start with `$`"))?;
+        let mut segments = vec![];
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                let (field, after) = after.split_at(end);
+                segments.push(match field {
+                    "" => return Err(invalid("empty field name")),
+                    "*" => Segment::Wildcard,
+                    _ => Segment::Field(field.to_string()),
+                });
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after.find(']').ok_or_else(|| invalid("unclosed `[`"))?;
+                let (inner, after) = after.split_at(end);
+                let quoted = inner
+                    .strip_prefix('\'')
+                    .and_then(|i| i.strip_suffix('\''))
+                    .or_else(|| inner.strip_prefix('"').and_then(|i| i.strip_suffix('"')));
+                segments.push(match (inner, quoted) {
+                    (_, Some(field)) => Segment::Field(field.to_string()),
+                    ("*", None) => Segment::Wildcard,
+                    (index, None) => Segment::Index(
+                        index
+                            .trim()
+                            .parse()
+                            .map_err(|_| invalid("expected an index, `*` or a quoted field"))?,
+                    ),
+                });
+                rest = &after[1..];
+            } else {
+                return Err(invalid("expected `.` or `[`"));
+            }
+        }
+        Ok(Self {
+            source: source.to_string(),
+            segments,
+        })
+    }
+}
+
+impl TryFrom<String> for JsonPath {
+    type Error = Error;
+
+    fn try_from(source: String) -> Result<Self> {
+        source.parse()
+    }
+}
+
+impl From<JsonPath> for String {
+    fn from(path: JsonPath) -> Self {
+        path.source
+    }
+}
+
+impl Display for JsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn it_works() {
+        let value = json!({
+            "data": {"records": [
+                {"name": "cloud", "values": ["192.168.0.102", "192.168.0.103"], "x-ttl": 60},
+                {"name": "www", "values": ["cloud"]}
+            ]}
+        });
+        let select = |path: &str| path.parse::<JsonPath>().unwrap().select(&value);
+        assert_eq!(select("$"), [&value]);
+        assert_eq!(
+            select("$.data.records[*].name"),
+            [&json!("cloud"), &json!("www")]
+        );
+        assert_eq!(select("$['data'].records[1].values[0]"), [&json!("cloud")]);
+        assert_eq!(select("$.data.records[0]['x-ttl']"), [&json!(60)]);
+        assert_eq!(select("$.data.records[0].*").len(), 3);
+        assert!(select("$.data.records[2].name").is_empty());
+        assert!(select("$.data.missing[*]").is_empty());
+
+        for invalid in ["data", "$.", "$.data[", "$[x]", "$data"] {
+            assert!(invalid.parse::<JsonPath>().is_err(), "{invalid}");
+        }
+        let path: JsonPath = serde_json::from_value(json!("$.data.records[*]")).unwrap();
+        assert_eq!(
+            serde_json::to_value(&path).unwrap(),
+            json!("$.data.records[*]")
+        );
+        assert!(serde_json::from_value::<JsonPath>(json!("data")).is_err());
+    }
+}
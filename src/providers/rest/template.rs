@@ -0,0 +1,367 @@
+//! Requests described by templates, with `{placeholder}`s filled from endpoints.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use eyre::{Result, eyre};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::endpoint::Endpoint;
+
+/// The placeholders, each of which also has a `from.` form in update templates.
+pub const PLACEHOLDERS: [&str; 7] = [
+    "dnsName",
+    "recordType",
+    "recordTTL",
+    "setIdentifier",
+    "targets",
+    "target",
+    "id",
+];
+
+/// A request, as a template.
+///
+/// The URL, header values and the strings of the JSON body may hold placeholders:
+/// - `{dnsName}`, `{recordType}`, `{recordTTL}` and `{setIdentifier}`, of the endpoint;
+/// - `{targets}`, all the targets, and `{target}`, the one of the request with `each_target`
+///   or the first one otherwise;
+/// - `{id}`, what the `id` mapping of the list template gave for the record (or target);
+/// - in update templates, the same prefixed with `from.` for the former record.
+///
+/// A body string that is only a placeholder becomes a JSON value of its type: the TTL a
+/// number, the targets an array, a missing value `null`. Within text, targets are joined with
+/// commas and missing values are empty. Values are percent-encoded within the URL.
+/// `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestTemplate {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    /// Send one request per target.
+    #[serde(default)]
+    pub each_target: bool,
+}
+impl RequestTemplate {
+    /// Constructor of `RequestTemplate`, without headers or body.
+    #[must_use]
+    pub fn new(method: &str, url: &str) -> Self {
+        Self {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: BTreeMap::new(),
+            body: None,
+            each_target: false,
+        }
+    }
+
+    /// Add a header.
+    #[must_use]
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Set the JSON body.
+    #[must_use]
+    pub fn with_body(mut self, body: Value) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    /// Send one request per target.
+    #[must_use]
+    pub const fn with_each_target(mut self) -> Self {
+        self.each_target = true;
+        self
+    }
+
+    /// All the placeholders of the template.
+    #[must_use]
+    pub fn placeholders(&self) -> Vec<&str> {
+        fn body<'a>(value: &'a Value, out: &mut Vec<&'a str>) {
+            match value {
+                Value::String(s) => out.extend(placeholders(s)),
+                Value::Array(a) => a.iter().for_each(|v| body(v, out)),
+                Value::Object(o) => o.values().for_each(|v| body(v, out)),
+                _ => {}
+            }
+        }
+        let mut ret = placeholders(&self.url);
+        ret.extend(self.headers.values().flat_map(|v| placeholders(v)));
+        if let Some(value) = &self.body {
+            body(value, &mut ret);
+        }
+        ret
+    }
+
+    /// Check the method and placeholders. `from.` ones are only allowed with `from`.
+    /// # Errors
+    ///
+    /// When the method or a placeholder is unknown.
+    pub fn validate(&self, from: bool) -> Result<()> {
+        self.method()?;
+        for placeholder in self.placeholders() {
+            let name = match placeholder.strip_prefix("from.") {
+                Some(name) if from => name,
+                _ => placeholder,
+            };
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(eyre!(
+                    "Unknown placeholder {{{placeholder}}} in {} {}",
+                    self.method,
+                    self.url
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// If some placeholder needs the ids of the current records.
+    #[must_use]
+    pub fn needs_ids(&self) -> bool {
+        self.placeholders()
+            .iter()
+            .any(|p| *p == "id" || *p == "from.id")
+    }
+
+    /// # Errors
+    ///
+    /// When the method is not valid.
+    pub fn method(&self) -> Result<Method> {
+        Method::from_bytes(self.method.to_ascii_uppercase().as_bytes())
+            .map_err(|_| eyre!("Invalid HTTP method {}", self.method))
+    }
+
+    /// The request, with the placeholders filled.
+    /// # Errors
+    ///
+    /// When a placeholder is unknown.
+    pub fn render(&self, values: &Values) -> Result<Rendered> {
+        let url = text(&self.url, values, percent_encode)?;
+        let headers = self
+            .headers
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), text(v, values, ToString::to_string)?)))
+            .collect::<Result<_>>()?;
+        let body = self.body.as_ref().map(|b| json(b, values)).transpose()?;
+        Ok(Rendered { url, headers, body })
+    }
+}
+
+/// A request out of a `RequestTemplate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendered {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Value>,
+}
+
+/// What the placeholders of one request stand for.
+#[derive(Debug, Clone, Copy)]
+pub struct Values<'a> {
+    pub endpoint: &'a Endpoint,
+    pub target: Option<&'a str>,
+    pub id: Option<&'a str>,
+    pub from: Option<(&'a Endpoint, Option<&'a str>)>,
+}
+impl Values<'_> {
+    /// The value of a placeholder.
+    /// # Errors
+    ///
+    /// When the placeholder is unknown, or `from.` without a former record.
+    pub fn get(&self, placeholder: &str) -> Result<Value> {
+        let (values, name) = match placeholder.strip_prefix("from.") {
+            Some(name) => {
+                let (endpoint, id) = self
+                    .from
+                    .ok_or_else(|| eyre!("{{{placeholder}}} is only known in updates"))?;
+                let values = Values {
+                    endpoint,
+                    target: None,
+                    id,
+                    from: None,
+                };
+                (values, name)
+            }
+            None => (*self, placeholder),
+        };
+        let ep = values.endpoint;
+        Ok(match name {
+            "dnsName" => ep.dns_name.clone().into(),
+            "recordType" => ep.record_type.as_ref().map(|t| format!("{t:?}")).into(),
+            "recordTTL" => ep.record_ttl.into(),
+            "setIdentifier" => ep.set_identifier.clone().into(),
+            "targets" => ep.targets.clone().unwrap_or_default().into(),
+            "target" => values
+                .target
+                .or_else(|| ep.targets.iter().flatten().next().map(String::as_str))
+                .into(),
+            "id" => values.id.into(),
+            _ => return Err(eyre!("Unknown placeholder {{{placeholder}}}")),
+        })
+    }
+}
+
+// A part of a template text.
+enum Piece<'a> {
+    Text(&'a str),
+    /// Without its braces.
+    Placeholder(&'a str),
+}
+
+fn pieces(text: &str) -> Vec<Piece<'_>> {
+    let mut ret = vec![];
+    let mut remaining = text;
+    while let Some(start) = remaining.find(['{', '}']) {
+        ret.push(Piece::Text(&remaining[..start]));
+        let tail = &remaining[start..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            ret.push(Piece::Text(&tail[..1]));
+            remaining = &tail[2..];
+        } else if tail.starts_with('{')
+            && let Some(len) = tail.find('}')
+        {
+            ret.push(Piece::Placeholder(&tail[1..len]));
+            remaining = &tail[len + 1..];
+        } else {
+            ret.push(Piece::Text(&tail[..1]));
+            remaining = &tail[1..];
+        }
+    }
+    ret.push(Piece::Text(remaining));
+    ret
+}
+
+// The placeholders of a text, without their braces.
+fn placeholders(text: &str) -> Vec<&str> {
+    pieces(text)
+        .into_iter()
+        .filter_map(|p| match p {
+            Piece::Placeholder(name) => Some(name),
+            Piece::Text(_) => None,
+        })
+        .collect()
+}
+
+fn text(template: &str, values: &Values, encode: fn(&str) -> String) -> Result<String> {
+    let mut ret = String::new();
+    for piece in pieces(template) {
+        let name = match piece {
+            Piece::Text(text) => {
+                ret.push_str(text);
+                continue;
+            }
+            Piece::Placeholder(name) => name,
+        };
+        let value = match values.get(name)? {
+            Value::Null => String::new(),
+            Value::String(s) => s,
+            Value::Array(a) => a
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map_or_else(|| v.to_string(), ToString::to_string)
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+            v => v.to_string(),
+        };
+        ret.push_str(&encode(&value));
+    }
+    Ok(ret)
+}
+
+fn json(template: &Value, values: &Values) -> Result<Value> {
+    Ok(match template {
+        Value::String(s) => match placeholders(s).as_slice() {
+            [only] if s.len() == only.len() + 2 => values.get(only)?,
+            _ => Value::String(text(s, values, ToString::to_string)?),
+        },
+        Value::Array(a) => a.iter().map(|v| json(v, values)).collect::<Result<_>>()?,
+        Value::Object(o) => o
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), json(v, values)?)))
+            .collect::<Result<_>>()?,
+        v => v.clone(),
+    })
+}
+
+// Percent-encodes all but the unreserved characters of RFC 3986.
+fn percent_encode(value: &str) -> String {
+    value.bytes().fold(String::new(), |mut ret, b| {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            ret.push(char::from(b));
+        } else {
+            let _ = write!(ret, "%{b:02X}");
+        }
+        ret
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::{RecordType, ep};
+    use serde_json::json;
+
+    #[test]
+    fn it_works() {
+        let from = ep("cloud.magicloud.lan", RecordType::TXT, &["a b"]);
+        let to = ep("cloud.magicloud.lan", RecordType::TXT, &["a b", "c/d"]).with_ttl(300);
+        let values = Values {
+            endpoint: &to,
+            target: None,
+            id: Some("42"),
+            from: Some((&from, Some("41"))),
+        };
+        let template =
+            RequestTemplate::new("put", "http://api/zones/{dnsName}/{from.id}?v={target}")
+                .with_header("Authorization", "Bearer token")
+                .with_header("X-Record", "{recordType} {targets}")
+                .with_header("X-Set", "{{{setIdentifier}}}")
+                .with_body(json!({
+                    "name": "{dnsName}",
+                    "ttl": "{recordTTL}",
+                    "old": {"ttl": "{from.recordTTL}", "targets": "{from.targets}"},
+                    "values": ["{targets}", "set {setIdentifier}"],
+                    "id": "{id}",
+                    "enabled": true
+                }));
+        template.validate(true).unwrap();
+        assert!(template.validate(false).is_err());
+        assert!(template.needs_ids());
+        assert_eq!(template.method().unwrap(), Method::PUT);
+
+        let Rendered { url, headers, body } = template.render(&values).unwrap();
+        assert_eq!(url, "http://api/zones/cloud.magicloud.lan/41?v=a%20b");
+        assert_eq!(
+            headers[1],
+            ("X-Record".to_string(), "TXT a b,c/d".to_string())
+        );
+        assert_eq!(headers[2], ("X-Set".to_string(), "{}".to_string()));
+        assert_eq!(
+            body.unwrap(),
+            json!({
+                "name": "cloud.magicloud.lan",
+                "ttl": 300,
+                "old": {"ttl": null, "targets": ["a b"]},
+                "values": [["a b", "c/d"], "set "],
+                "id": "42",
+                "enabled": true
+            })
+        );
+
+        let unknown = RequestTemplate::new("GET", "http://api/{name}");
+        assert!(unknown.validate(true).is_err());
+        assert!(
+            RequestTemplate::new("NOT A METHOD", "http://api")
+                .validate(false)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! The DNS wire format of RFC 1035, as far as UPDATE and AXFR need it.
+
+use eyre::{Result, eyre};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_MX: u16 = 15;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_NAPTR: u16 = 35;
+pub const TYPE_TSIG: u16 = 250;
+pub const TYPE_AXFR: u16 = 252;
+
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_NONE: u16 = 254;
+pub const CLASS_ANY: u16 = 255;
+
+pub const OPCODE_QUERY: u16 = 0;
+pub const OPCODE_UPDATE: u16 = 5;
+
+/// A question, or the zone of an UPDATE.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+/// A resource record. Names within the RDATA are kept uncompressed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rr {
+    pub name: String,
+    pub rtype: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub rdata: Vec<u8>,
+}
+
+/// A DNS message. For UPDATE, `answers` are the prerequisites and `authority` the updates.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Rr>,
+    pub authority: Vec<Rr>,
+    pub additional: Vec<Rr>,
+}
+impl Message {
+    /// A request with the opcode.
+    #[must_use]
+    pub const fn request(id: u16, opcode: u16) -> Self {
+        Self {
+            id,
+            flags: opcode << 11,
+            questions: vec![],
+            answers: vec![],
+            authority: vec![],
+            additional: vec![],
+        }
+    }
+
+    #[must_use]
+    pub const fn rcode(&self) -> u16 {
+        self.flags & 0xf
+    }
+
+    /// The message on the wire, without name compression.
+    /// # Errors
+    ///
+    /// When a name is not valid, or a section too large.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(512);
+        out.extend(self.id.to_be_bytes());
+        out.extend(self.flags.to_be_bytes());
+        for count in [
+            self.questions.len(),
+            self.answers.len(),
+            self.authority.len(),
+            self.additional.len(),
+        ] {
+            out.extend(u16::try_from(count)?.to_be_bytes());
+        }
+        for q in &self.questions {
+            encode_name(&q.name, &mut out)?;
+            out.extend(q.qtype.to_be_bytes());
+            out.extend(q.qclass.to_be_bytes());
+        }
+        for rr in self
+            .answers
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+        {
+            rr.encode(&mut out)?;
+        }
+        Ok(out)
+    }
+
+    /// Read a message. Also returns where its last record starts, for TSIG.
+    /// # Errors
+    ///
+    /// When the message is truncated or malformed.
+    pub fn decode(data: &[u8]) -> Result<(Self, usize)> {
+        let mut reader = Reader { data, at: 0 };
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+        let mut ret = Self {
+            id,
+            flags,
+            ..Self::default()
+        };
+        for _ in 0..counts[0] {
+            ret.questions.push(Question {
+                name: reader.name()?,
+                qtype: reader.u16()?,
+                qclass: reader.u16()?,
+            });
+        }
+        let mut last = reader.at;
+        for (i, count) in counts[1..].iter().enumerate() {
+            for _ in 0..*count {
+                last = reader.at;
+                let rr = reader.rr()?;
+                match i {
+                    0 => ret.answers.push(rr),
+                    1 => ret.authority.push(rr),
+                    _ => ret.additional.push(rr),
+                }
+            }
+        }
+        Ok((ret, last))
+    }
+}
+
+impl Rr {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        encode_name(&self.name, out)?;
+        out.extend(self.rtype.to_be_bytes());
+        out.extend(self.class.to_be_bytes());
+        out.extend(self.ttl.to_be_bytes());
+        out.extend(u16::try_from(self.rdata.len())?.to_be_bytes());
+        out.extend(&self.rdata);
+        Ok(())
+    }
+}
+
+/// Write a name, absolute with or without the trailing dot, as labels.
+/// # Errors
+///
+/// When a label is empty or longer than 63 bytes.
+pub fn encode_name(name: &str, out: &mut Vec<u8>) -> Result<()> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if !name.is_empty() {
+        for label in name.split('.') {
+            let len = u8::try_from(label.len())
+                .ok()
+                .filter(|l| (1..64).contains(l))
+                .ok_or_else(|| eyre!("Invalid label {label:?} in {name}"))?;
+            out.push(len);
+            out.extend(label.as_bytes());
+        }
+    }
+    out.push(0);
+    Ok(())
+}
+
+/// A name in canonical form, lower-cased, for TSIG digests.
+/// # Errors
+///
+/// When the name is not valid.
+pub fn canonical_name(name: &str) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    encode_name(&name.to_ascii_lowercase(), &mut out)?;
+    Ok(out)
+}
+
+/// Reads RDATA of uncompressed names, as `Rr` holds them.
+pub struct Reader<'a> {
+    pub data: &'a [u8],
+    pub at: usize,
+}
+impl Reader<'_> {
+    /// Read `n` bytes.
+    /// # Errors
+    ///
+    /// When there are not that many bytes left.
+    pub fn bytes(&mut self, n: usize) -> Result<&[u8]> {
+        let ret = self
+            .data
+            .get(self.at..self.at + n)
+            .ok_or_else(|| eyre!("Truncated DNS message"))?;
+        self.at += n;
+        Ok(ret)
+    }
+
+    /// # Errors
+    ///
+    /// When there are not enough bytes left.
+    pub fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /// # Errors
+    ///
+    /// When there are not enough bytes left.
+    pub fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// A name, following compression pointers, without the trailing dot.
+    /// # Errors
+    ///
+    /// When the name is truncated, or pointers loop.
+    pub fn name(&mut self) -> Result<String> {
+        let mut labels = vec![];
+        let mut at = self.at;
+        let mut jumped = false;
+        for _ in 0..128 {
+            let len = *self
+                .data
+                .get(at)
+                .ok_or_else(|| eyre!("Truncated DNS name"))?;
+            match len {
+                0 => {
+                    if !jumped {
+                        self.at = at + 1;
+                    }
+                    return Ok(labels.join("."));
+                }
+                l if l & 0xc0 == 0xc0 => {
+                    let low = *self
+                        .data
+                        .get(at + 1)
+                        .ok_or_else(|| eyre!("Truncated DNS name"))?;
+                    if !jumped {
+                        self.at = at + 2;
+                    }
+                    jumped = true;
+                    at = (usize::from(l & 0x3f) << 8) | usize::from(low);
+                }
+                l => {
+                    let label = self
+                        .data
+                        .get(at + 1..at + 1 + usize::from(l))
+                        .ok_or_else(|| eyre!("Truncated DNS name"))?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    at += 1 + usize::from(l);
+                }
+            }
+        }
+        Err(eyre!("DNS name compression loop"))
+    }
+
+    fn rr(&mut self) -> Result<Rr> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        let class = self.u16()?;
+        let ttl = self.u32()?;
+        let len = usize::from(self.u16()?);
+        let end = self.at + len;
+        // Names in RDATA may be compressed: rewrite them uncompressed.
+        let mut rdata = vec![];
+        match rtype {
+            TYPE_NS | TYPE_CNAME | TYPE_PTR => encode_name(&self.name()?, &mut rdata)?,
+            TYPE_MX => {
+                rdata.extend(self.bytes(2)?);
+                encode_name(&self.name()?, &mut rdata)?;
+            }
+            TYPE_SRV => {
+                rdata.extend(self.bytes(6)?);
+                encode_name(&self.name()?, &mut rdata)?;
+            }
+            TYPE_SOA => {
+                encode_name(&self.name()?, &mut rdata)?;
+                encode_name(&self.name()?, &mut rdata)?;
+                rdata.extend(self.bytes(20)?);
+            }
+            _ => rdata.extend(self.bytes(len)?),
+        }
+        if self.at != end {
+            return Err(eyre!("RDATA length mismatch in {name} type {rtype}"));
+        }
+        Ok(Rr {
+            name,
+            rtype,
+            class,
+            ttl,
+            rdata,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let mut message = Message::request(0x1234, OPCODE_UPDATE);
+        message.questions.push(Question {
+            name: "magicloud.lan".to_string(),
+            qtype: TYPE_SOA,
+            qclass: CLASS_IN,
+        });
+        let mut rdata = vec![];
+        encode_name("web.magicloud.lan.", &mut rdata).unwrap();
+        message.authority.push(Rr {
+            name: "www.magicloud.lan".to_string(),
+            rtype: TYPE_CNAME,
+            class: CLASS_IN,
+            ttl: 300,
+            rdata,
+        });
+        let wire = message.encode().unwrap();
+        assert_eq!(&wire[..4], [0x12, 0x34, 0x28, 0x00]);
+        let (decoded, last) = Message::decode(&wire).unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(last, 12 + 15 + 4);
+
+        // A compressed answer: www.magicloud.lan CNAME web.<pointer to magicloud.lan>.
+        let mut wire = wire[..12 + 15 + 4].to_vec();
+        wire[7] = 1;
+        wire[9] = 0;
+        wire.extend([3, b'w', b'w', b'w', 0xc0, 12]);
+        wire.extend([0, 5, 0, 1, 0, 0, 1, 44, 0, 6, 3, b'w', b'e', b'b', 0xc0, 12]);
+        let (decoded, _) = Message::decode(&wire).unwrap();
+        assert_eq!(decoded.answers, message.authority);
+    }
+}
@@ -0,0 +1,753 @@
+//! A `Provider` updating a DNS server with RFC 2136 dynamic updates, such as BIND
+//! with `allow-update` or `update-policy`, like the in-tree rfc2136 provider of External-DNS.
+//!
+//! `records` reads the zone with a zone transfer (AXFR). `apply_changes` sends all the
+//! changes as one UPDATE message over TCP, which the server applies entirely or not at all:
+//! - creates add the records;
+//! - updates require the old records to be exactly there (a prerequisite), then replace them;
+//! - deletes remove the records they list, leaving others of the same name and type.
+//!
+//! When the records were changed since they were read, the prerequisites fail, and so does
+//! `apply_changes`: External-DNS reads the records again at its next sync.
+//! Requests are signed with TSIG when a key is given, and then responses must be signed too.
+//!
+//! Targets are as in External-DNS: MX as `preference host`, SRV as
+//! `priority weight port target`, NAPTR as `order preference "flags" "service" "regexp"
+//! replacement`, and TXT as quoted character strings, or a plain text.
+
+pub mod message;
+pub mod tsig;
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+use tracing::{info, instrument};
+
+use crate::{
+    changes::{Changes, Operation},
+    domain_filter::DomainFilter,
+    endpoint::{Endpoint, RecordType},
+    provider::Provider,
+};
+
+use message::{
+    CLASS_ANY, CLASS_IN, CLASS_NONE, Message, OPCODE_QUERY, OPCODE_UPDATE, Question, Reader, Rr,
+    TYPE_A, TYPE_AAAA, TYPE_AXFR, TYPE_CNAME, TYPE_MX, TYPE_NAPTR, TYPE_NS, TYPE_PTR, TYPE_SOA,
+    TYPE_SRV, TYPE_TXT, encode_name,
+};
+use tsig::{TsigKey, Verifier};
+
+/// A `Provider` sending dynamic updates to the primary server of a zone.
+#[derive(Debug)]
+pub struct Rfc2136Provider {
+    server: SocketAddr,
+    zone: String,
+    key: Option<TsigKey>,
+    default_ttl: u32,
+    timeout: Duration,
+}
+impl Rfc2136Provider {
+    /// Constructor of `Rfc2136Provider`, for the zone, such as `magicloud.lan`,
+    /// on the server, such as `192.168.0.1:53`.
+    #[must_use]
+    pub fn new(server: SocketAddr, zone: &str) -> Self {
+        Self {
+            server,
+            zone: zone.trim_end_matches('.').to_string(),
+            key: None,
+            default_ttl: 300,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Sign requests with this key.
+    #[must_use]
+    pub fn with_tsig(mut self, key: TsigKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// The TTL of records without one, 300 seconds by default.
+    #[must_use]
+    pub const fn with_default_ttl(mut self, ttl: u32) -> Self {
+        self.default_ttl = ttl;
+        self
+    }
+
+    /// The time allowed for each exchange with the server, 10 seconds by default.
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The UPDATE message of the changes, unsigned.
+    /// # Errors
+    ///
+    /// When an endpoint misses data, or a target is not valid for its type.
+    pub fn update_message(&self, changes: &Changes) -> Result<Message> {
+        let mut message = Message::request(message_id(), OPCODE_UPDATE);
+        message.questions.push(Question {
+            name: self.zone.clone(),
+            qtype: TYPE_SOA,
+            qclass: CLASS_IN,
+        });
+        for operation in changes.operations() {
+            match operation {
+                Operation::Create(ep) => {
+                    message.authority.extend(rrs(&ep, CLASS_IN, self.ttl(&ep))?);
+                }
+                Operation::Update(ft) => {
+                    // The RRset must be exactly the old one, TTL aside.
+                    message.answers.extend(rrs(&ft.from, CLASS_IN, 0)?);
+                    let (name, rtype) = name_type(&ft.from)?;
+                    message.authority.push(Rr {
+                        name,
+                        rtype,
+                        class: CLASS_ANY,
+                        ttl: 0,
+                        rdata: vec![],
+                    });
+                    message
+                        .authority
+                        .extend(rrs(&ft.to, CLASS_IN, self.ttl(&ft.to))?);
+                }
+                Operation::Delete(ep) => message.authority.extend(rrs(&ep, CLASS_NONE, 0)?),
+            }
+        }
+        Ok(message)
+    }
+
+    fn ttl(&self, endpoint: &Endpoint) -> u32 {
+        endpoint
+            .record_ttl
+            .and_then(|t| u32::try_from(t).ok())
+            .filter(|t| *t > 0)
+            .unwrap_or(self.default_ttl)
+    }
+
+    async fn connect(&self) -> Result<TcpStream> {
+        timeout(self.timeout, TcpStream::connect(self.server))
+            .await
+            .map_err(|_| eyre!("Timeout connecting to {}", self.server))?
+            .map_err(|e| eyre!("Cannot connect to {}: {e}", self.server))
+    }
+
+    async fn send(&self, stream: &mut TcpStream, mut message: Message) -> Result<Option<Vec<u8>>> {
+        let mac = match &self.key {
+            Some(key) => Some(key.sign(&mut message, None, false, tsig::now())?),
+            None => None,
+        };
+        let wire = message.encode()?;
+        let mut framed = u16::try_from(wire.len())
+            .map_err(|_| eyre!("DNS message too large: {} bytes", wire.len()))?
+            .to_be_bytes()
+            .to_vec();
+        framed.extend(wire);
+        timeout(self.timeout, stream.write_all(&framed))
+            .await
+            .map_err(|_| eyre!("Timeout writing to {}", self.server))??;
+        Ok(mac)
+    }
+
+    async fn receive(&self, stream: &mut TcpStream) -> Result<Vec<u8>> {
+        let read = async {
+            let len = stream.read_u16().await?;
+            let mut buf = vec![0; usize::from(len)];
+            stream.read_exact(&mut buf).await?;
+            Ok::<_, std::io::Error>(buf)
+        };
+        Ok(timeout(self.timeout, read)
+            .await
+            .map_err(|_| eyre!("Timeout reading from {}", self.server))??)
+    }
+}
+
+// Message IDs only need to differ between exchanges on the same connection.
+fn message_id() -> u16 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    u16::try_from((nanos ^ (nanos >> 16)) & 0xffff).unwrap_or_default()
+}
+
+fn check_rcode(message: &Message) -> Result<()> {
+    let name = match message.rcode() {
+        0 => return Ok(()),
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET, records changed since they were read",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        _ => "unknown error",
+    };
+    Err(eyre!("DNS server answered {} ({name})", message.rcode()))
+}
+
+fn name_type(endpoint: &Endpoint) -> Result<(String, u16)> {
+    let name = endpoint
+        .dns_name
+        .clone()
+        .ok_or_else(|| eyre!("No dnsName in {endpoint:?}"))?;
+    let rtype = match endpoint.record_type {
+        Some(RecordType::A) => TYPE_A,
+        Some(RecordType::AAAA) => TYPE_AAAA,
+        Some(RecordType::CNAME) => TYPE_CNAME,
+        Some(RecordType::TXT) => TYPE_TXT,
+        Some(RecordType::SRV) => TYPE_SRV,
+        Some(RecordType::NS) => TYPE_NS,
+        Some(RecordType::PTR) => TYPE_PTR,
+        Some(RecordType::MX) => TYPE_MX,
+        Some(RecordType::NAPTR) => TYPE_NAPTR,
+        None => return Err(eyre!("No recordType in {endpoint:?}")),
+    };
+    Ok((name, rtype))
+}
+
+// The records of an endpoint, one per target. The class `NONE` deletes them.
+fn rrs(endpoint: &Endpoint, class: u16, ttl: u32) -> Result<Vec<Rr>> {
+    let (name, rtype) = name_type(endpoint)?;
+    endpoint
+        .targets
+        .iter()
+        .flatten()
+        .map(|target| {
+            Ok(Rr {
+                name: name.clone(),
+                rtype,
+                class,
+                ttl,
+                rdata: rdata(rtype, target)?,
+            })
+        })
+        .collect()
+}
+
+/// The RDATA of a target.
+/// # Errors
+///
+/// When the target is not valid for the type.
+pub fn rdata(rtype: u16, target: &str) -> Result<Vec<u8>> {
+    let invalid = || eyre!("Invalid target for type {rtype}: {target}");
+    let fields: Vec<&str> = target.split_whitespace().collect();
+    let mut out = vec![];
+    match (rtype, &fields[..]) {
+        (TYPE_A | TYPE_AAAA, _) => {
+            match (rtype, target.parse::<IpAddr>().map_err(|_| invalid())?) {
+                (TYPE_A, IpAddr::V4(ip)) => out.extend(ip.octets()),
+                (TYPE_AAAA, IpAddr::V6(ip)) => out.extend(ip.octets()),
+                _ => return Err(invalid()),
+            }
+        }
+        (TYPE_CNAME | TYPE_NS | TYPE_PTR, [host]) => encode_name(host, &mut out)?,
+        (TYPE_MX, [preference, host]) => {
+            out.extend(
+                preference
+                    .parse::<u16>()
+                    .map_err(|_| invalid())?
+                    .to_be_bytes(),
+            );
+            encode_name(host, &mut out)?;
+        }
+        (TYPE_SRV, [priority, weight, port, host]) => {
+            for n in [priority, weight, port] {
+                out.extend(n.parse::<u16>().map_err(|_| invalid())?.to_be_bytes());
+            }
+            encode_name(host, &mut out)?;
+        }
+        (TYPE_TXT, _) => {
+            let strings = if target.starts_with('"') {
+                character_strings(target).ok_or_else(invalid)?
+            } else {
+                target.as_bytes().chunks(255).map(<[u8]>::to_vec).collect()
+            };
+            for s in strings {
+                push_string(&s, &mut out)?;
+            }
+        }
+        (TYPE_NAPTR, [order, preference, ..]) => {
+            for n in [order, preference] {
+                out.extend(n.parse::<u16>().map_err(|_| invalid())?.to_be_bytes());
+            }
+            // The quoted flags, service and regexp, then the replacement.
+            let rest = target
+                .trim()
+                .splitn(3, char::is_whitespace)
+                .nth(2)
+                .ok_or_else(invalid)?;
+            let (strings, replacement) =
+                rest.rsplit_once(char::is_whitespace).ok_or_else(invalid)?;
+            let strings = character_strings(strings)
+                .filter(|s| s.len() == 3)
+                .ok_or_else(invalid)?;
+            for s in strings {
+                push_string(&s, &mut out)?;
+            }
+            encode_name(replacement, &mut out)?;
+        }
+        _ => return Err(invalid()),
+    }
+    Ok(out)
+}
+
+fn push_string(s: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    out.push(u8::try_from(s.len()).map_err(|_| eyre!("Character string over 255 bytes"))?);
+    out.extend(s);
+    Ok(())
+}
+
+// Quoted character strings separated by spaces, with `\"` and `\\` escapes.
+fn character_strings(text: &str) -> Option<Vec<Vec<u8>>> {
+    let mut ret = vec![];
+    let mut chars = text.trim().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => s.push(chars.next()?),
+                        c => s.push(c),
+                    }
+                }
+                ret.push(s.into_bytes());
+            }
+            c if c.is_whitespace() => {}
+            _ => return None,
+        }
+    }
+    Some(ret)
+}
+
+fn quote(s: &[u8]) -> String {
+    let s = String::from_utf8_lossy(s);
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The type and External-DNS target of a record, `None` for other types.
+#[must_use]
+pub fn target(rr: &Rr) -> Option<(RecordType, String)> {
+    let mut reader = Reader {
+        data: &rr.rdata,
+        at: 0,
+    };
+    let strings = |reader: &mut Reader<'_>, n: Option<usize>| -> Option<Vec<String>> {
+        let mut ret = vec![];
+        while reader.at < reader.data.len() && n.is_none_or(|n| ret.len() < n) {
+            let len = usize::from(*reader.bytes(1).ok()?.first()?);
+            ret.push(quote(reader.bytes(len).ok()?));
+        }
+        Some(ret)
+    };
+    let ret = match rr.rtype {
+        TYPE_A => (
+            RecordType::A,
+            IpAddr::from(<[u8; 4]>::try_from(&rr.rdata[..]).ok()?).to_string(),
+        ),
+        TYPE_AAAA => (
+            RecordType::AAAA,
+            IpAddr::from(<[u8; 16]>::try_from(&rr.rdata[..]).ok()?).to_string(),
+        ),
+        TYPE_CNAME => (RecordType::CNAME, reader.name().ok()?),
+        TYPE_NS => (RecordType::NS, reader.name().ok()?),
+        TYPE_PTR => (RecordType::PTR, reader.name().ok()?),
+        TYPE_MX => {
+            let preference = reader.u16().ok()?;
+            (
+                RecordType::MX,
+                format!("{preference} {}", reader.name().ok()?),
+            )
+        }
+        TYPE_SRV => {
+            let [priority, weight, port] =
+                [reader.u16().ok()?, reader.u16().ok()?, reader.u16().ok()?];
+            let host = reader.name().ok()?;
+            (
+                RecordType::SRV,
+                format!("{priority} {weight} {port} {host}"),
+            )
+        }
+        TYPE_TXT => (RecordType::TXT, strings(&mut reader, None)?.join(" ")),
+        TYPE_NAPTR => {
+            let [order, preference] = [reader.u16().ok()?, reader.u16().ok()?];
+            let strings = strings(&mut reader, Some(3))?.join(" ");
+            let replacement = reader.name().ok()?;
+            let replacement = if replacement.is_empty() {
+                ".".to_string()
+            } else {
+                replacement
+            };
+            (
+                RecordType::NAPTR,
+                format!("{order} {preference} {strings} {replacement}"),
+            )
+        }
+        _ => return None,
+    };
+    Some(ret)
+}
+
+#[async_trait]
+impl Provider for Rfc2136Provider {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        Ok(DomainFilter::Strings {
+            include: Some(vec![self.zone.clone()]),
+            exclude: None,
+        })
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        let mut request = Message::request(message_id(), OPCODE_QUERY);
+        request.questions.push(Question {
+            name: self.zone.clone(),
+            qtype: TYPE_AXFR,
+            qclass: CLASS_IN,
+        });
+        let mut stream = self.connect().await?;
+        let mac = self.send(&mut stream, request).await?;
+        let mut verifier = self.key.as_ref().map(|key| Verifier::new(key, mac));
+
+        // The transfer starts and ends with the SOA record.
+        let mut rrs = vec![];
+        let mut soas = 0;
+        while soas < 2 {
+            let raw = self.receive(&mut stream).await?;
+            let (message, last) = Message::decode(&raw)?;
+            check_rcode(&message)?;
+            if let Some(verifier) = &mut verifier {
+                verifier.check(&raw, &message, last)?;
+            }
+            if message.answers.is_empty() {
+                return Err(eyre!("Empty zone transfer message"));
+            }
+            for rr in message.answers {
+                if rr.rtype == TYPE_SOA {
+                    soas += 1;
+                } else {
+                    rrs.push(rr);
+                }
+            }
+        }
+        if let Some(verifier) = &verifier {
+            verifier.finish()?;
+        }
+
+        let mut order = vec![];
+        let mut found: BTreeMap<(String, RecordType), Endpoint> = BTreeMap::new();
+        for rr in rrs {
+            let Some((record_type, target)) = target(&rr) else {
+                continue;
+            };
+            let key = (rr.name.clone(), record_type.clone());
+            let ep = found.entry(key.clone()).or_insert_with(|| {
+                order.push(key);
+                Endpoint {
+                    dns_name: Some(rr.name.clone()),
+                    targets: Some(vec![]),
+                    record_type: Some(record_type),
+                    set_identifier: None,
+                    record_ttl: Some(i64::from(rr.ttl)),
+                    labels: None,
+                    provider_specific: None,
+                }
+            });
+            ep.targets.get_or_insert_default().push(target);
+        }
+        Ok(order
+            .into_iter()
+            .filter_map(|key| found.remove(&key))
+            .collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        info!(target: "rfc2136", message = format!("Applying {}", changes.summary()));
+        let message = self.update_message(&changes)?;
+        let mut stream = self.connect().await?;
+        let mac = self.send(&mut stream, message).await?;
+        let raw = self.receive(&mut stream).await?;
+        let (response, last) = Message::decode(&raw)?;
+        check_rcode(&response)?;
+        if let Some(key) = &self.key {
+            Verifier::new(key, mac).check(&raw, &response, last)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::FromTo;
+    use crate::endpoint::ep;
+    use std::sync::{Arc, Mutex, PoisonError};
+    use tokio::net::TcpListener;
+
+    const SECRET: &str = "c2VjcmV0LXNlY3JldC1zZWNyZXQtc2VjcmV0LTAx";
+
+    // A stand-in for a primary server: checks signatures and prerequisites, applies updates,
+    // and transfers the zone in two messages, the second one signing the timers only.
+    #[derive(Debug)]
+    struct StandIn {
+        key: TsigKey,
+        // The SOA record first.
+        records: Mutex<Vec<Rr>>,
+    }
+    impl StandIn {
+        fn answer(&self, raw: &[u8]) -> Vec<Message> {
+            let (request, last) = Message::decode(raw).unwrap();
+            let mut verifier = Verifier::new(&self.key, None);
+            let mut response = Message {
+                id: request.id,
+                flags: 0x8000 | (request.flags & 0x7800),
+                questions: request.questions.clone(),
+                ..Message::default()
+            };
+            if verifier.check(raw, &request, last).is_err() {
+                response.flags |= 9;
+                return vec![response];
+            }
+            let mac = verifier.mac().map(<[u8]>::to_vec);
+            let mut responses = if request.questions[0].qtype == TYPE_AXFR {
+                let records = self.records.lock().unwrap_or_else(PoisonError::into_inner);
+                let (first, second) = records.split_at(records.len() / 2);
+                let mut next = response.clone();
+                response.answers = first.to_vec();
+                next.answers = second.to_vec();
+                next.answers.push(records[0].clone());
+                drop(records);
+                vec![response, next]
+            } else {
+                response.flags |= self.update(&request);
+                vec![response]
+            };
+            let mut prior = mac;
+            for (i, message) in responses.iter_mut().enumerate() {
+                prior = Some(
+                    self.key
+                        .sign(message, prior.as_deref(), i > 0, tsig::now())
+                        .unwrap(),
+                );
+            }
+            responses
+        }
+
+        // The RCODE of an UPDATE.
+        fn update(&self, request: &Message) -> u16 {
+            let mut records = self.records.lock().unwrap_or_else(PoisonError::into_inner);
+            let rrset = |records: &[Rr], name: &str, rtype: u16| {
+                let mut rdata: Vec<Vec<u8>> = records
+                    .iter()
+                    .filter(|r| r.name == name && r.rtype == rtype)
+                    .map(|r| r.rdata.clone())
+                    .collect();
+                rdata.sort();
+                rdata
+            };
+            let mut required: BTreeMap<(String, u16), Vec<Vec<u8>>> = BTreeMap::new();
+            for rr in &request.answers {
+                required
+                    .entry((rr.name.clone(), rr.rtype))
+                    .or_default()
+                    .push(rr.rdata.clone());
+            }
+            for ((name, rtype), mut rdata) in required {
+                rdata.sort();
+                if rrset(&records, &name, rtype) != rdata {
+                    return 8;
+                }
+            }
+            for rr in &request.authority {
+                match rr.class {
+                    CLASS_ANY => records.retain(|r| r.name != rr.name || r.rtype != rr.rtype),
+                    CLASS_NONE => records.retain(|r| {
+                        r.name != rr.name || r.rtype != rr.rtype || r.rdata != rr.rdata
+                    }),
+                    _ => {
+                        if !records.iter().any(|r| {
+                            r.name == rr.name && r.rtype == rr.rtype && r.rdata == rr.rdata
+                        }) {
+                            records.push(rr.clone());
+                        }
+                    }
+                }
+            }
+            drop(records);
+            0
+        }
+    }
+
+    async fn serve(listener: TcpListener, stand_in: Arc<StandIn>) {
+        loop {
+            let Ok((mut stream, _)) = listener.accept().await else {
+                return;
+            };
+            let stand_in = stand_in.clone();
+            tokio::spawn(async move {
+                while let Ok(len) = stream.read_u16().await {
+                    let mut raw = vec![0; usize::from(len)];
+                    if stream.read_exact(&mut raw).await.is_err() {
+                        return;
+                    }
+                    for message in stand_in.answer(&raw) {
+                        let wire = message.encode().unwrap();
+                        let mut framed = u16::try_from(wire.len()).unwrap().to_be_bytes().to_vec();
+                        framed.extend(wire);
+                        if stream.write_all(&framed).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    fn rr(name: &str, rtype: u16, target: &str) -> Rr {
+        Rr {
+            name: name.to_string(),
+            rtype,
+            class: CLASS_IN,
+            ttl: 3600,
+            rdata: rdata(rtype, target).unwrap(),
+        }
+    }
+
+    #[test]
+    fn targets() {
+        for (rtype, text) in [
+            (TYPE_A, "192.168.0.1"),
+            (TYPE_AAAA, "fd00::1"),
+            (TYPE_CNAME, "web.magicloud.lan"),
+            (TYPE_MX, "10 mail.magicloud.lan"),
+            (TYPE_SRV, "0 5 143 mail.magicloud.lan"),
+            (TYPE_TXT, r#""v=spf1 mx -all" "say \"hi\"""#),
+            (
+                TYPE_NAPTR,
+                r#"100 10 "U" "E2U+sip" "!^.*$!sip:info@magicloud.lan!" ."#,
+            ),
+        ] {
+            let rr = rr("magicloud.lan", rtype, text);
+            assert_eq!(target(&rr).unwrap().1, text);
+        }
+        assert_eq!(rdata(TYPE_TXT, "plain text").unwrap(), b"\x0aplain text");
+        assert!(rdata(TYPE_A, "fd00::1").is_err());
+        assert!(rdata(TYPE_MX, "mail.magicloud.lan").is_err());
+    }
+
+    #[tokio::test]
+    async fn it_works() {
+        let key = TsigKey::new("externaldns-key", "hmac-sha256", SECRET).unwrap();
+        let soa = Rr {
+            name: "magicloud.lan".to_string(),
+            rtype: TYPE_SOA,
+            class: CLASS_IN,
+            ttl: 3600,
+            rdata: {
+                let mut rdata = vec![];
+                encode_name("ns1.magicloud.lan", &mut rdata).unwrap();
+                encode_name("hostmaster.magicloud.lan", &mut rdata).unwrap();
+                rdata.extend([0; 20]);
+                rdata
+            },
+        };
+        let stand_in = Arc::new(StandIn {
+            key: key.clone(),
+            records: Mutex::new(vec![
+                soa,
+                rr("magicloud.lan", TYPE_NS, "ns1.magicloud.lan"),
+                rr("ns1.magicloud.lan", TYPE_A, "192.168.0.1"),
+            ]),
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, stand_in.clone()));
+        let provider = Rfc2136Provider::new(server, "magicloud.lan").with_tsig(key);
+
+        let ns = ep("magicloud.lan", RecordType::NS, &["ns1.magicloud.lan"]).with_ttl(3600);
+        let ns1 = ep("ns1.magicloud.lan", RecordType::A, &["192.168.0.1"]).with_ttl(3600);
+        assert_eq!(provider.records().await.unwrap(), vec![ns, ns1.clone()]);
+
+        let www = ep(
+            "www.magicloud.lan",
+            RecordType::A,
+            &["192.168.0.102", "192.168.0.103"],
+        )
+        .with_ttl(3600);
+        let txt = ep(
+            "www.magicloud.lan",
+            RecordType::TXT,
+            &["\"heritage=external-dns\""],
+        )
+        .with_ttl(3600);
+        provider
+            .apply_changes(Changes {
+                create: vec![www.clone(), txt.clone()],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        let records = provider.records().await.unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records.contains(&www) && records.contains(&txt));
+        assert_eq!(
+            records.iter().find(|r| **r == www).unwrap().targets,
+            www.targets
+        );
+
+        let moved = ep("www.magicloud.lan", RecordType::A, &["192.168.0.104"]).with_ttl(3600);
+        let update = |from: &Endpoint| Changes {
+            update: vec![FromTo {
+                from: from.clone(),
+                to: moved.clone(),
+            }],
+            delete: vec![txt.clone()],
+            ..Changes::default()
+        };
+        provider.apply_changes(update(&www)).await.unwrap();
+        // The old records are gone: the prerequisite fails, and nothing is applied.
+        let error = provider.apply_changes(update(&www)).await.unwrap_err();
+        assert!(format!("{error}").contains("NXRRSET"), "{error}");
+        let records = provider.records().await.unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records.iter().find(|r| **r == moved).unwrap().targets,
+            moved.targets
+        );
+
+        let wrong = Rfc2136Provider::new(server, "magicloud.lan")
+            .with_tsig(TsigKey::new("externaldns-key", "hmac-sha256", "b3RoZXI=").unwrap());
+        assert!(wrong.records().await.is_err());
+        let unsigned = Rfc2136Provider::new(server, "magicloud.lan");
+        assert!(
+            unsigned
+                .apply_changes(Changes {
+                    delete: vec![ns1],
+                    ..Changes::default()
+                })
+                .await
+                .is_err()
+        );
+        assert_eq!(stand_in.records.lock().unwrap().len(), 4);
+    }
+}
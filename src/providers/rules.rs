@@ -0,0 +1,210 @@
+//! Records kept as one rule per name and target, as Pi-hole and `AdGuard` Home do.
+//!
+//! Such backends only know A, AAAA and CNAME records, with no TTL: an endpoint is
+//! the rules of its name and type, and changes become rules to remove and to add.
+
+use std::{collections::BTreeMap, net::IpAddr};
+
+use eyre::{Result, eyre};
+use tracing::warn;
+
+use crate::{
+    changes::{Changes, Operation},
+    endpoint::{Endpoint, RecordType},
+};
+
+/// One name resolving to one target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub name: String,
+    pub record_type: RecordType,
+    pub target: String,
+}
+impl Rule {
+    /// The rule of a name to an address or, when the answer is not one, to a CNAME target.
+    #[must_use]
+    pub fn of(name: &str, answer: &str) -> Self {
+        Self {
+            name: name.trim_end_matches('.').to_string(),
+            record_type: match answer.parse::<IpAddr>() {
+                Ok(IpAddr::V4(_)) => RecordType::A,
+                Ok(IpAddr::V6(_)) => RecordType::AAAA,
+                Err(_) => RecordType::CNAME,
+            },
+            target: answer.trim_end_matches('.').to_string(),
+        }
+    }
+}
+
+/// A change to the rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Remove(Rule),
+    Add(Rule),
+}
+
+pub const fn supported(endpoint: &Endpoint) -> bool {
+    matches!(
+        endpoint.record_type,
+        Some(RecordType::A | RecordType::AAAA | RecordType::CNAME)
+    )
+}
+
+/// Drop the endpoints of other record types, with a warning.
+pub fn adjust(target: &str, endpoints: Vec<Endpoint>) -> Vec<Endpoint> {
+    endpoints
+        .into_iter()
+        .filter(|ep| {
+            let ok = supported(ep);
+            if !ok {
+                warn!(target: "rules", message = format!("{target}: unsupported record type: {ep:?}"));
+            }
+            ok
+        })
+        .collect()
+}
+
+/// The endpoints of the rules, one per name and type, sorted.
+pub fn endpoints(rules: impl IntoIterator<Item = Rule>) -> Vec<Endpoint> {
+    let mut grouped: BTreeMap<(String, RecordType), Vec<String>> = BTreeMap::new();
+    for rule in rules {
+        let targets = grouped.entry((rule.name, rule.record_type)).or_default();
+        if !targets.contains(&rule.target) {
+            targets.push(rule.target);
+        }
+    }
+    grouped
+        .into_iter()
+        .map(|((name, record_type), targets)| Endpoint {
+            dns_name: Some(name),
+            targets: Some(targets),
+            record_type: Some(record_type),
+            set_identifier: None,
+            record_ttl: None,
+            labels: None,
+            provider_specific: None,
+        })
+        .collect()
+}
+
+/// The rules of an endpoint, one per target.
+/// # Errors
+///
+/// When the endpoint has no name, its type is not supported, or a target does not fit it.
+pub fn rules(endpoint: &Endpoint) -> Result<Vec<Rule>> {
+    let name = endpoint
+        .dns_name
+        .as_deref()
+        .ok_or_else(|| eyre!("No dnsName in {endpoint:?}"))?;
+    if !supported(endpoint) {
+        return Err(eyre!(
+            "Only A, AAAA and CNAME records can be written: {endpoint:?}"
+        ));
+    }
+    endpoint
+        .targets
+        .iter()
+        .flatten()
+        .map(|target| {
+            let rule = Rule::of(name, target);
+            if Some(&rule.record_type) == endpoint.record_type.as_ref() {
+                Ok(rule)
+            } else {
+                Err(eyre!("Invalid target {target} for {endpoint:?}"))
+            }
+        })
+        .collect()
+}
+
+/// The steps applying the changes, in the order of `Changes::operations`. Updates only
+/// touch the targets that differ.
+/// # Errors
+///
+/// When an endpoint cannot be turned into rules.
+pub fn steps(changes: &Changes) -> Result<Vec<Step>> {
+    let mut ret = vec![];
+    for operation in changes.operations() {
+        match operation {
+            Operation::Create(ep) => ret.extend(rules(&ep)?.into_iter().map(Step::Add)),
+            Operation::Delete(ep) => ret.extend(rules(&ep)?.into_iter().map(Step::Remove)),
+            Operation::Update(ft) => {
+                let (from, to) = (rules(&ft.from)?, rules(&ft.to)?);
+                ret.extend(
+                    from.iter()
+                        .filter(|r| !to.contains(r))
+                        .cloned()
+                        .map(Step::Remove),
+                );
+                ret.extend(to.into_iter().filter(|r| !from.contains(r)).map(Step::Add));
+            }
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::FromTo;
+    use crate::endpoint::ep;
+
+    #[test]
+    fn it_works() {
+        let listed = [
+            "192.168.0.102",
+            "fd00::102",
+            "192.168.0.103",
+            "nextcloud.magicloud.lan.",
+        ]
+        .map(|answer| Rule::of("cloud.magicloud.lan", answer));
+        assert_eq!(
+            endpoints(listed),
+            vec![
+                ep(
+                    "cloud.magicloud.lan",
+                    RecordType::A,
+                    &["192.168.0.102", "192.168.0.103"]
+                ),
+                ep("cloud.magicloud.lan", RecordType::AAAA, &["fd00::102"]),
+                ep(
+                    "cloud.magicloud.lan",
+                    RecordType::CNAME,
+                    &["nextcloud.magicloud.lan"]
+                ),
+            ]
+        );
+
+        let changes = Changes {
+            update: vec![FromTo {
+                from: ep(
+                    "nas.magicloud.lan",
+                    RecordType::A,
+                    &["192.168.0.10", "192.168.0.11"],
+                ),
+                to: ep(
+                    "nas.magicloud.lan",
+                    RecordType::A,
+                    &["192.168.0.11", "192.168.0.12"],
+                ),
+            }],
+            ..Changes::default()
+        };
+        assert_eq!(
+            steps(&changes).unwrap(),
+            vec![
+                Step::Remove(Rule::of("nas.magicloud.lan", "192.168.0.10")),
+                Step::Add(Rule::of("nas.magicloud.lan", "192.168.0.12")),
+            ]
+        );
+
+        let wrong = ep("nas.magicloud.lan", RecordType::AAAA, &["192.168.0.10"]);
+        assert!(rules(&wrong).is_err());
+        let txt = ep(
+            "nas.magicloud.lan",
+            RecordType::TXT,
+            &["heritage=external-dns"],
+        );
+        assert!(rules(&txt).is_err());
+        assert!(adjust("test", vec![txt]).is_empty());
+    }
+}
@@ -0,0 +1,734 @@
+//! A `Provider` writing records into an RFC 1035 master zone file, as served by BIND, Knot,
+//! NSD or the `CoreDNS` `file` plugin.
+//!
+//! # Zone file layout
+//!
+//! Each record managed by the provider is written as a block: a comment holding the endpoint
+//! as JSON, followed by its resource records, with absolute names and class `IN`.
+//!
+//! ```text
+//! ; external-dns {"dnsName":"www.magicloud.lan","targets":["192.168.0.102"],"recordType":"A"}
+//! www.magicloud.lan. 300 IN A 192.168.0.102
+//! ```
+//!
+//! With [`Managed::Section`], new records go between `; BEGIN external-dns` and
+//! `; END external-dns` marker lines, and every record of that section is managed.
+//! With [`Managed::Owned`], new blocks are appended to the file, and only the records
+//! written as blocks are managed, wherever they are in the file. A block ends with the last
+//! record of its endpoint, one per target, even when other records follow without a blank line.
+//!
+//! All other records (see `parser`) are listed by `records`, but are read-only: changes touching
+//! them fail. Everything outside the managed blocks and section is kept verbatim,
+//! except the SOA serial, which is incremented on each `apply_changes`.
+//!
+//! TXT targets are written as they are when they start with a double quote, and quoted otherwise.
+
+pub mod parser;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    path::PathBuf,
+};
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    changes::Changes,
+    domain_filter::DomainFilter,
+    endpoint::{Endpoint, RecordKey, RecordType},
+    file_store::{FileStore, unblock},
+    provider::Provider,
+};
+
+use parser::{Context, ZoneRecord};
+
+const BLOCK_PREFIX: &str = "; external-dns ";
+const SECTION_BEGIN: &str = "; BEGIN external-dns";
+const SECTION_END: &str = "; END external-dns";
+
+/// Which records of the zone file the provider manages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Managed {
+    /// The records between the section markers.
+    #[default]
+    Section,
+    /// The records the provider wrote, wherever they are.
+    Owned,
+}
+
+/// A `Provider` keeping records in a master zone file.
+#[derive(Debug, Clone)]
+pub struct ZoneFileProvider {
+    zone: String,
+    store: FileStore,
+    managed: Managed,
+    default_ttl: Option<i64>,
+}
+impl ZoneFileProvider {
+    /// Constructor of `ZoneFileProvider`, for the zone, such as `magicloud.lan`,
+    /// the file is the master file of.
+    #[must_use]
+    pub fn new(zone: &str, zone_filename: impl Into<PathBuf>) -> Self {
+        Self {
+            zone: zone.trim_end_matches('.').to_string(),
+            store: FileStore::new(zone_filename),
+            managed: Managed::default(),
+            default_ttl: None,
+        }
+    }
+
+    /// Which records are managed, the section by default.
+    #[must_use]
+    pub const fn with_managed(mut self, managed: Managed) -> Self {
+        self.managed = managed;
+        self
+    }
+
+    /// The TTL written for records without one. Without it, such records get the `$TTL`
+    /// of the zone.
+    #[must_use]
+    pub const fn with_default_ttl(mut self, ttl: i64) -> Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    /// Keep that many former versions of the zone file, see [`FileStore`].
+    #[must_use]
+    pub fn with_backups(mut self, backups: usize) -> Self {
+        self.store = self.store.with_backups(backups);
+        self
+    }
+
+    /// The resource records of one endpoint, one per line.
+    /// # Errors
+    ///
+    /// When the endpoint misses data, or a target is not as External-DNS writes it.
+    pub fn resource_records(&self, endpoint: &Endpoint) -> Result<String> {
+        let dns_name = endpoint
+            .dns_name
+            .as_deref()
+            .ok_or_else(|| eyre!("No dnsName in {endpoint:?}"))?;
+        let record_type = endpoint
+            .record_type
+            .as_ref()
+            .ok_or_else(|| eyre!("No recordType in {endpoint:?}"))?;
+        let targets = endpoint
+            .targets
+            .as_deref()
+            .filter(|t| !t.is_empty())
+            .ok_or_else(|| eyre!("No targets in {endpoint:?}"))?;
+        let ttl = endpoint
+            .record_ttl
+            .filter(|t| *t > 0)
+            .or(self.default_ttl)
+            .map(|t| format!(" {t}"))
+            .unwrap_or_default();
+        let fqdn = |name: &str| format!("{}.", name.trim_end_matches('.'));
+        let mut out = String::new();
+        for target in targets {
+            let rdata = match record_type {
+                RecordType::A | RecordType::AAAA | RecordType::NAPTR => target.clone(),
+                RecordType::CNAME | RecordType::NS | RecordType::PTR => fqdn(target),
+                RecordType::TXT if target.starts_with('"') => target.clone(),
+                RecordType::TXT => quote(target),
+                RecordType::MX => {
+                    let (preference, host) = target
+                        .split_once(' ')
+                        .ok_or_else(|| eyre!("MX target not as `preference host`: {target}"))?;
+                    format!("{preference} {}", fqdn(host))
+                }
+                RecordType::SRV => {
+                    let [priority, weight, port, host] = target
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .try_into()
+                        .map_err(|_| {
+                            eyre!("SRV target not as `priority weight port target`: {target}")
+                        })?;
+                    format!("{priority} {weight} {port} {}", fqdn(host))
+                }
+            };
+            let _ = writeln!(out, "{}{ttl} IN {record_type:?} {rdata}", fqdn(dns_name));
+        }
+        Ok(out)
+    }
+
+    /// The block of one endpoint: the JSON comment and the resource records.
+    /// # Errors
+    ///
+    /// When the endpoint cannot be written.
+    pub fn block(&self, endpoint: &Endpoint) -> Result<String> {
+        Ok(format!(
+            "{BLOCK_PREFIX}{}\n{}",
+            serde_json::to_string(endpoint)?,
+            self.resource_records(endpoint)?
+        ))
+    }
+
+    fn read(&self) -> Result<Zone> {
+        Zone::parse(&self.store.read()?, &self.zone)
+    }
+
+    fn render(&self, zone: &Zone) -> Result<String> {
+        let mut out = String::new();
+        for item in &zone.items {
+            match item {
+                Item::Text(text) => out.push_str(text),
+                Item::Block(ep) => out.push_str(&self.block(ep)?),
+                Item::Section(eps) => {
+                    out.push_str(SECTION_BEGIN);
+                    out.push('\n');
+                    let blocks = eps
+                        .iter()
+                        .map(|ep| self.block(ep))
+                        .collect::<Result<Vec<_>>>()?;
+                    out.push_str(&blocks.join("\n"));
+                    out.push_str(SECTION_END);
+                    out.push('\n');
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn apply(&self, mut zone: Zone, changes: Changes) -> Result<String> {
+        let read_only = zone
+            .read_only
+            .iter()
+            .map(RecordKey::of)
+            .collect::<Result<BTreeSet<_>>>()?;
+        let touched = changes
+            .create
+            .iter()
+            .chain(changes.update.iter().flat_map(|ft| [&ft.from, &ft.to]))
+            .chain(&changes.delete);
+        for ep in touched {
+            if read_only.contains(&RecordKey::of(ep)?) {
+                return Err(eyre!("Record not managed by the provider: {ep:?}"));
+            }
+        }
+
+        let managed = zone
+            .managed()
+            .map(RecordKey::of)
+            .collect::<Result<BTreeSet<_>>>()?;
+        let mut replaced = BTreeMap::new();
+        for ep in changes.delete {
+            replaced.insert(RecordKey::of(&ep)?, None);
+        }
+        for ft in changes.update {
+            let (from, to) = (RecordKey::of(&ft.from)?, RecordKey::of(&ft.to)?);
+            if from != to && managed.contains(&to) && !replaced.contains_key(&to) {
+                return Err(eyre!("Record already exists: {:?}", ft.to));
+            }
+            replaced.insert(from, None);
+            replaced.insert(to, Some(ft.to));
+        }
+        // Updates stay where the record was, creates go where new records go.
+        let mut created = Vec::new();
+        for ep in changes.create {
+            let key = RecordKey::of(&ep)?;
+            match replaced.get_mut(&key) {
+                Some(new) => *new = Some(ep),
+                None if managed.contains(&key) => {
+                    return Err(eyre!("Record already exists: {ep:?}"));
+                }
+                None => created.push(ep),
+            }
+        }
+        let mut replace = |ep: Endpoint| -> Result<Option<Endpoint>> {
+            Ok(replaced.remove(&RecordKey::of(&ep)?).unwrap_or(Some(ep)))
+        };
+        let mut items = Vec::new();
+        for item in std::mem::take(&mut zone.items) {
+            match item {
+                Item::Block(ep) => {
+                    if let Some(ep) = replace(ep)? {
+                        items.push(Item::Block(ep));
+                    } else if matches!(items.last(), Some(Item::Text(t)) if t.trim().is_empty()) {
+                        // Drop the blank line before the block too.
+                        items.pop();
+                    }
+                }
+                Item::Section(eps) => {
+                    let eps = eps
+                        .into_iter()
+                        .map(&mut replace)
+                        .filter_map(Result::transpose)
+                        .collect::<Result<_>>()?;
+                    items.push(Item::Section(eps));
+                }
+                text @ Item::Text(_) => items.push(text),
+            }
+        }
+        // Updated records not found go with the created ones.
+        created.extend(replaced.into_values().flatten());
+        zone.items = items;
+        zone.add(created, self.managed);
+        zone.bump_serial();
+        self.render(&zone)
+    }
+}
+
+/// The content of the zone file.
+#[derive(Debug, Default)]
+struct Zone {
+    items: Vec<Item>,
+    /// The records outside the managed blocks and section.
+    read_only: Vec<Endpoint>,
+}
+
+#[derive(Debug)]
+enum Item {
+    /// One entry of the file, kept verbatim.
+    Text(String),
+    /// A block written by the provider.
+    Block(Endpoint),
+    /// The managed section.
+    Section(Vec<Endpoint>),
+}
+
+impl Zone {
+    fn parse(content: &str, zone: &str) -> Result<Self> {
+        let mut ret = Self::default();
+        let mut reading = Context::new(zone);
+        let mut read_only: Vec<ZoneRecord> = vec![];
+        let mut section: Option<(Vec<Endpoint>, Vec<ZoneRecord>)> = None;
+        // The name and type of the block being read, and how many of its records are left.
+        let mut block: Option<(String, String, usize)> = None;
+        for entry in parser::entries(content) {
+            let trimmed = entry.trim();
+            // Every entry is read, for the origin, TTL and owner of the next ones.
+            let record = reading.read(entry)?;
+            if trimmed == SECTION_BEGIN {
+                section = Some((vec![], vec![]));
+                block = None;
+                continue;
+            }
+            if trimmed == SECTION_END {
+                if let Some((mut eps, records)) = section.take() {
+                    eps.extend(parser::endpoints(&records));
+                    ret.items.push(Item::Section(eps));
+                }
+                block = None;
+                continue;
+            }
+            if let Some(ep) = trimmed
+                .strip_prefix(BLOCK_PREFIX.trim_end())
+                .and_then(|json| serde_json::from_str::<Endpoint>(json.trim()).ok())
+            {
+                block = Some((
+                    ep.dns_name
+                        .as_deref()
+                        .unwrap_or_default()
+                        .trim_end_matches('.')
+                        .to_string(),
+                    ep.record_type
+                        .as_ref()
+                        .map(|t| format!("{t:?}"))
+                        .unwrap_or_default(),
+                    ep.targets.as_ref().map_or(0, Vec::len),
+                ));
+                match &mut section {
+                    Some((eps, _)) => eps.push(ep),
+                    None => ret.items.push(Item::Block(ep)),
+                }
+                continue;
+            }
+            // The records of a block follow its comment, one per target.
+            if let (Some((name, record_type, left)), Some(record)) = (&mut block, &record)
+                && *left > 0
+                && record.name.eq_ignore_ascii_case(name)
+                && record.record_type == *record_type
+            {
+                *left -= 1;
+                continue;
+            }
+            block = None;
+            match (&mut section, record) {
+                (Some((_, records)), Some(record)) => records.push(record),
+                // Comments and blank lines within the section are rewritten.
+                (Some(_), None) => {}
+                (None, record) => {
+                    read_only.extend(record);
+                    ret.items.push(Item::Text(entry.to_string()));
+                }
+            }
+        }
+        if section.is_some() {
+            return Err(eyre!("No `{SECTION_END}` after `{SECTION_BEGIN}`"));
+        }
+        ret.read_only = parser::endpoints(&read_only);
+        Ok(ret)
+    }
+
+    fn managed(&self) -> impl Iterator<Item = &Endpoint> {
+        self.items.iter().flat_map(|item| match item {
+            Item::Block(ep) => std::slice::from_ref(ep),
+            Item::Section(eps) => eps.as_slice(),
+            Item::Text(_) => &[],
+        })
+    }
+
+    // Add new records, to the section or as blocks at the end.
+    fn add(&mut self, endpoints: Vec<Endpoint>, managed: Managed) {
+        if endpoints.is_empty() {
+            return;
+        }
+        if !self
+            .items
+            .last()
+            .is_none_or(|i| matches!(i, Item::Text(t) if t.trim().is_empty()))
+        {
+            self.items.push(Item::Text("\n".to_string()));
+        }
+        match managed {
+            Managed::Section => {
+                if let Some(Item::Section(eps)) = self
+                    .items
+                    .iter_mut()
+                    .find(|i| matches!(i, Item::Section(_)))
+                {
+                    eps.extend(endpoints);
+                } else {
+                    self.items.push(Item::Section(endpoints));
+                }
+            }
+            Managed::Owned => {
+                for (i, ep) in endpoints.into_iter().enumerate() {
+                    if i > 0 {
+                        self.items.push(Item::Text("\n".to_string()));
+                    }
+                    self.items.push(Item::Block(ep));
+                }
+            }
+        }
+    }
+
+    // Increment the serial of the first SOA record, if any.
+    fn bump_serial(&mut self) {
+        let mut context = Context::new("");
+        for item in &mut self.items {
+            let Item::Text(text) = item else { continue };
+            let is_soa = context
+                .read(text)
+                .ok()
+                .flatten()
+                .is_some_and(|r| r.record_type == "SOA");
+            if !is_soa {
+                continue;
+            }
+            let tokens = parser::tokens(text);
+            let Some(at) = tokens
+                .iter()
+                .position(|(_, t)| t.eq_ignore_ascii_case("SOA"))
+            else {
+                continue;
+            };
+            let Some(&(offset, serial)) = tokens.get(at + 3) else {
+                continue;
+            };
+            let Ok(number) = serial.parse::<u32>() else {
+                warn!(target: "zonefile", message = format!("Invalid SOA serial: {serial}"));
+                return;
+            };
+            // The whole token, keeping its zero padding.
+            let bumped = format!("{:0width$}", number.wrapping_add(1), width = serial.len());
+            text.replace_range(offset..offset + serial.len(), &bumped);
+            return;
+        }
+        warn!(target: "zonefile", message = "No SOA record to bump the serial of");
+    }
+}
+
+// A TXT target as character strings of at most 255 bytes.
+fn quote(text: &str) -> String {
+    let escaped: Vec<String> = text
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => format!("\\{c}"),
+            _ => c.to_string(),
+        })
+        .collect();
+    let mut strings = vec![String::new()];
+    for c in escaped {
+        if strings.last().is_some_and(|s| s.len() + c.len() > 255) {
+            strings.push(String::new());
+        }
+        if let Some(s) = strings.last_mut() {
+            s.push_str(&c);
+        }
+    }
+    strings
+        .iter()
+        .map(|s| format!("\"{s}\""))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[async_trait]
+impl Provider for ZoneFileProvider {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        Ok(DomainFilter::Strings {
+            include: Some(vec![self.zone.clone()]),
+            exclude: None,
+        })
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        let this = self.clone();
+        let zone = unblock(move || this.read()).await?;
+        Ok(zone
+            .managed()
+            .cloned()
+            .chain(zone.read_only.iter().cloned())
+            .collect())
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        info!(target: "zonefile", message = format!("Applying {}", changes.summary()));
+        // Under one lock, so that hand edits made meanwhile are kept.
+        let this = self.clone();
+        unblock(move || {
+            this.store
+                .update(|content| this.apply(Zone::parse(content, &this.zone)?, changes))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changes::FromTo;
+    use crate::endpoint::ep;
+
+    const ZONE: &str = "$ORIGIN magicloud.lan.
+$TTL 3600
+@   IN  SOA ns1 hostmaster (
+        2024010101 ; serial
+        3600 900 604800 300 )
+    IN  NS  ns1
+ns1 IN  A   192.168.0.1
+";
+
+    fn temp_zone(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}.zone", std::process::id()));
+        std::fs::write(&path, ZONE).unwrap();
+        path
+    }
+
+    #[test]
+    fn resource_records() {
+        let provider = ZoneFileProvider::new("magicloud.lan", "unused").with_default_ttl(300);
+        let rr = |ep: Endpoint| provider.resource_records(&ep).unwrap();
+        assert_eq!(
+            rr(ep(
+                "www.magicloud.lan",
+                RecordType::CNAME,
+                &["web.magicloud.lan"]
+            )),
+            "www.magicloud.lan. 300 IN CNAME web.magicloud.lan.\n"
+        );
+        assert_eq!(
+            rr(ep(
+                "magicloud.lan",
+                RecordType::MX,
+                &["10 mail.magicloud.lan"]
+            )),
+            "magicloud.lan. 300 IN MX 10 mail.magicloud.lan.\n"
+        );
+        assert_eq!(
+            rr(ep("magicloud.lan", RecordType::TXT, &["say \"hi\""])),
+            "magicloud.lan. 300 IN TXT \"say \\\"hi\\\"\"\n"
+        );
+        let long = "x".repeat(300);
+        assert_eq!(
+            rr(ep("magicloud.lan", RecordType::TXT, &[&long])),
+            format!(
+                "magicloud.lan. 300 IN TXT \"{}\" \"{}\"\n",
+                "x".repeat(255),
+                "x".repeat(45)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn it_works() {
+        let path = temp_zone("zonefile-section");
+        let provider = ZoneFileProvider::new("magicloud.lan", &path);
+        let www = ep("www.magicloud.lan", RecordType::A, &["192.168.0.102"]);
+        let mail = ep("mail.magicloud.lan", RecordType::A, &["192.168.0.103"]);
+        provider
+            .apply_changes(Changes {
+                create: vec![www.clone(), mail.clone()],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        let records = provider.records().await.unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[..2], [www.clone(), mail.clone()]);
+
+        let moved = ep("www.magicloud.lan", RecordType::A, &["192.168.0.104"]);
+        provider
+            .apply_changes(Changes {
+                update: vec![FromTo {
+                    from: www,
+                    to: moved.clone(),
+                }],
+                delete: vec![mail],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        // Hand-written records are read-only.
+        let ns1 = ep("ns1.magicloud.lan", RecordType::A, &["192.168.0.1"]);
+        assert!(
+            provider
+                .apply_changes(Changes {
+                    delete: vec![ns1],
+                    ..Changes::default()
+                })
+                .await
+                .is_err()
+        );
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("zone.lock"));
+        assert_eq!(
+            content,
+            format!(
+                "{}\n{SECTION_BEGIN}\n{}{SECTION_END}\n",
+                ZONE.replace("2024010101", "2024010103"),
+                provider.block(&moved).unwrap()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn owned() {
+        let path = temp_zone("zonefile-owned");
+        let provider = ZoneFileProvider::new("magicloud.lan", &path).with_managed(Managed::Owned);
+        let www = ep("www.magicloud.lan", RecordType::A, &["192.168.0.102"]);
+        let txt = ep("magicloud.lan", RecordType::TXT, &["\"v=spf1 -all\""]);
+        provider
+            .apply_changes(Changes {
+                create: vec![www.clone(), txt.clone()],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        // A hand edit after the blocks.
+        let mut content = std::fs::read_to_string(&path).unwrap();
+        content.push_str("\nftp IN CNAME ns1\n");
+        std::fs::write(&path, &content).unwrap();
+        provider
+            .apply_changes(Changes {
+                delete: vec![www],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("zone.lock"));
+        assert_eq!(
+            content,
+            format!(
+                "{}\n{}\nftp IN CNAME ns1\n",
+                ZONE.replace("2024010101", "2024010103"),
+                provider.block(&txt).unwrap()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn owned_adjacent() {
+        let path = temp_zone("zonefile-adjacent");
+        let provider = ZoneFileProvider::new("magicloud.lan", &path).with_managed(Managed::Owned);
+        let www = ep("www.magicloud.lan", RecordType::A, &["192.168.0.102"]);
+        let txt = ep("magicloud.lan", RecordType::TXT, &["\"v=spf1 -all\""]);
+        provider
+            .apply_changes(Changes {
+                create: vec![www.clone(), txt.clone()],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        // A hand edit right after the last block, without a blank line.
+        let mut content = std::fs::read_to_string(&path).unwrap();
+        content.push_str("ftp IN CNAME ns1\n");
+        std::fs::write(&path, &content).unwrap();
+        let ftp = Endpoint {
+            record_ttl: Some(3600),
+            ..ep(
+                "ftp.magicloud.lan",
+                RecordType::CNAME,
+                &["ns1.magicloud.lan"],
+            )
+        };
+        assert!(provider.records().await.unwrap().contains(&ftp));
+
+        // The record exists already.
+        let mut again = www.clone();
+        again.targets = Some(vec!["192.168.0.103".to_string()]);
+        assert!(
+            provider
+                .apply_changes(Changes {
+                    create: vec![again],
+                    ..Changes::default()
+                })
+                .await
+                .is_err()
+        );
+
+        provider
+            .apply_changes(Changes {
+                delete: vec![txt],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("zone.lock"));
+        assert_eq!(
+            content,
+            format!(
+                "{}\n{}ftp IN CNAME ns1\n",
+                ZONE.replace("2024010101", "2024010103"),
+                provider.block(&www).unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn zero_padded_serial() {
+        for (serial, bumped) in [("0099", "0100"), ("007", "008"), ("99", "100")] {
+            let mut zone =
+                Zone::parse(&ZONE.replace("2024010101", serial), "magicloud.lan").unwrap();
+            zone.bump_serial();
+            let text: String = zone
+                .items
+                .iter()
+                .filter_map(|item| match item {
+                    Item::Text(text) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect();
+            assert!(
+                text.contains(&format!("\n        {bumped} ; serial\n")),
+                "{serial}: {text}"
+            );
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! Parser of RFC 1035 master files, for the records outside the managed part of a zone.
+
+use std::collections::BTreeMap;
+
+use eyre::{Result, eyre};
+
+use crate::endpoint::{Endpoint, RecordType};
+
+/// One resource record, with absolute names without the trailing dot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneRecord {
+    pub name: String,
+    pub ttl: Option<i64>,
+    /// The type as written, upper-cased.
+    pub record_type: String,
+    /// The RDATA fields, character strings keeping their quotes.
+    pub rdata: Vec<String>,
+    /// The `$ORIGIN` relative names in the RDATA are relative to.
+    pub origin: String,
+}
+impl ZoneRecord {
+    /// The type and the External-DNS target of the record,
+    /// `None` for types `RecordType` does not have, such as SOA.
+    #[must_use]
+    pub fn target(&self) -> Option<(RecordType, String)> {
+        let name = |s: &String| absolute(s, &self.origin);
+        let ret = match (self.record_type.as_str(), &self.rdata[..]) {
+            ("A", [ip]) => (RecordType::A, ip.clone()),
+            ("AAAA", [ip]) => (RecordType::AAAA, ip.clone()),
+            ("CNAME", [host]) => (RecordType::CNAME, name(host)),
+            ("NS", [host]) => (RecordType::NS, name(host)),
+            ("PTR", [host]) => (RecordType::PTR, name(host)),
+            ("MX", [preference, host]) => (RecordType::MX, format!("{preference} {}", name(host))),
+            ("SRV", [priority, weight, port, host]) => (
+                RecordType::SRV,
+                format!("{priority} {weight} {port} {}", name(host)),
+            ),
+            ("NAPTR", [fields @ .., replacement]) if fields.len() == 5 => {
+                let replacement = if replacement == "." {
+                    replacement.clone()
+                } else {
+                    name(replacement)
+                };
+                (
+                    RecordType::NAPTR,
+                    format!("{} {replacement}", fields.join(" ")),
+                )
+            }
+            ("TXT", texts) if !texts.is_empty() => (RecordType::TXT, texts.join(" ")),
+            _ => return None,
+        };
+        Some(ret)
+    }
+}
+
+/// The reading state carried from entry to entry: `$ORIGIN`, `$TTL` and the previous owner.
+#[derive(Debug, Clone)]
+pub struct Context {
+    origin: String,
+    default_ttl: Option<i64>,
+    last_ttl: Option<i64>,
+    last_owner: Option<String>,
+}
+impl Context {
+    /// Constructor of `Context`, for the zone `origin`.
+    #[must_use]
+    pub fn new(origin: &str) -> Self {
+        Self {
+            origin: origin.trim_end_matches('.').to_string(),
+            default_ttl: None,
+            last_ttl: None,
+            last_owner: None,
+        }
+    }
+
+    /// Read one entry of `entries`: directives update the context, records are returned.
+    /// `$INCLUDE` and `$GENERATE` are not followed.
+    /// # Errors
+    ///
+    /// When the entry is not a valid directive or record.
+    pub fn read(&mut self, entry: &str) -> Result<Option<ZoneRecord>> {
+        let tokens: Vec<&str> = tokens(entry).into_iter().map(|(_, t)| t).collect();
+        let Some(first) = tokens.first() else {
+            return Ok(None);
+        };
+        if first.starts_with('$') {
+            match (first.to_ascii_uppercase().as_str(), tokens.get(1)) {
+                ("$ORIGIN", Some(origin)) => self.origin = absolute(origin, &self.origin),
+                ("$TTL", Some(ttl)) => {
+                    self.default_ttl =
+                        Some(parse_ttl(ttl).ok_or_else(|| eyre!("Invalid $TTL: {entry}"))?);
+                }
+                ("$ORIGIN" | "$TTL", None) => return Err(eyre!("Missing argument: {entry}")),
+                _ => {}
+            }
+            return Ok(None);
+        }
+
+        let mut rest = &tokens[..];
+        let name = if entry.starts_with([' ', '\t']) {
+            self.last_owner
+                .clone()
+                .ok_or_else(|| eyre!("No previous owner for {entry}"))?
+        } else {
+            rest = &rest[1..];
+            absolute(first, &self.origin)
+        };
+        self.last_owner = Some(name.clone());
+
+        // TTL and class, both optional, in any order.
+        let mut ttl = None;
+        loop {
+            match rest.first() {
+                Some(t) if ["IN", "CH", "HS", "CS"].contains(&t.to_ascii_uppercase().as_str()) => {
+                    rest = &rest[1..];
+                }
+                Some(t) if ttl.is_none() && parse_ttl(t).is_some() => {
+                    ttl = parse_ttl(t);
+                    rest = &rest[1..];
+                }
+                _ => break,
+            }
+        }
+        let [record_type, rdata @ ..] = rest else {
+            return Err(eyre!("No record type in {entry}"));
+        };
+        if ttl.is_some() {
+            self.last_ttl = ttl;
+        }
+        Ok(Some(ZoneRecord {
+            name,
+            ttl: ttl.or(self.default_ttl).or(self.last_ttl),
+            record_type: record_type.to_ascii_uppercase(),
+            rdata: rdata.iter().map(ToString::to_string).collect(),
+            origin: self.origin.clone(),
+        }))
+    }
+}
+
+/// Split the content into entries: one line, or the lines spanned by parentheses.
+/// Entries keep their line endings, so that joining them gives the content back.
+#[must_use]
+pub fn entries(content: &str) -> Vec<&str> {
+    let mut ret = vec![];
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut chars = content.char_indices();
+    let mut quoted = false;
+    let mut comment = false;
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if !comment => {
+                chars.next();
+            }
+            '"' if !comment => quoted = !quoted,
+            ';' if !quoted => comment = true,
+            '(' if !quoted && !comment => depth += 1,
+            ')' if !quoted && !comment => depth = depth.saturating_sub(1),
+            '\n' => {
+                comment = false;
+                if depth == 0 {
+                    ret.push(&content[start..=i]);
+                    start = i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    if start < content.len() {
+        ret.push(&content[start..]);
+    }
+    ret
+}
+
+/// The tokens of an entry and their byte offsets, without comments and parentheses.
+/// Quoted character strings are one token, quotes included.
+#[must_use]
+pub fn tokens(entry: &str) -> Vec<(usize, &str)> {
+    let mut ret = vec![];
+    let mut start = None;
+    let mut quoted = false;
+    let mut chars = entry.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if quoted {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '"' => {
+                    quoted = false;
+                    if let Some(s) = start.take() {
+                        ret.push((s, &entry[s..=i]));
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' | ';' | '(' | ')' | ' ' | '\t' | '\r' | '\n' => {
+                if let Some(s) = start.take() {
+                    ret.push((s, &entry[s..i]));
+                }
+                match c {
+                    '"' => {
+                        quoted = true;
+                        start = Some(i);
+                    }
+                    ';' => break,
+                    _ => {}
+                }
+            }
+            '\\' => {
+                start.get_or_insert(i);
+                chars.next();
+            }
+            _ => {
+                start.get_or_insert(i);
+            }
+        }
+    }
+    if let Some(s) = start {
+        ret.push((s, &entry[s..]));
+    }
+    ret
+}
+
+/// The absolute name, without the trailing dot, of a name as written in the zone.
+#[must_use]
+pub fn absolute(name: &str, origin: &str) -> String {
+    if name == "@" {
+        origin.to_string()
+    } else if let Some(name) = name.strip_suffix('.') {
+        name.to_string()
+    } else if origin.is_empty() {
+        name.to_string()
+    } else {
+        format!("{name}.{origin}")
+    }
+}
+
+/// A TTL in seconds, or with BIND units such as `1h30m`.
+#[must_use]
+pub fn parse_ttl(ttl: &str) -> Option<i64> {
+    if !ttl.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    if let Ok(seconds) = ttl.parse() {
+        return Some(seconds);
+    }
+    let mut total = 0i64;
+    let mut number = String::new();
+    for c in ttl.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604_800,
+            _ => return None,
+        };
+        total += number.parse::<i64>().ok()? * unit;
+        number.clear();
+    }
+    number.is_empty().then_some(total)
+}
+
+/// Endpoints of records, one per name and type, in order of appearance.
+/// Records of types `RecordType` does not have are left out.
+#[must_use]
+pub fn endpoints(records: &[ZoneRecord]) -> Vec<Endpoint> {
+    let mut order = Vec::new();
+    let mut found: BTreeMap<(String, RecordType), Endpoint> = BTreeMap::new();
+    for record in records {
+        let Some((record_type, target)) = record.target() else {
+            continue;
+        };
+        let key = (record.name.clone(), record_type.clone());
+        let ep = found.entry(key.clone()).or_insert_with(|| {
+            order.push(key);
+            Endpoint {
+                dns_name: Some(record.name.clone()),
+                targets: Some(vec![]),
+                record_type: Some(record_type),
+                set_identifier: None,
+                record_ttl: record.ttl,
+                labels: None,
+                provider_specific: None,
+            }
+        });
+        let targets = ep.targets.get_or_insert_default();
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    order
+        .into_iter()
+        .filter_map(|key| found.remove(&key))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let zone = r#"$ORIGIN magicloud.lan.
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+        2024010101 ; serial
+        3600 900 604800 300 )
+    IN  NS  ns1
+    IN  MX  10 mail
+    IN  TXT "v=spf1 mx -all" "a; b"
+ns1 IN  A   192.168.0.1
+www 300 IN CNAME web.example.com.
+_imap._tcp  SRV 0 5 143 mail
+$ORIGIN 0.168.192.in-addr.arpa.
+1   PTR ns1.magicloud.lan.
+"#;
+        assert_eq!(entries(zone).concat(), zone);
+        assert_eq!(entries(zone).len(), 11);
+
+        let mut context = Context::new("magicloud.lan");
+        let records: Vec<ZoneRecord> = entries(zone)
+            .into_iter()
+            .filter_map(|e| context.read(e).unwrap())
+            .collect();
+        assert_eq!(records[0].record_type, "SOA");
+        assert_eq!(records[0].rdata[2], "2024010101");
+        let found: Vec<_> = endpoints(&records)
+            .into_iter()
+            .map(|ep| {
+                (
+                    ep.dns_name.unwrap(),
+                    ep.record_type.unwrap(),
+                    ep.targets.unwrap().join("|"),
+                    ep.record_ttl,
+                )
+            })
+            .collect();
+        let expected = [
+            ("magicloud.lan", RecordType::NS, "ns1.magicloud.lan", 3600),
+            (
+                "magicloud.lan",
+                RecordType::MX,
+                "10 mail.magicloud.lan",
+                3600,
+            ),
+            (
+                "magicloud.lan",
+                RecordType::TXT,
+                r#""v=spf1 mx -all" "a; b""#,
+                3600,
+            ),
+            ("ns1.magicloud.lan", RecordType::A, "192.168.0.1", 3600),
+            (
+                "www.magicloud.lan",
+                RecordType::CNAME,
+                "web.example.com",
+                300,
+            ),
+            (
+                "_imap._tcp.magicloud.lan",
+                RecordType::SRV,
+                "0 5 143 mail.magicloud.lan",
+                3600,
+            ),
+            (
+                "1.0.168.192.in-addr.arpa",
+                RecordType::PTR,
+                "ns1.magicloud.lan",
+                3600,
+            ),
+        ]
+        .map(|(n, t, targets, ttl)| (n.to_string(), t, targets.to_string(), Some(ttl)));
+        assert_eq!(found, expected);
+    }
+}
@@ -0,0 +1,302 @@
+//! Record and replay of the traffic of the webhook server.
+//!
+//! With `Webhook::with_recorder`, every request to the webhook server and its response
+//! are appended to a JSON-lines file, with the values of sensitive headers redacted.
+//! `replay` feeds such a recording back into a `Provider`,
+//! to reproduce what External-DNS sent, and to turn it into regression tests.
+
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    error::ErrorInternalServerError,
+    http::Method,
+    middleware::Next,
+    test::TestRequest,
+    web::{self, Bytes, Data},
+};
+use eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use tracing::{instrument, warn};
+
+use crate::{provider::Provider, webhook::call_in_process};
+
+/// A request to the webhook server and its response.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+    /// When the request came in, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// How long the response took, in milliseconds.
+    pub duration: u64,
+    pub method: String,
+    /// Path and query.
+    pub uri: String,
+    /// Values of credentials, such as `Authorization`, `Cookie` or API keys, are redacted.
+    pub request_headers: BTreeMap<String, String>,
+    pub request_body: String,
+    pub status: u16,
+    pub response_body: String,
+}
+
+/// Appends `Exchange`s to a JSON-lines file.
+#[derive(Debug)]
+pub struct Recorder {
+    file: Mutex<File>,
+}
+impl Recorder {
+    /// Open (or create) the recording file, appending to it.
+    /// # Errors
+    ///
+    /// When the file cannot be opened.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            file: Mutex::new(OpenOptions::new().create(true).append(true).open(path)?),
+        })
+    }
+
+    /// Append one exchange, as one line.
+    /// This blocks on the file, the middleware calls it off the async workers.
+    /// # Errors
+    ///
+    /// When the file cannot be written.
+    pub fn record(&self, exchange: &Exchange) -> Result<()> {
+        let mut line = serde_json::to_vec(exchange)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        file.write_all(&line)?;
+        file.flush()?;
+        drop(file);
+        Ok(())
+    }
+
+    /// Read all exchanges of a recording file.
+    /// # Errors
+    ///
+    /// When the file cannot be read, or a line is not an `Exchange`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Vec<Exchange>> {
+        BufReader::new(File::open(path)?)
+            .lines()
+            .filter(|l| l.as_ref().map_or(true, |l| !l.trim().is_empty()))
+            .map(|l| Ok(serde_json::from_str(&l?)?))
+            .collect()
+    }
+}
+
+/// Bodies read by the recorder are limited as the JSON ones the routes take.
+pub(crate) const BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Replaces the values of sensitive headers.
+pub const REDACTED: &str = "[redacted]";
+
+// If the header may hold credentials.
+fn sensitive(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    [
+        "auth", "cookie", "token", "secret", "password", "api-key", "apikey",
+    ]
+    .iter()
+    .any(|s| name.contains(s))
+}
+
+/// The recording middleware, installed with `Webhook::with_recorder` only.
+pub(crate) async fn record(
+    recorder: Data<Arc<Recorder>>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+    let started = Instant::now();
+    let request_body = req.extract::<Bytes>().await?;
+    req.set_payload(request_body.clone().into());
+    let method = req.method().to_string();
+    let uri = req.uri().to_string();
+    let request_headers = req
+        .headers()
+        .iter()
+        .filter_map(|(k, v)| {
+            let v = if sensitive(k.as_str()) {
+                REDACTED
+            } else {
+                v.to_str().ok()?
+            };
+            Some((k.to_string(), v.to_string()))
+        })
+        .collect();
+
+    let (req, res) = next.call(req).await?.map_into_boxed_body().into_parts();
+    let status = res.status().as_u16();
+    let (res, response_body) = res.into_parts();
+    let response_body = body::to_bytes(response_body)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+    let res = res.set_body(response_body.clone()).map_into_boxed_body();
+
+    let exchange = Exchange {
+        timestamp,
+        duration: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+        method,
+        uri,
+        request_headers,
+        request_body: String::from_utf8_lossy(&request_body).to_string(),
+        status,
+        response_body: String::from_utf8_lossy(&response_body).to_string(),
+    };
+    let recorder = recorder.into_inner();
+    let written = web::block(move || recorder.record(&exchange))
+        .await
+        .map_err(|e| eyre!(e.to_string()))
+        .flatten();
+    if let Err(e) = written {
+        warn!(target: "recorder", message = format!("Cannot record exchange: {e:?}"));
+    }
+
+    Ok(ServiceResponse::new(req, res))
+}
+
+/// Outcome of replaying one `Exchange`.
+#[derive(Debug, Clone)]
+pub struct Replayed {
+    pub exchange: Exchange,
+    pub status: u16,
+    pub response_body: String,
+}
+impl Replayed {
+    /// If the provider answered as recorded.
+    /// JSON bodies are compared as JSON, so formatting and key order do not matter.
+    #[must_use]
+    pub fn matches(&self) -> bool {
+        let as_json = |s: &str| serde_json::from_str::<serde_json::Value>(s).ok();
+        self.status == self.exchange.status
+            && match (
+                as_json(&self.response_body),
+                as_json(&self.exchange.response_body),
+            ) {
+                (Some(a), Some(b)) => a == b,
+                _ => self.response_body == self.exchange.response_body,
+            }
+    }
+}
+
+/// Send the recorded requests, in order, through the routes of the webhook server to `provider`.
+/// # Errors
+///
+/// When a recorded request cannot be rebuilt.
+#[instrument(skip_all)]
+pub async fn replay(
+    exchanges: impl IntoIterator<Item = Exchange>,
+    provider: Arc<dyn Provider>,
+) -> Result<Vec<Replayed>> {
+    let mut ret = Vec::new();
+    for exchange in exchanges {
+        let method = Method::from_bytes(exchange.method.as_bytes())
+            .map_err(|e| eyre!("Bad method in {exchange:?}: {e}"))?;
+        let mut req = TestRequest::default()
+            .method(method)
+            .uri(&exchange.uri)
+            .set_payload(exchange.request_body.clone());
+        for (k, v) in &exchange.request_headers {
+            req = req.insert_header((k.as_str(), v.as_str()));
+        }
+        let (status, _, body) = call_in_process(provider.clone(), req).await?;
+        ret.push(Replayed {
+            exchange,
+            status: status.as_u16(),
+            response_body: String::from_utf8_lossy(&body).to_string(),
+        });
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MEDIATYPE, domain_filter::DomainFilter, providers::memory::InMemoryProvider,
+        webhook::provider_services,
+    };
+    use actix_web::{
+        App,
+        http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+        middleware::from_fn,
+        test,
+        web::PayloadConfig,
+    };
+
+    const SEED: &str = r#"[{"dnsName": "nextcloud.magicloud.lan", "targets": ["192.168.0.102"], "recordType": "A"}]"#;
+
+    #[tokio::test]
+    async fn it_works() {
+        let path = std::env::temp_dir().join(format!("recorder-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let recorder = Arc::new(Recorder::create(&path).unwrap());
+        let filter = DomainFilter::Strings {
+            include: Some(vec!["magicloud.lan".to_string()]),
+            exclude: None,
+        };
+        let provider = Arc::new(InMemoryProvider::from_json(filter.clone(), SEED).unwrap());
+
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(record))
+                .app_data(Data::new(recorder))
+                .app_data(PayloadConfig::new(BODY_LIMIT))
+                .configure(provider_services(provider)),
+        )
+        .await;
+        let res = test::call_service(
+            &app,
+            TestRequest::get()
+                .uri("/records")
+                .insert_header((ACCEPT, MEDIATYPE))
+                .insert_header((AUTHORIZATION, "Bearer secret"))
+                .insert_header(("X-Api-Key", "secret"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status().as_u16(), 200);
+        let res = test::call_service(
+            &app,
+            TestRequest::post()
+                .uri("/records")
+                .insert_header((CONTENT_TYPE, MEDIATYPE))
+                .set_payload(
+                    r#"{"create": [{"dnsName": "gitea.magicloud.lan", "targets": ["192.168.0.103"], "recordType": "A"}]}"#,
+                )
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status().as_u16(), 204);
+
+        let exchanges = Recorder::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(exchanges.len(), 2);
+        let headers = &exchanges[0].request_headers;
+        assert_eq!(headers["authorization"], REDACTED);
+        assert_eq!(headers["x-api-key"], REDACTED);
+        assert_eq!(headers["accept"], MEDIATYPE);
+        assert_eq!(exchanges[1].method, "POST");
+        assert_eq!(exchanges[1].status, 204);
+        assert!(exchanges[1].request_body.contains("gitea.magicloud.lan"));
+
+        // Replaying against the same starting state reproduces the responses.
+        let fresh = Arc::new(InMemoryProvider::from_json(filter, SEED).unwrap());
+        let replayed = replay(exchanges.clone(), fresh.clone()).await.unwrap();
+        assert!(replayed.iter().all(Replayed::matches));
+        assert_eq!(fresh.len(), 2);
+
+        // Replaying again fails the creation, since the record now exists.
+        let replayed = replay(exchanges, fresh).await.unwrap();
+        assert!(!replayed[0].matches());
+        assert_eq!(replayed[1].status, 500);
+    }
+}
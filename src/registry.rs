@@ -0,0 +1,626 @@
+//! Helpers for the TXT registry of External-DNS.
+//!
+//! External-DNS keeps the ownership of each record it manages in a companion TXT record,
+//! such as `a-nextcloud.magicloud.lan` holding
+//! `"heritage=external-dns,external-dns/owner=default,external-dns/resource=ingress/nextcloud/nextcloud"`.
+
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use eyre::{Result, eyre};
+
+use crate::endpoint::{Endpoint, RecordType};
+
+/// The heritage External-DNS marks its TXT registry records with.
+pub const HERITAGE: &str = "external-dns";
+/// Label of the owner id (`--txt-owner-id`).
+pub const OWNER_LABEL: &str = "owner";
+/// Label of the Kubernetes resource a record comes from.
+pub const RESOURCE_LABEL: &str = "resource";
+/// Label keeping the nonce of an encrypted TXT registry record. It is not part of the text.
+pub const ENCRYPTION_NONCE_LABEL: &str = "txt-encryption-nonce";
+
+// The record types External-DNS tries, in order, when finding the type in a TXT name.
+const SUPPORTED_TYPES: [RecordType; 9] = [
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::CNAME,
+    RecordType::NS,
+    RecordType::SRV,
+    RecordType::MX,
+    RecordType::TXT,
+    RecordType::PTR,
+    RecordType::NAPTR,
+];
+
+const RECORD_TYPE_TEMPLATE: &str = "%{record_type}";
+
+/// The value of a TXT registry record: the labels of the owned record.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Heritage {
+    pub labels: BTreeMap<String, String>,
+}
+impl Heritage {
+    /// Constructor of `Heritage`, with the owner and the resource.
+    #[must_use]
+    pub fn new(owner: &str, resource: Option<&str>) -> Self {
+        let mut labels = BTreeMap::from([(OWNER_LABEL.to_string(), owner.to_string())]);
+        if let Some(resource) = resource {
+            labels.insert(RESOURCE_LABEL.to_string(), resource.to_string());
+        }
+        Self { labels }
+    }
+
+    /// The `--txt-owner-id` of the External-DNS instance owning the record.
+    #[must_use]
+    pub fn owner(&self) -> Option<&str> {
+        self.labels.get(OWNER_LABEL).map(String::as_str)
+    }
+
+    /// The resource the record comes from, such as `ingress/nextcloud/nextcloud`.
+    #[must_use]
+    pub fn resource(&self) -> Option<&str> {
+        self.labels.get(RESOURCE_LABEL).map(String::as_str)
+    }
+
+    /// The text, without the surrounding quotes, with labels ordered by key, as External-DNS does.
+    #[must_use]
+    pub fn to_plain(&self) -> String {
+        std::iter::once(format!("heritage={HERITAGE}"))
+            .chain(
+                self.labels
+                    .iter()
+                    .filter(|(k, _)| *k != ENCRYPTION_NONCE_LABEL)
+                    .map(|(k, v)| format!("{HERITAGE}/{k}={v}")),
+            )
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+/// Parses the value of a TXT registry record, quoted or not.
+/// Tokens not in `key=value` form, and keys not under `external-dns/`, are ignored,
+/// as External-DNS does.
+impl FromStr for Heritage {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut heritage_found = false;
+        let mut labels = BTreeMap::new();
+        for token in s.trim_matches('"').split(',') {
+            let Some((key, value)) = token.split_once('=') else {
+                continue;
+            };
+            if value.contains('=') {
+                continue;
+            }
+            if key == "heritage" {
+                if value != HERITAGE {
+                    return Err(eyre!("Unknown heritage {value} in {s}"));
+                }
+                heritage_found = true;
+            } else if let Some(key) = key.strip_prefix(&format!("{HERITAGE}/")) {
+                labels.insert(key.to_string(), value.to_string());
+            }
+        }
+        if heritage_found {
+            Ok(Self { labels })
+        } else {
+            Err(eyre!("No heritage in {s}"))
+        }
+    }
+}
+/// Renders with the surrounding quotes, as External-DNS writes the target.
+impl Display for Heritage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\"{}\"", self.to_plain())
+    }
+}
+
+/// How TXT registry record names derive from the names of the owned records,
+/// that is `--txt-prefix`, `--txt-suffix` and `--txt-wildcard-replacement`.
+///
+/// Either affix may contain `%{record_type}`, replaced by the lowercase record type.
+/// Otherwise the type is put in front of the first label, as in `a-nextcloud.magicloud.lan`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxtNaming {
+    pub prefix: String,
+    pub suffix: String,
+    pub wildcard_replacement: String,
+}
+impl TxtNaming {
+    /// The name of the TXT registry record of a record, in the current format.
+    #[must_use]
+    pub fn txt_name(&self, dns_name: &str, record_type: &RecordType) -> String {
+        let record_type = type_name(record_type);
+        let prefix = self.prefix.replace(RECORD_TYPE_TEMPLATE, &record_type);
+        let suffix = self.suffix.replace(RECORD_TYPE_TEMPLATE, &record_type);
+        let (first, rest) = self.split(dns_name);
+        let first = if self.type_in_affix() {
+            first
+        } else {
+            format!("{record_type}-{first}")
+        };
+        join(&prefix, &first, &suffix, rest)
+    }
+
+    /// The name of the TXT registry record of a record, in the old format, without the type.
+    /// External-DNS still reads those, and used to write them alongside the current ones.
+    #[must_use]
+    pub fn old_txt_name(&self, dns_name: &str) -> String {
+        let (first, rest) = self.split(dns_name);
+        join(
+            &self.prefix.replace(RECORD_TYPE_TEMPLATE, ""),
+            &first,
+            &self.suffix.replace(RECORD_TYPE_TEMPLATE, ""),
+            rest,
+        )
+    }
+
+    /// The name and type of the record owned by a TXT registry record.
+    /// The type is `None` for old format names.
+    /// Returns `None` when the name does not follow this naming.
+    #[must_use]
+    pub fn endpoint_name(&self, txt_name: &str) -> Option<(String, Option<RecordType>)> {
+        let txt_name = txt_name.to_lowercase();
+        let (name, record_type) = if self.suffix.is_empty() {
+            self.drop_affix(&txt_name)?
+        } else if self.prefix.is_empty() {
+            // The suffix goes between the first label and the rest, and may contain dots.
+            let dots = self.suffix.matches('.').count();
+            let labels: Vec<_> = txt_name.splitn(2 + dots, '.').collect();
+            if labels.len() < 2 + dots {
+                return None;
+            }
+            let (name, record_type) = self.drop_affix(&labels[..=dots].join("."))?;
+            (format!("{name}.{}", labels[1 + dots]), record_type)
+        } else {
+            return None;
+        };
+        let name = match name.split_once('.') {
+            Some((first, rest))
+                if !self.wildcard_replacement.is_empty() && first == self.wildcard_replacement =>
+            {
+                format!("*.{rest}")
+            }
+            _ => name,
+        };
+        Some((name, record_type))
+    }
+
+    fn type_in_affix(&self) -> bool {
+        self.prefix.contains(RECORD_TYPE_TEMPLATE) || self.suffix.contains(RECORD_TYPE_TEMPLATE)
+    }
+
+    fn split<'a>(&self, dns_name: &'a str) -> (String, Option<&'a str>) {
+        let (first, rest) = dns_name
+            .split_once('.')
+            .map_or((dns_name, None), |(f, r)| (f, Some(r)));
+        if first == "*" && !self.wildcard_replacement.is_empty() {
+            (self.wildcard_replacement.clone(), rest)
+        } else {
+            (first.to_string(), rest)
+        }
+    }
+
+    fn drop_affix(&self, name: &str) -> Option<(String, Option<RecordType>)> {
+        let strip = |name: &str, prefix: &str, suffix: &str| {
+            name.strip_prefix(prefix)
+                .and_then(|n| n.strip_suffix(suffix))
+                .map(ToString::to_string)
+        };
+        if self.type_in_affix() {
+            for t in &SUPPORTED_TYPES {
+                let type_name = type_name(t);
+                if let Some(name) = strip(
+                    name,
+                    &self.prefix.replace(RECORD_TYPE_TEMPLATE, &type_name),
+                    &self.suffix.replace(RECORD_TYPE_TEMPLATE, &type_name),
+                ) {
+                    return Some((name, Some(t.clone())));
+                }
+            }
+        }
+        let name = strip(
+            name,
+            &self.prefix.replace(RECORD_TYPE_TEMPLATE, ""),
+            &self.suffix.replace(RECORD_TYPE_TEMPLATE, ""),
+        )?;
+        for t in &SUPPORTED_TYPES {
+            if let Some(name) = name.strip_prefix(&format!("{}-", type_name(t))) {
+                return Some((name.to_string(), Some(t.clone())));
+            }
+        }
+        Some((name, None))
+    }
+}
+
+fn type_name(record_type: &RecordType) -> String {
+    format!("{record_type:?}").to_lowercase()
+}
+
+fn join(prefix: &str, first: &str, suffix: &str, rest: Option<&str>) -> String {
+    rest.map_or_else(
+        || format!("{prefix}{first}{suffix}"),
+        |rest| format!("{prefix}{first}{suffix}.{rest}"),
+    )
+}
+
+/// A record, with its TXT registry record and the parsed ownership, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owned<'a> {
+    pub record: &'a Endpoint,
+    pub txt: Option<&'a Endpoint>,
+    pub heritage: Option<Heritage>,
+}
+impl Owned<'_> {
+    /// The owner id, if the record is owned.
+    #[must_use]
+    pub fn owner(&self) -> Option<&str> {
+        self.heritage.as_ref().and_then(Heritage::owner)
+    }
+
+    /// The resource the record comes from, if the record is owned.
+    #[must_use]
+    pub fn resource(&self) -> Option<&str> {
+        self.heritage.as_ref().and_then(Heritage::resource)
+    }
+}
+
+/// Pair records with the TXT registry records owning them.
+/// TXT registry records are not returned as records themselves.
+/// A current format TXT record takes precedence over an old format one.
+#[must_use]
+pub fn pair<'a>(records: &'a [Endpoint], naming: &TxtNaming) -> Vec<Owned<'a>> {
+    pair_by(records, naming, |t| t.parse().ok())
+}
+
+/// Same as `pair`, for a registry encrypted with `key`. Plain TXT registry records are read too.
+#[cfg(feature = "txt-encryption")]
+#[must_use]
+pub fn pair_encrypted<'a>(
+    records: &'a [Endpoint],
+    naming: &TxtNaming,
+    key: &encryption::TxtEncryptionKey,
+) -> Vec<Owned<'a>> {
+    pair_by(records, naming, |t| Heritage::decrypt(t, key).ok())
+}
+
+fn pair_by<'a>(
+    records: &'a [Endpoint],
+    naming: &TxtNaming,
+    parse: impl Fn(&str) -> Option<Heritage>,
+) -> Vec<Owned<'a>> {
+    let mut registry = BTreeMap::new();
+    let mut owned = Vec::new();
+    for ep in records {
+        let heritage = (ep.record_type == Some(RecordType::TXT))
+            .then(|| {
+                let target = ep.targets.as_ref()?.first()?;
+                let heritage = parse(target)?;
+                let (name, record_type) = naming.endpoint_name(ep.dns_name.as_ref()?)?;
+                Some((name, record_type, heritage))
+            })
+            .flatten();
+        match heritage {
+            Some((name, record_type, heritage)) => {
+                registry.insert(
+                    (name, record_type, ep.set_identifier.clone()),
+                    (ep, heritage),
+                );
+            }
+            None => owned.push(ep),
+        }
+    }
+    owned
+        .into_iter()
+        .map(|record| {
+            let name = record.dns_name.clone().unwrap_or_default().to_lowercase();
+            let found = registry
+                .get(&(
+                    name.clone(),
+                    record.record_type.clone(),
+                    record.set_identifier.clone(),
+                ))
+                .or_else(|| registry.get(&(name, None, record.set_identifier.clone())));
+            Owned {
+                record,
+                txt: found.map(|(txt, _)| *txt),
+                heritage: found.map(|(_, heritage)| heritage.clone()),
+            }
+        })
+        .collect()
+}
+
+/// Encryption of TXT registry records, in the format of `--txt-encrypt-enabled`
+/// and `--txt-encrypt-aes-key` of External-DNS.
+///
+/// The text is gzipped, then sealed with AES-256-GCM. The record holds the base64
+/// of the 12 bytes nonce followed by the sealed data, as External-DNS writes it.
+#[cfg(feature = "txt-encryption")]
+pub mod encryption {
+    use std::io::{Read, Write};
+
+    use aes_gcm::{
+        Aes256Gcm, Key, KeyInit, Nonce,
+        aead::{Aead, AeadCore, OsRng},
+    };
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use eyre::{Result, eyre};
+    use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+
+    use super::{ENCRYPTION_NONCE_LABEL, Heritage};
+
+    const NONCE_SIZE: usize = 12;
+
+    /// The AES-256 key of `--txt-encrypt-aes-key`.
+    #[derive(Clone)]
+    pub struct TxtEncryptionKey(Key<Aes256Gcm>);
+    impl TxtEncryptionKey {
+        /// The key is either 32 bytes as is, or their standard base64, as External-DNS accepts.
+        /// # Errors
+        ///
+        /// When the key is neither.
+        pub fn new(key: &str) -> Result<Self> {
+            let bytes = if key.len() == 32 {
+                key.as_bytes().to_vec()
+            } else {
+                STANDARD
+                    .decode(key)
+                    .map_err(|e| eyre!("The AES key is neither 32 bytes nor base64: {e}"))?
+            };
+            if bytes.len() == 32 {
+                Ok(Self(*Key::<Aes256Gcm>::from_slice(&bytes)))
+            } else {
+                Err(eyre!("The AES key is {} bytes, not 32", bytes.len()))
+            }
+        }
+    }
+    impl std::fmt::Debug for TxtEncryptionKey {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("TxtEncryptionKey(..)")
+        }
+    }
+
+    /// Encrypt `text` with the base64 `nonce`.
+    /// # Errors
+    ///
+    /// When the nonce is not the base64 of 12 bytes.
+    pub fn encrypt_text(text: &str, key: &TxtEncryptionKey, nonce: &str) -> Result<String> {
+        let nonce = STANDARD.decode(nonce)?;
+        if nonce.len() != NONCE_SIZE {
+            return Err(eyre!(
+                "The nonce is {} bytes, not {NONCE_SIZE}",
+                nonce.len()
+            ));
+        }
+        let mut gz = GzEncoder::new(Vec::new(), Compression::best());
+        gz.write_all(text.as_bytes())?;
+        let sealed = Aes256Gcm::new(&key.0)
+            .encrypt(Nonce::from_slice(&nonce), gz.finish()?.as_slice())
+            .map_err(|e| eyre!("Cannot encrypt: {e}"))?;
+        Ok(STANDARD.encode([nonce, sealed].concat()))
+    }
+
+    /// Decrypt `text`, returning the plain text and the base64 nonce.
+    /// # Errors
+    ///
+    /// When `text` is not encrypted with `key`.
+    pub fn decrypt_text(text: &str, key: &TxtEncryptionKey) -> Result<(String, String)> {
+        let data = STANDARD.decode(text)?;
+        if data.len() <= NONCE_SIZE {
+            return Err(eyre!("The encrypted text is too short: {text}"));
+        }
+        let (nonce, sealed) = data.split_at(NONCE_SIZE);
+        let gzipped = Aes256Gcm::new(&key.0)
+            .decrypt(Nonce::from_slice(nonce), sealed)
+            .map_err(|e| eyre!("Cannot decrypt: {e}"))?;
+        let mut plain = String::new();
+        GzDecoder::new(gzipped.as_slice()).read_to_string(&mut plain)?;
+        Ok((plain, STANDARD.encode(nonce)))
+    }
+
+    impl Heritage {
+        /// Parse the value of a TXT registry record, decrypting it when encrypted with `key`.
+        /// The nonce is kept in the labels, so encrypting again gives the same value.
+        /// # Errors
+        ///
+        /// When the value is neither an encrypted nor a plain TXT registry value.
+        pub fn decrypt(s: &str, key: &TxtEncryptionKey) -> Result<Self> {
+            match decrypt_text(s.trim_matches('"'), key) {
+                Ok((plain, nonce)) => {
+                    let mut ret: Self = plain.parse()?;
+                    ret.labels.insert(ENCRYPTION_NONCE_LABEL.to_string(), nonce);
+                    Ok(ret)
+                }
+                // Registries may hold plain values written before encryption was enabled.
+                Err(_) => s.parse(),
+            }
+        }
+
+        /// The encrypted value, with the surrounding quotes.
+        /// A nonce is generated, and kept in the labels, when there is none yet.
+        /// # Errors
+        ///
+        /// When the nonce label is not the base64 of 12 bytes.
+        pub fn encrypt(&mut self, key: &TxtEncryptionKey) -> Result<String> {
+            let nonce = self
+                .labels
+                .entry(ENCRYPTION_NONCE_LABEL.to_string())
+                .or_insert_with(|| STANDARD.encode(Aes256Gcm::generate_nonce(&mut OsRng)))
+                .clone();
+            Ok(format!(
+                "\"{}\"",
+                encrypt_text(&self.to_plain(), key, &nonce)?
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUE: &str = "\"heritage=external-dns,external-dns/owner=default,external-dns/resource=ingress/nextcloud/nextcloud\"";
+
+    #[test]
+    fn it_works() {
+        let heritage: Heritage = VALUE.parse().unwrap();
+        assert_eq!(heritage.owner(), Some("default"));
+        assert_eq!(heritage.resource(), Some("ingress/nextcloud/nextcloud"));
+        assert_eq!(heritage.to_string(), VALUE);
+        assert_eq!(
+            Heritage::new("default", Some("ingress/nextcloud/nextcloud")),
+            heritage
+        );
+
+        assert!(
+            "\"heritage=other,external-dns/owner=default\""
+                .parse::<Heritage>()
+                .is_err()
+        );
+        assert!("\"v=spf1 -all\"".parse::<Heritage>().is_err());
+    }
+
+    #[test]
+    fn names() {
+        let naming = TxtNaming::default();
+        assert_eq!(
+            naming.txt_name("nextcloud.magicloud.lan", &RecordType::A),
+            "a-nextcloud.magicloud.lan"
+        );
+        assert_eq!(
+            naming.old_txt_name("nextcloud.magicloud.lan"),
+            "nextcloud.magicloud.lan"
+        );
+        assert_eq!(
+            naming.endpoint_name("cname-gitea.magicloud.lan"),
+            Some(("gitea.magicloud.lan".to_string(), Some(RecordType::CNAME)))
+        );
+        assert_eq!(
+            naming.endpoint_name("gitea.magicloud.lan"),
+            Some(("gitea.magicloud.lan".to_string(), None))
+        );
+
+        let naming = TxtNaming {
+            prefix: "txt.".to_string(),
+            ..TxtNaming::default()
+        };
+        assert_eq!(
+            naming.txt_name("nextcloud.magicloud.lan", &RecordType::AAAA),
+            "txt.aaaa-nextcloud.magicloud.lan"
+        );
+        assert_eq!(
+            naming.endpoint_name("txt.aaaa-nextcloud.magicloud.lan"),
+            Some((
+                "nextcloud.magicloud.lan".to_string(),
+                Some(RecordType::AAAA)
+            ))
+        );
+        assert_eq!(naming.endpoint_name("nextcloud.magicloud.lan"), None);
+
+        let naming = TxtNaming {
+            suffix: "-%{record_type}.txt".to_string(),
+            wildcard_replacement: "any".to_string(),
+            ..TxtNaming::default()
+        };
+        assert_eq!(
+            naming.txt_name("*.magicloud.lan", &RecordType::CNAME),
+            "any-cname.txt.magicloud.lan"
+        );
+        assert_eq!(
+            naming.old_txt_name("*.magicloud.lan"),
+            "any-.txt.magicloud.lan"
+        );
+        assert_eq!(
+            naming.endpoint_name("any-cname.txt.magicloud.lan"),
+            Some(("*.magicloud.lan".to_string(), Some(RecordType::CNAME)))
+        );
+    }
+
+    #[test]
+    fn pairs() {
+        let records: Vec<Endpoint> = serde_json::from_str(&format!(
+            r#"[
+    {{"dnsName": "nextcloud.magicloud.lan", "targets": ["192.168.0.102"], "recordType": "A"}},
+    {{"dnsName": "a-nextcloud.magicloud.lan", "targets": [{}], "recordType": "TXT"}},
+    {{"dnsName": "gitea.magicloud.lan", "targets": ["192.168.0.103"], "recordType": "A"}},
+    {{"dnsName": "magicloud.lan", "targets": ["\"v=spf1 -all\""], "recordType": "TXT"}}
+]"#,
+            serde_json::to_string(VALUE).unwrap()
+        ))
+        .unwrap();
+        let owned = pair(&records, &TxtNaming::default());
+        assert_eq!(owned.len(), 3);
+        assert_eq!(owned[0].record, &records[0]);
+        assert_eq!(owned[0].txt, Some(&records[1]));
+        assert_eq!(owned[0].owner(), Some("default"));
+        assert_eq!(owned[1].owner(), None);
+        assert_eq!(owned[2].record, &records[3]);
+        assert_eq!(owned[2].heritage, None);
+    }
+
+    // `barTextEncrypted` of `endpoint/labels_test.go` of External-DNS, sealed by its
+    // `EncryptText` with the `aesKey` of that suite.
+    #[cfg(feature = "txt-encryption")]
+    #[test]
+    fn external_dns_encryption() {
+        use encryption::{TxtEncryptionKey, decrypt_text};
+
+        const KEY: &str = ")K_Fy|?Z.64#UuHm`}[d!GC%WJM_fs{_";
+        const ENCRYPTED: &str = "yi6vVATlgYN0enXBIupVK2atNUKtajofWMroWtvZjUanFZXlWvqjJPpjmMd91kv86bZj+syQEP0uR3TK6eFVV7oKFh/NxYyh238FjZ+25zlXW9TgbLoMalUNOkhKFdfXkLeeaqJjePB59t+kQBYX+ZEryK652asPs6M+xTIvtg07N7WWZ6SjJujm0RRISg==";
+        const PLAIN: &str = "heritage=external-dns,,external-dns/owner=bar-owner,\
+                             external-dns/resource=bar-resource,external-dns/new-key=bar-new-key,\
+                             random=stuff,no-equal-sign,,";
+
+        let key = TxtEncryptionKey::new(KEY).unwrap();
+        assert_eq!(
+            decrypt_text(ENCRYPTED, &key).unwrap(),
+            (PLAIN.to_string(), "yi6vVATlgYN0enXB".to_string())
+        );
+        let heritage = Heritage::decrypt(&format!("\"{ENCRYPTED}\""), &key).unwrap();
+        assert_eq!(heritage.owner(), Some("bar-owner"));
+    }
+
+    // A vector sealed by this crate, pinning the layout and the nonce label.
+    #[cfg(feature = "txt-encryption")]
+    #[test]
+    fn encryption() {
+        use encryption::{TxtEncryptionKey, decrypt_text, encrypt_text};
+
+        const KEY: &str = "s%zF`.*'5`9.AhI2!B,.~hmbs^.*TL?;";
+        const NONCE: &str = "4/HMsxy5TBotlGQy";
+        const ENCRYPTED: &str = "4/HMsxy5TBotlGQylWXcquFa+/gpvHk0LE0n7dbDRGBE2fD3Hm/RAvCDnr8sHFCNx3l6k6bQg1FWYZssDR40yXTkaGNW9ptGBfqqQM5v8XUp7gFQHU9ElPE6rkGZkvPf8pnQTelb7V3X+7LbkGmqFA==";
+
+        let key = TxtEncryptionKey::new(KEY).unwrap();
+        assert_eq!(
+            decrypt_text(ENCRYPTED, &key).unwrap(),
+            (VALUE.trim_matches('"').to_string(), NONCE.to_string())
+        );
+
+        let mut heritage = Heritage::decrypt(&format!("\"{ENCRYPTED}\""), &key).unwrap();
+        assert_eq!(heritage.owner(), Some("default"));
+        assert_eq!(heritage.labels[ENCRYPTION_NONCE_LABEL], NONCE);
+        assert_eq!(heritage.to_string(), VALUE);
+        // Re-encrypting keeps the nonce of the label, and round trips.
+        let encrypted = heritage.encrypt(&key).unwrap();
+        assert!(encrypted.starts_with(&format!("\"{NONCE}")));
+        assert_eq!(Heritage::decrypt(&encrypted, &key).unwrap(), heritage);
+
+        // Plain values are still read.
+        assert_eq!(
+            Heritage::decrypt(VALUE, &key).unwrap(),
+            VALUE.parse().unwrap()
+        );
+        // Keys may be given as base64.
+        let key = TxtEncryptionKey::new(&base64::Engine::encode(
+            &base64::engine::general_purpose::STANDARD,
+            KEY,
+        ))
+        .unwrap();
+        assert!(decrypt_text(ENCRYPTED, &key).is_ok());
+        assert!(encrypt_text("x", &key, "short").is_err());
+
+        let mut fresh = Heritage::new("default", None);
+        let encrypted = fresh.encrypt(&key).unwrap();
+        assert_eq!(Heritage::decrypt(&encrypted, &key).unwrap(), fresh);
+    }
+}
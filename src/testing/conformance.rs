@@ -0,0 +1,324 @@
+use std::{fmt::Display, sync::Arc};
+
+use eyre::{Result, eyre};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    changes::{Changes, FromTo},
+    endpoint::{Endpoint, RecordType},
+    provider::Provider,
+};
+
+use super::FakeExternalDns;
+
+/// Checks that a `Provider` behaves as External-DNS expects, through `FakeExternalDns`.
+///
+/// Each case is a record to create, then to update into the `to` side, then to delete.
+/// Records existing before the run are kept, and must be left untouched.
+#[derive(Debug, Clone)]
+pub struct ConformanceSuite {
+    cases: Vec<FromTo<Endpoint>>,
+}
+impl ConformanceSuite {
+    /// Constructor of `ConformanceSuite`, from explicit cases.
+    #[must_use]
+    pub const fn new(cases: Vec<FromTo<Endpoint>>) -> Self {
+        Self { cases }
+    }
+
+    /// A, CNAME and TXT (registry like) cases under `domain`.
+    #[must_use]
+    pub fn for_domain(domain: &str) -> Self {
+        let ep = |name: &str, record_type: RecordType, targets: &[&str]| Endpoint {
+            dns_name: Some(format!("{name}.{domain}")),
+            targets: Some(targets.iter().map(ToString::to_string).collect()),
+            record_type: Some(record_type),
+            set_identifier: None,
+            record_ttl: None,
+            labels: None,
+            provider_specific: None,
+        };
+        let heritage = |resource: &str| {
+            format!(
+                "\"heritage=external-dns,external-dns/owner=conformance,external-dns/resource={resource}\""
+            )
+        };
+        Self::new(vec![
+            FromTo {
+                from: ep("conformance-a", RecordType::A, &["192.0.2.1"]),
+                to: ep("conformance-a", RecordType::A, &["192.0.2.2", "192.0.2.3"]),
+            },
+            FromTo {
+                from: ep(
+                    "conformance-cname",
+                    RecordType::CNAME,
+                    &[&format!("conformance-a.{domain}")],
+                ),
+                to: ep(
+                    "conformance-cname",
+                    RecordType::CNAME,
+                    &[&format!("conformance-other.{domain}")],
+                ),
+            },
+            FromTo {
+                from: ep(
+                    "a-conformance-a",
+                    RecordType::TXT,
+                    &[&heritage("ingress/default/conformance")],
+                ),
+                to: ep(
+                    "a-conformance-a",
+                    RecordType::TXT,
+                    &[&heritage("ingress/default/conformance-updated")],
+                ),
+            },
+        ])
+    }
+
+    /// Keep only the cases of the record types the provider supports.
+    #[must_use]
+    pub fn retain_types(mut self, record_types: &[RecordType]) -> Self {
+        self.cases.retain(|ft| {
+            ft.from
+                .record_type
+                .as_ref()
+                .is_some_and(|t| record_types.contains(t))
+        });
+        self
+    }
+
+    /// Run all checks against `provider`, in order.
+    /// Later checks are skipped once one fails, since they depend on the state left by the former.
+    #[instrument(skip_all)]
+    pub async fn run(&self, provider: Arc<dyn Provider>) -> ConformanceReport {
+        let external_dns = FakeExternalDns::new(provider);
+        let mut report = ConformanceReport::default();
+
+        // As External-DNS, negotiate before anything else.
+        report.push("negotiate", external_dns.negotiate().await.map(|_| ()));
+        let initial = if report.passed() {
+            match external_dns.records().await {
+                Ok(x) => x,
+                Err(e) => {
+                    report.push("records", Err(e));
+                    return report;
+                }
+            }
+        } else {
+            vec![]
+        };
+        let created: Vec<_> = self.cases.iter().map(|ft| ft.from.clone()).collect();
+        let updated: Vec<_> = self.cases.iter().map(|ft| ft.to.clone()).collect();
+
+        let checks: [(&'static str, Check<'_>); 5] = [
+            (
+                "adjust-endpoints-idempotent",
+                Box::pin(async {
+                    let once = external_dns.adjust_endpoints(&updated).await?;
+                    let twice = external_dns.adjust_endpoints(&once).await?;
+                    let diff = Changes::between(&once, &twice)?;
+                    if diff.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(eyre!("Adjusting adjusted endpoints changes them: {diff:?}"))
+                    }
+                }),
+            ),
+            (
+                "create",
+                Box::pin(sync_expecting(&external_dns, &initial, &created, |c| {
+                    c.create.len() == created.len() && c.update.is_empty() && c.delete.is_empty()
+                })),
+            ),
+            (
+                "update",
+                Box::pin(sync_expecting(&external_dns, &initial, &updated, |c| {
+                    c.create.is_empty() && c.update.len() == updated.len() && c.delete.is_empty()
+                })),
+            ),
+            (
+                "delete",
+                Box::pin(sync_expecting(&external_dns, &initial, &[], |c| {
+                    c.create.is_empty() && c.update.is_empty() && c.delete.len() == updated.len()
+                })),
+            ),
+            (
+                "untouched",
+                Box::pin(async {
+                    let diff = Changes::between(&external_dns.records().await?, &initial)?;
+                    if diff.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(eyre!("Records existing before the run changed: {diff:?}"))
+                    }
+                }),
+            ),
+        ];
+        for (name, check) in checks {
+            if report.passed() {
+                report.push(name, check.await);
+            } else {
+                report.skip(name);
+            }
+        }
+        report
+    }
+}
+
+type Check<'a> = std::pin::Pin<Box<dyn Future<Output = Result<()>> + 'a>>;
+
+async fn sync_expecting(
+    external_dns: &FakeExternalDns,
+    initial: &[Endpoint],
+    cases: &[Endpoint],
+    expected: impl Fn(&Changes) -> bool,
+) -> Result<()> {
+    let desired: Vec<_> = initial.iter().chain(cases).cloned().collect();
+    let changes = external_dns.sync(&desired).await?;
+    if expected(&changes) {
+        Ok(())
+    } else {
+        Err(eyre!("Unexpected changes planned: {changes:?}"))
+    }
+}
+
+/// Outcome of each check of a `ConformanceSuite` run.
+#[derive(Debug, Default)]
+pub struct ConformanceReport {
+    pub checks: Vec<(&'static str, CheckOutcome)>,
+}
+impl ConformanceReport {
+    /// If no check failed.
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|(_, o)| matches!(o, CheckOutcome::Failed(_)))
+    }
+
+    /// Turn the report into an error listing the failures, if any.
+    /// # Errors
+    ///
+    /// When any check failed.
+    pub fn into_result(self) -> Result<()> {
+        if self.passed() {
+            Ok(())
+        } else {
+            Err(eyre!("{self}"))
+        }
+    }
+
+    fn push(&mut self, name: &'static str, outcome: Result<()>) {
+        match outcome {
+            Ok(()) => {
+                info!(target: "conformance", message = format!("{name}: passed"));
+                self.checks.push((name, CheckOutcome::Passed));
+            }
+            Err(e) => {
+                warn!(target: "conformance", message = format!("{name}: {e:?}"));
+                self.checks
+                    .push((name, CheckOutcome::Failed(format!("{e:?}"))));
+            }
+        }
+    }
+
+    fn skip(&mut self, name: &'static str) {
+        self.checks.push((name, CheckOutcome::Skipped));
+    }
+}
+impl Display for ConformanceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, outcome) in &self.checks {
+            match outcome {
+                CheckOutcome::Passed => writeln!(f, "{name}: passed")?,
+                CheckOutcome::Skipped => writeln!(f, "{name}: skipped")?,
+                CheckOutcome::Failed(e) => writeln!(f, "{name}: FAILED: {e}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of a check.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Passed,
+    Failed(String),
+    Skipped,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{domain_filter::DomainFilter, providers::memory::InMemoryProvider};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[tokio::test]
+    async fn it_works() {
+        let provider = Arc::new(
+            InMemoryProvider::from_json(
+                DomainFilter::Strings {
+                    include: Some(vec!["magicloud.lan".to_string()]),
+                    exclude: None,
+                },
+                r#"[{"dnsName": "nextcloud.magicloud.lan", "targets": ["192.168.0.102"], "recordType": "A"}]"#,
+            )
+            .unwrap(),
+        );
+        let report = ConformanceSuite::for_domain("magicloud.lan")
+            .run(provider.clone())
+            .await;
+        assert_eq!(
+            report.to_string(),
+            "negotiate: passed
+adjust-endpoints-idempotent: passed
+create: passed
+update: passed
+delete: passed
+untouched: passed
+"
+        );
+        assert_eq!(provider.len(), 1);
+    }
+
+    // Accepts everything, keeps nothing. Lists records only once negotiated.
+    #[derive(Debug, Default)]
+    struct Forgetful {
+        negotiated: AtomicBool,
+    }
+    #[async_trait::async_trait]
+    impl Provider for Forgetful {
+        async fn domain_filter(&self) -> Result<DomainFilter> {
+            self.negotiated.store(true, Ordering::Relaxed);
+            Ok(DomainFilter::Strings {
+                include: None,
+                exclude: None,
+            })
+        }
+        async fn records(&self) -> Result<Vec<Endpoint>> {
+            if self.negotiated.load(Ordering::Relaxed) {
+                Ok(vec![])
+            } else {
+                Err(eyre!("Not negotiated"))
+            }
+        }
+        async fn apply_changes(&self, _: Changes) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn catches_failures() {
+        let report = ConformanceSuite::for_domain("magicloud.lan")
+            .run(Arc::new(Forgetful::default()))
+            .await;
+        assert!(!report.passed());
+        assert_eq!(report.checks[0], ("negotiate", CheckOutcome::Passed));
+        assert!(matches!(
+            report.checks[2],
+            ("create", CheckOutcome::Failed(_))
+        ));
+        assert_eq!(report.checks[3], ("update", CheckOutcome::Skipped));
+    }
+}
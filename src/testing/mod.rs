@@ -0,0 +1,194 @@
+//! Helpers to test `Provider` implementations without External-DNS.
+//!
+//! `FakeExternalDns` drives a `Provider` through the routes of `Webhook`, in process,
+//! the same way External-DNS does. `conformance::ConformanceSuite` builds on it
+//! to check that a `Provider` behaves as External-DNS expects.
+
+pub mod conformance;
+
+use std::sync::Arc;
+
+use actix_web::{
+    http::{
+        Method, StatusCode,
+        header::{ACCEPT, CONTENT_TYPE},
+    },
+    test::TestRequest,
+};
+use eyre::{Result, eyre};
+use serde::{Serialize, de::DeserializeOwned};
+use tracing::instrument;
+
+use crate::{
+    MEDIATYPE, changes::Changes, domain_filter::DomainFilter, endpoint::Endpoint,
+    provider::Provider, webhook::call_in_process,
+};
+
+/// The External-DNS side of the webhook protocol.
+///
+/// Each call goes through the same routes, guards and (de)serialization as `Webhook::start`,
+/// and checks the responses as the webhook provider of External-DNS does.
+#[derive(Debug, Clone)]
+pub struct FakeExternalDns {
+    provider: Arc<dyn Provider>,
+}
+impl FakeExternalDns {
+    /// Constructor of `FakeExternalDns`.
+    #[must_use]
+    pub fn new(provider: Arc<dyn Provider>) -> Self {
+        Self { provider }
+    }
+
+    /// `GET /`, as External-DNS does at start up.
+    /// # Errors
+    ///
+    /// When the response is not a 200 with the webhook media type and a `DomainFilter`.
+    #[instrument(skip_all)]
+    pub async fn negotiate(&self) -> Result<DomainFilter> {
+        let (status, content_type, body) = self
+            .call(
+                TestRequest::get()
+                    .uri("/")
+                    .insert_header((ACCEPT, MEDIATYPE)),
+            )
+            .await?;
+        expect_status(StatusCode::OK, status, &body)?;
+        if content_type.as_deref() != Some(MEDIATYPE) {
+            return Err(eyre!(
+                "Wrong content type in negotiation: {content_type:?}, expected {MEDIATYPE}"
+            ));
+        }
+        parse(&body)
+    }
+
+    /// `GET /records`.
+    /// # Errors
+    ///
+    /// When the response is not a 200 with a list of endpoints.
+    #[instrument(skip_all)]
+    pub async fn records(&self) -> Result<Vec<Endpoint>> {
+        let (status, _, body) = self
+            .call(
+                TestRequest::get()
+                    .uri("/records")
+                    .insert_header((ACCEPT, MEDIATYPE)),
+            )
+            .await?;
+        expect_status(StatusCode::OK, status, &body)?;
+        parse(&body)
+    }
+
+    /// `POST /adjustendpoints`.
+    /// # Errors
+    ///
+    /// When the response is not a 200 with a list of endpoints.
+    #[instrument(skip_all)]
+    pub async fn adjust_endpoints(&self, endpoints: &[Endpoint]) -> Result<Vec<Endpoint>> {
+        let (status, _, body) = self.post("/adjustendpoints", &endpoints).await?;
+        expect_status(StatusCode::OK, status, &body)?;
+        parse(&body)
+    }
+
+    /// `POST /records`.
+    /// # Errors
+    ///
+    /// When the response is not a 204.
+    #[instrument(skip_all)]
+    pub async fn apply_changes(&self, changes: &Changes) -> Result<()> {
+        let (status, _, body) = self.post("/records", changes).await?;
+        expect_status(StatusCode::NO_CONTENT, status, &body)
+    }
+
+    /// One synchronisation loop of External-DNS, making the provider hold `desired`:
+    /// negotiate, list the records, adjust `desired`, post the planned changes if any,
+    /// and list again to check that they are all there.
+    /// Returns the posted changes.
+    /// # Errors
+    ///
+    /// When any call fails, or the records listed at the end are not the adjusted `desired`.
+    #[instrument(skip_all)]
+    pub async fn sync(&self, desired: &[Endpoint]) -> Result<Changes> {
+        self.negotiate().await?;
+        let current = self.records().await?;
+        let desired = self.adjust_endpoints(desired).await?;
+        let changes = Changes::between(&current, &desired)?;
+        if !changes.is_empty() {
+            self.apply_changes(&changes).await?;
+        }
+        let left = Changes::between(&self.records().await?, &desired)?;
+        if left.is_empty() {
+            Ok(changes)
+        } else {
+            Err(eyre!(
+                "Records differ from the desired ones after sync: {left:?}"
+            ))
+        }
+    }
+
+    async fn post(
+        &self,
+        uri: &str,
+        payload: &impl Serialize,
+    ) -> Result<(StatusCode, Option<String>, Vec<u8>)> {
+        self.call(
+            TestRequest::default()
+                .method(Method::POST)
+                .uri(uri)
+                .insert_header((ACCEPT, MEDIATYPE))
+                .insert_header((CONTENT_TYPE, MEDIATYPE))
+                .set_payload(serde_json::to_vec(payload)?),
+        )
+        .await
+    }
+
+    async fn call(&self, req: TestRequest) -> Result<(StatusCode, Option<String>, Vec<u8>)> {
+        call_in_process(self.provider.clone(), req).await
+    }
+}
+
+fn expect_status(expected: StatusCode, got: StatusCode, body: &[u8]) -> Result<()> {
+    if expected == got {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "Expected status {expected}, got {got}: {}",
+            String::from_utf8_lossy(body)
+        ))
+    }
+}
+
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T> {
+    serde_json::from_slice(body).map_err(|e| {
+        eyre!(
+            "Cannot parse response ({e}): {}",
+            String::from_utf8_lossy(body)
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::memory::InMemoryProvider;
+
+    #[tokio::test]
+    async fn it_works() {
+        let provider = Arc::new(InMemoryProvider::default());
+        let external_dns = FakeExternalDns::new(provider.clone());
+        let desired: Vec<Endpoint> = serde_json::from_str(
+            r#"[
+    {"dnsName": "nextcloud.magicloud.lan", "targets": ["192.168.0.102"], "recordType": "A"},
+    {"dnsName": "gitea.magicloud.lan", "targets": ["nextcloud.magicloud.lan"], "recordType": "CNAME"}
+]"#,
+        )
+        .unwrap();
+
+        let changes = external_dns.sync(&desired).await.unwrap();
+        assert_eq!(changes.create.len(), 2);
+        assert_eq!(provider.len(), 2);
+
+        let changes = external_dns.sync(&desired[..1]).await.unwrap();
+        assert_eq!(changes.delete, desired[1..]);
+        assert!(external_dns.sync(&desired[..1]).await.unwrap().is_empty());
+    }
+}
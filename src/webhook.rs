@@ -1,15 +1,22 @@
 use crate::{
-    MEDIATYPE, domain_filter::DomainFilter, endpoint::Endpoint, provider::Provider, status::Status,
-    webhook_json::WebhookJson,
+    MEDIATYPE, domain_filter::DomainFilter, endpoint::Endpoint, provider::Provider,
+    recorder::Recorder, status::Status, webhook_json::WebhookJson,
 };
 use actix_web::{
-    App, HttpServer, ResponseError, get,
+    App, HttpServer, ResponseError, body,
+    dev::Service,
+    get,
     guard::GuardContext,
-    http::{StatusCode, header::Accept},
-    middleware::Logger,
+    http::{
+        StatusCode,
+        header::{Accept, CONTENT_TYPE},
+    },
+    middleware::{Condition, Logger, from_fn},
     post,
-    web::{Data, Json},
+    test::{self, TestRequest},
+    web::{Data, Json, PayloadConfig, ServiceConfig},
 };
+use eyre::eyre;
 use serde_json::{Value, from_value};
 use std::{fmt::Display, sync::Arc};
 use tracing::{instrument, warn};
@@ -27,6 +34,8 @@ pub struct Webhook {
     exposed_address: String,
     exposed_port: u16,
     status: Arc<dyn Status>,
+
+    recorder: Option<Arc<Recorder>>,
 }
 impl Webhook {
     /// Constructor of `Webhook`.
@@ -40,9 +49,17 @@ impl Webhook {
             exposed_address: "0.0.0.0".to_string(),
             exposed_port: 8080,
             status,
+            recorder: None,
         }
     }
 
+    /// Record every request to, and response from, the webhook server.
+    #[must_use]
+    pub fn with_recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
     /// Start the webhook server, and healthz web server.
     /// # Errors
     ///
@@ -60,15 +77,22 @@ impl Webhook {
         .run();
 
         let x = self.dns_manager.clone();
+        let recorder = self.recorder.clone();
         let provider = HttpServer::new(move || {
             App::new()
+                .wrap(Condition::new(
+                    recorder.is_some(),
+                    from_fn(crate::recorder::record),
+                ))
                 .wrap(Logger::default())
                 .wrap(TracingLogger::default())
-                .app_data(Data::new(x.clone()))
-                .service(get_root)
-                .service(get_records)
-                .service(post_records)
-                .service(post_adjustendpoints)
+                .configure(|cfg| {
+                    if let Some(recorder) = &recorder {
+                        cfg.app_data(Data::new(recorder.clone()))
+                            .app_data(PayloadConfig::new(crate::recorder::BODY_LIMIT));
+                    }
+                })
+                .configure(provider_services(x.clone()))
         })
         .workers(4)
         .bind((self.provider_address.clone(), self.provider_port))?
@@ -81,6 +105,38 @@ impl Webhook {
     }
 }
 
+/// Routes of the webhook server, for `App::configure`.
+pub fn provider_services(dns_manager: Arc<dyn Provider>) -> impl FnOnce(&mut ServiceConfig) {
+    move |cfg| {
+        cfg.app_data(Data::new(dns_manager))
+            .service(get_root)
+            .service(get_records)
+            .service(post_records)
+            .service(post_adjustendpoints);
+    }
+}
+
+/// Send a request through the routes of the webhook server, in process.
+/// Returns the status, content type and body of the response.
+pub async fn call_in_process(
+    dns_manager: Arc<dyn Provider>,
+    req: TestRequest,
+) -> eyre::Result<(StatusCode, Option<String>, Vec<u8>)> {
+    let app = test::init_service(App::new().configure(provider_services(dns_manager))).await;
+    let res = app.call(req.to_request()).await.map_err(|e| eyre!("{e}"))?;
+    let status = res.status();
+    let content_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string);
+    let body = body::to_bytes(res.into_body())
+        .await
+        .map_err(|e| eyre!("Cannot read response body: {e}"))?
+        .to_vec();
+    Ok((status, content_type, body))
+}
+
 // Initialisation and negotiates headers and returns domain filter.
 // Returns 200/500
 #[get("/", guard = "media_type_guard")]
@@ -159,6 +215,20 @@ async fn get_healthz(status: Data<Arc<dyn Status>>) -> (String, StatusCode) {
     status.healthz().await
 }
 
+/// An error for `Provider`s to answer External-DNS with a client error status,
+/// such as 422, instead of 500.
+#[derive(Debug)]
+pub struct Rejected {
+    pub status: StatusCode,
+    pub reason: String,
+}
+impl Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.reason)
+    }
+}
+impl std::error::Error for Rejected {}
+
 #[derive(Debug)]
 struct ErrorWraper(eyre::Error);
 impl Display for ErrorWraper {
@@ -166,4 +236,11 @@ impl Display for ErrorWraper {
         f.write_fmt(format_args!("{}", self.0))
     }
 }
-impl ResponseError for ErrorWraper {}
+impl ResponseError for ErrorWraper {
+    fn status_code(&self) -> StatusCode {
+        self.0
+            .chain()
+            .find_map(|e| e.downcast_ref::<Rejected>())
+            .map_or(StatusCode::INTERNAL_SERVER_ERROR, |r| r.status)
+    }
+}
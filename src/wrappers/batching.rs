@@ -0,0 +1,405 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use tokio::sync::oneshot;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    changes::{Changes, FromTo},
+    domain_filter::DomainFilter,
+    endpoint::{Endpoint, RecordKey},
+    provider::Provider,
+};
+
+/// Buffers the changes External-DNS posts for a window, and applies them as one batch,
+/// for backends limiting the rate of writes.
+///
+/// Changes to the same record are merged: a create then a delete cancel each other,
+/// update chains collapse into one update, a delete then a create become an update.
+/// Changes that cannot merge, such as two creates of the same record, go to the next batch.
+/// Each request waits for its batch. When the batch fails, or a cancelled create was for a
+/// record the backend has, the requests of the batch are applied one by one instead, in order,
+/// each getting its own result.
+#[derive(Debug)]
+pub struct Batching {
+    inner: Arc<dyn Provider>,
+    window: Duration,
+    queue: Arc<Mutex<Queue>>,
+}
+impl Batching {
+    /// Constructor of `Batching`, with the time to wait for more changes.
+    #[must_use]
+    pub fn new(inner: Arc<dyn Provider>, window: Duration) -> Self {
+        Self {
+            inner,
+            window,
+            queue: Arc::default(),
+        }
+    }
+}
+
+impl Batching {
+    // Add the changes to the last batch, or a new one. Returns if flushing must start.
+    fn enqueue(
+        &self,
+        changes: &Changes,
+        waiter: oneshot::Sender<Result<(), String>>,
+    ) -> Result<bool> {
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        let merged = match queue.batches.back_mut() {
+            Some(last) => last.merge(changes)?,
+            None => false,
+        };
+        if !merged {
+            let mut batch = Batch::default();
+            if !batch.merge(changes)? {
+                return Err(eyre!("Changes touch the same record twice: {changes:?}"));
+            }
+            queue.batches.push_back(batch);
+        }
+        if let Some(last) = queue.batches.back_mut() {
+            last.requests.push((changes.clone(), waiter));
+        }
+        let start = !queue.flushing;
+        queue.flushing = true;
+        drop(queue);
+        Ok(start)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    batches: VecDeque<Batch>,
+    flushing: bool,
+}
+
+#[derive(Debug, Default)]
+struct Batch {
+    ops: BTreeMap<RecordKey, Op>,
+    /// The records of the creates cancelled by a delete.
+    cancelled: BTreeSet<RecordKey>,
+    /// The changes of each request, and where to send its result.
+    requests: Vec<(Changes, oneshot::Sender<Result<(), String>>)>,
+}
+impl Batch {
+    /// Merge `changes`, or leave the batch untouched and return `false` on conflict.
+    fn merge(&mut self, changes: &Changes) -> Result<bool> {
+        let mut ops = self.ops.clone();
+        let mut cancelled = self.cancelled.clone();
+        let incoming = changes
+            .delete
+            .iter()
+            .map(|ep| Op::Delete(ep.clone()))
+            .chain(
+                changes
+                    .update
+                    .iter()
+                    .map(|ft| Op::Update(ft.from.clone(), ft.to.clone())),
+            )
+            .chain(changes.create.iter().map(|ep| Op::Create(ep.clone())));
+        for op in incoming {
+            let key = RecordKey::of(op.endpoint())?;
+            let merged = match ops.remove(&key) {
+                None => Merged::Op(Box::new(op)),
+                Some(old) => old.then(op),
+            };
+            match merged {
+                Merged::Op(merged) => {
+                    ops.insert(key, *merged);
+                }
+                Merged::Cancelled => {
+                    cancelled.insert(key);
+                }
+                Merged::Conflict => return Ok(false),
+            }
+        }
+        self.ops = ops;
+        self.cancelled = cancelled;
+        Ok(true)
+    }
+
+    // If the cancelled creates would have succeeded, their records not existing.
+    async fn cancellable(&self, inner: &dyn Provider) -> Result<bool> {
+        if self.cancelled.is_empty() {
+            return Ok(true);
+        }
+        Ok(!inner
+            .records()
+            .await?
+            .iter()
+            .any(|ep| RecordKey::of(ep).is_ok_and(|key| self.cancelled.contains(&key))))
+    }
+
+    fn changes(&self) -> Changes {
+        let mut ret = Changes::default();
+        for op in self.ops.values().cloned() {
+            match op {
+                Op::Create(ep) => ret.create.push(ep),
+                Op::Update(from, to) => ret.update.push(FromTo { from, to }),
+                Op::Delete(ep) => ret.delete.push(ep),
+            }
+        }
+        ret
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Create(Endpoint),
+    Update(Endpoint, Endpoint),
+    Delete(Endpoint),
+}
+impl Op {
+    const fn endpoint(&self) -> &Endpoint {
+        match self {
+            Self::Create(ep) | Self::Update(ep, _) | Self::Delete(ep) => ep,
+        }
+    }
+
+    /// The single operation doing `self` then `next`.
+    fn then(self, next: Self) -> Merged {
+        match (self, next) {
+            (Self::Create(_), Self::Delete(_)) => Merged::Cancelled,
+            (Self::Create(_), Self::Update(_, to)) => Merged::Op(Box::new(Self::Create(to))),
+            (Self::Update(from, _), Self::Update(_, to))
+            | (Self::Delete(from), Self::Create(to)) => {
+                Merged::Op(Box::new(Self::Update(from, to)))
+            }
+            (Self::Update(from, _), Self::Delete(_)) => Merged::Op(Box::new(Self::Delete(from))),
+            _ => Merged::Conflict,
+        }
+    }
+}
+
+enum Merged {
+    Op(Box<Op>),
+    Cancelled,
+    /// The operations cannot follow each other.
+    Conflict,
+}
+
+// The next batch to apply, or the end of the flushing when there is none.
+fn next_batch(queue: &Mutex<Queue>) -> Option<Batch> {
+    let mut queue = queue.lock().unwrap_or_else(PoisonError::into_inner);
+    let ret = queue.batches.pop_front();
+    queue.flushing = ret.is_some();
+    drop(queue);
+    ret
+}
+
+async fn flush(inner: Arc<dyn Provider>, queue: Arc<Mutex<Queue>>, window: Duration) {
+    tokio::time::sleep(window).await;
+    while let Some(batch) = next_batch(&queue) {
+        apply(inner.as_ref(), batch).await;
+    }
+}
+
+async fn apply(inner: &dyn Provider, batch: Batch) {
+    let changes = batch.changes();
+    info!(target: "batching", message = format!(
+        "Applying {} requests as one batch: {}", batch.requests.len(), changes.summary()
+    ));
+    let result = match batch.cancellable(inner).await {
+        Ok(true) if changes.is_empty() => Ok(()),
+        Ok(true) => inner.apply_changes(changes).await,
+        Ok(false) => Err(eyre!("A cancelled create is for an existing record")),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => {
+            for (_, waiter) in batch.requests {
+                // The request may be gone, nothing to tell then.
+                let _ = waiter.send(Ok(()));
+            }
+        }
+        Err(e) if batch.requests.len() == 1 && batch.cancelled.is_empty() => {
+            for (_, waiter) in batch.requests {
+                let _ = waiter.send(Err(format!("{e:?}")));
+            }
+        }
+        Err(e) => {
+            warn!(target: "batching", message = format!(
+                "Applying the {} requests of the batch one by one: {e}", batch.requests.len()
+            ));
+            for (changes, waiter) in batch.requests {
+                let result = inner
+                    .apply_changes(changes)
+                    .await
+                    .map_err(|e| format!("{e:?}"));
+                let _ = waiter.send(result);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for Batching {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        self.inner.domain_filter().await
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        self.inner.records().await
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        if self.enqueue(&changes, tx)? {
+            // Spawned, so the batch goes on even if the request starting it is dropped.
+            tokio::spawn(flush(self.inner.clone(), self.queue.clone(), self.window));
+        }
+
+        rx.await
+            .map_err(|_| eyre!("The batch was dropped"))?
+            .map_err(|e| eyre!(e))
+    }
+
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        self.inner.adjust_endpoints(endpoints).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::ep;
+    use crate::{endpoint::RecordType, providers::memory::InMemoryProvider};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Counts the batches reaching the backend.
+    #[derive(Debug, Default)]
+    struct Counting {
+        inner: InMemoryProvider,
+        calls: AtomicUsize,
+    }
+    #[async_trait]
+    impl Provider for Counting {
+        async fn domain_filter(&self) -> Result<DomainFilter> {
+            self.inner.domain_filter().await
+        }
+        async fn records(&self) -> Result<Vec<Endpoint>> {
+            self.inner.records().await
+        }
+        async fn apply_changes(&self, changes: Changes) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.inner.apply_changes(changes).await
+        }
+    }
+
+    #[tokio::test]
+    async fn it_works() {
+        let backend = Arc::new(Counting::default());
+        backend
+            .inner
+            .seed([ep("old.magicloud.lan", RecordType::A, &["192.168.0.100"])])
+            .unwrap();
+        let batching = Batching::new(backend.clone(), Duration::from_millis(50));
+
+        let create = Changes {
+            create: vec![
+                ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]),
+                ep("tmp.magicloud.lan", RecordType::A, &["192.168.0.109"]),
+            ],
+            ..Changes::default()
+        };
+        let update = |from: &str, to: &str| Changes {
+            update: vec![FromTo {
+                from: ep("nextcloud.magicloud.lan", RecordType::A, &[from]),
+                to: ep("nextcloud.magicloud.lan", RecordType::A, &[to]),
+            }],
+            ..Changes::default()
+        };
+        let delete = Changes {
+            delete: vec![
+                ep("tmp.magicloud.lan", RecordType::A, &["192.168.0.109"]),
+                ep("old.magicloud.lan", RecordType::A, &["192.168.0.100"]),
+            ],
+            ..Changes::default()
+        };
+        // A second create of the same record cannot merge: it goes to a second batch, and fails.
+        let again = Changes {
+            create: vec![ep(
+                "nextcloud.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.200"],
+            )],
+            ..Changes::default()
+        };
+        let (r1, r2, r3, r4, r5) = tokio::join!(
+            batching.apply_changes(create),
+            batching.apply_changes(update("192.168.0.102", "192.168.0.103")),
+            batching.apply_changes(update("192.168.0.103", "192.168.0.104")),
+            batching.apply_changes(delete),
+            batching.apply_changes(again),
+        );
+        assert!(r1.is_ok() && r2.is_ok() && r3.is_ok() && r4.is_ok());
+        assert!(r5.is_err());
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            backend.inner.endpoints(),
+            vec![ep(
+                "nextcloud.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.104"]
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn isolates_failures() {
+        let backend = Arc::new(Counting::default());
+        let old = ep("old.magicloud.lan", RecordType::A, &["192.168.0.100"]);
+        backend.inner.seed([old.clone()]).unwrap();
+        let batching = Batching::new(backend.clone(), Duration::from_millis(50));
+
+        let create = |ep: Endpoint| Changes {
+            create: vec![ep],
+            ..Changes::default()
+        };
+        let missing = ep("missing.magicloud.lan", RecordType::A, &["192.168.0.101"]);
+        let (r1, r2, r3, r4, r5) = tokio::join!(
+            // Creating an existing record fails, even when deleted afterwards.
+            batching.apply_changes(create(old.clone())),
+            batching.apply_changes(Changes {
+                delete: vec![old],
+                ..Changes::default()
+            }),
+            batching.apply_changes(create(ep(
+                "new.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.102"]
+            ))),
+            batching.apply_changes(Changes {
+                update: vec![FromTo {
+                    from: missing.clone(),
+                    to: missing,
+                }],
+                ..Changes::default()
+            }),
+            batching.apply_changes(create(ep(
+                "other.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.103"]
+            ))),
+        );
+        assert!(r1.is_err() && r4.is_err());
+        assert!(r2.is_ok() && r3.is_ok() && r5.is_ok());
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 5);
+        assert_eq!(
+            backend.inner.endpoints(),
+            vec![
+                ep("new.magicloud.lan", RecordType::A, &["192.168.0.102"]),
+                ep("other.magicloud.lan", RecordType::A, &["192.168.0.103"]),
+            ]
+        );
+    }
+}
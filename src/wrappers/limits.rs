@@ -0,0 +1,364 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use actix_web::http::StatusCode;
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use tracing::{instrument, warn};
+
+use crate::{
+    changes::Changes, domain_filter::DomainFilter, endpoint::Endpoint, provider::Provider,
+    webhook::Rejected,
+};
+
+/// Thresholds above which a batch of changes is refused. `None` is no limit.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChangeLimits {
+    /// Deleted records in one batch.
+    pub max_deletes: Option<usize>,
+    /// Deleted records in one batch, in percent of the current records.
+    pub max_delete_percent: Option<f64>,
+    /// Creates, updates and deletes in one batch.
+    pub max_changes: Option<usize>,
+    /// Refused or failed batches in a row opening the circuit breaker.
+    pub trip_after: Option<usize>,
+}
+
+/// The token, in the approval file, closing the circuit breaker.
+pub const RESET_TOKEN: &str = "reset";
+
+/// Refuses batches of changes exceeding `ChangeLimits`, so a bad rollout cannot wipe the zone.
+///
+/// Refused batches get a 422 and are logged, with an approval token. Writing the token into
+/// the approval file lets that very batch through once, when External-DNS retries it;
+/// once the batch is applied, the token is removed from the file, which is removed once empty.
+///
+/// With `trip_after`, as many refused or failed batches in a row open a circuit breaker:
+/// every batch is then refused, until `reset`, or until `RESET_TOKEN` is written into
+/// the approval file.
+#[derive(Debug)]
+pub struct ChangeLimitGuard {
+    inner: Arc<dyn Provider>,
+    limits: ChangeLimits,
+    approval_file: Option<PathBuf>,
+    breaker: Mutex<Breaker>,
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    /// Refused or failed batches in a row.
+    failures: usize,
+    open: bool,
+}
+impl ChangeLimitGuard {
+    /// Constructor of `ChangeLimitGuard`, without approval file.
+    #[must_use]
+    pub fn new(inner: Arc<dyn Provider>, limits: ChangeLimits) -> Self {
+        Self {
+            inner,
+            limits,
+            approval_file: None,
+            breaker: Mutex::default(),
+        }
+    }
+
+    /// If the circuit breaker is open.
+    #[must_use]
+    pub fn is_open(&self) -> bool {
+        self.breaker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .open
+    }
+
+    /// Close the circuit breaker.
+    pub fn reset(&self) {
+        *self.breaker.lock().unwrap_or_else(PoisonError::into_inner) = Breaker::default();
+    }
+
+    // Count a refused or failed batch, or a successful one.
+    fn count(&self, failed: bool) {
+        let mut breaker = self.breaker.lock().unwrap_or_else(PoisonError::into_inner);
+        breaker.failures = if failed { breaker.failures + 1 } else { 0 };
+        let failures = breaker.failures;
+        let trips = !breaker.open && self.limits.trip_after.is_some_and(|max| failures >= max);
+        breaker.open |= trips;
+        drop(breaker);
+        if trips {
+            warn!(target: "limits", message = format!(
+                "Circuit breaker open after {failures} refused or failed batches"
+            ));
+        }
+    }
+
+    /// The file to write approval tokens into.
+    #[must_use]
+    pub fn with_approval_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.approval_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    async fn violations(&self, changes: &Changes) -> Result<Vec<String>> {
+        let mut ret = Vec::new();
+        let deletes = changes.delete.len();
+        let total = changes.create.len() + changes.update.len() + deletes;
+        if let Some(max) = self.limits.max_deletes.filter(|max| deletes > *max) {
+            ret.push(format!("{deletes} deletes, more than {max}"));
+        }
+        if let Some(max) = self.limits.max_changes.filter(|max| total > *max) {
+            ret.push(format!("{total} changes, more than {max}"));
+        }
+        if let Some(max) = self.limits.max_delete_percent
+            && deletes > 0
+        {
+            let current = self.inner.records().await?.len();
+            #[allow(clippy::cast_precision_loss)]
+            let percent = 100.0 * deletes as f64 / current.max(1) as f64;
+            if percent > max {
+                ret.push(format!(
+                    "{deletes} deletes of {current} records ({percent:.1}%), more than {max}%"
+                ));
+            }
+        }
+        Ok(ret)
+    }
+
+    // If the approval file holds the token.
+    fn approved(&self, token: &str) -> bool {
+        self.approval_file
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .is_some_and(|content| content.lines().any(|l| l.trim() == token))
+    }
+
+    // Remove the token from the approval file, and the file once empty.
+    fn consume(&self, token: &str) -> Result<()> {
+        let Some(path) = &self.approval_file else {
+            return Ok(());
+        };
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| eyre!("Cannot read {}: {e}", path.display()))?;
+        let mut lines: Vec<_> = content.lines().collect();
+        let Some(at) = lines.iter().position(|l| l.trim() == token) else {
+            return Err(eyre!("Token {token} is gone from {}", path.display()));
+        };
+        // The other tokens are for other batches.
+        lines.remove(at);
+        let remaining: String = lines.iter().flat_map(|l| [*l, "\n"]).collect();
+        if remaining.trim().is_empty() {
+            std::fs::remove_file(path)
+        } else {
+            std::fs::write(path, remaining)
+        }
+        .map_err(|e| eyre!("Cannot remove token {token} from {}: {e}", path.display()))
+    }
+
+    fn refuse(reason: String) -> Result<()> {
+        warn!(target: "limits", message = reason);
+        Err(Rejected {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            reason,
+        }
+        .into())
+    }
+}
+
+/// A token identifying the content of a batch, whatever the order of its changes.
+#[must_use]
+pub fn approval_token(changes: &Changes) -> String {
+    let line = |action: &str, ep: &Endpoint| {
+        let mut targets = ep.targets.clone().unwrap_or_default();
+        targets.sort();
+        format!(
+            "{action} {} {:?} {:?} {}",
+            ep.dns_name.as_deref().unwrap_or_default(),
+            ep.record_type,
+            ep.set_identifier,
+            targets.join(",")
+        )
+    };
+    let mut lines: Vec<_> = changes
+        .create
+        .iter()
+        .map(|ep| line("create", ep))
+        .chain(
+            changes
+                .update
+                .iter()
+                .map(|ft| format!("{} -> {}", line("update", &ft.from), line("", &ft.to))),
+        )
+        .chain(changes.delete.iter().map(|ep| line("delete", ep)))
+        .collect();
+    lines.sort();
+    // FNV-1a, stable across builds and restarts.
+    let hash = lines
+        .iter()
+        .flat_map(|l| l.bytes().chain([b'\n']))
+        .fold(0xcbf2_9ce4_8422_2325_u64, |h, b| {
+            (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{hash:016x}")
+}
+
+#[async_trait]
+impl Provider for ChangeLimitGuard {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        self.inner.domain_filter().await
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        self.inner.records().await
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        if self.is_open() {
+            if !self.approved(RESET_TOKEN) {
+                return Self::refuse(format!(
+                    "Circuit breaker open. To reset it, write {RESET_TOKEN} into the approval file"
+                ));
+            }
+            self.consume(RESET_TOKEN)?;
+            self.reset();
+            warn!(target: "limits", message = "Circuit breaker reset");
+        }
+        let violations = self.violations(&changes).await?;
+        let token = approval_token(&changes);
+        let approved = !violations.is_empty() && self.approved(&token);
+        if approved {
+            warn!(target: "limits", message = format!(
+                "Applying approved batch {token}: {}", violations.join(", ")
+            ));
+        } else if !violations.is_empty() {
+            self.count(true);
+            return Self::refuse(format!(
+                "Batch refused, {}. To approve it, write {token} into the approval file",
+                violations.join(", ")
+            ));
+        }
+        let result = self.inner.apply_changes(changes).await;
+        self.count(result.is_err());
+        result?;
+        // Only once applied, so that a failed batch stays approved for its retry.
+        if approved {
+            self.consume(&token)?;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        self.inner.adjust_endpoints(endpoints).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::ep;
+    use crate::{
+        MEDIATYPE, endpoint::RecordType, providers::memory::InMemoryProvider,
+        webhook::call_in_process,
+    };
+    use actix_web::{http::header::CONTENT_TYPE, test::TestRequest};
+
+    fn a(i: usize) -> Endpoint {
+        ep(
+            &format!("host{i}.magicloud.lan"),
+            RecordType::A,
+            &[&format!("192.168.0.{i}")],
+        )
+    }
+
+    #[tokio::test]
+    async fn it_works() {
+        let inner = Arc::new(InMemoryProvider::default());
+        inner.seed((0..10).map(a)).unwrap();
+        let approval = std::env::temp_dir().join(format!("approval-{}", std::process::id()));
+        let guard = Arc::new(
+            ChangeLimitGuard::new(
+                inner.clone(),
+                ChangeLimits {
+                    max_deletes: Some(5),
+                    max_delete_percent: Some(30.0),
+                    max_changes: None,
+                    trip_after: Some(2),
+                },
+            )
+            .with_approval_file(&approval),
+        );
+
+        let small = Changes {
+            delete: vec![a(0), a(1)],
+            ..Changes::default()
+        };
+        guard.apply_changes(small).await.unwrap();
+        assert_eq!(inner.len(), 8);
+
+        // 3 of 8 is above 30%. External-DNS gets a 422.
+        let large = Changes {
+            delete: vec![a(2), a(3), a(4)],
+            ..Changes::default()
+        };
+        let (status, _, body) = call_in_process(
+            guard.clone(),
+            TestRequest::post()
+                .uri("/records")
+                .insert_header((CONTENT_TYPE, MEDIATYPE))
+                .set_json(&large),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(String::from_utf8_lossy(&body).contains("more than 30%"));
+        assert_eq!(inner.len(), 8);
+
+        // Approved once, whatever the order.
+        let reordered = Changes {
+            delete: vec![a(4), a(3), a(2)],
+            ..Changes::default()
+        };
+        std::fs::write(&approval, format!("other\n{}\n", approval_token(&large))).unwrap();
+        guard.apply_changes(reordered).await.unwrap();
+        assert_eq!(inner.len(), 5);
+        assert_eq!(std::fs::read_to_string(&approval).unwrap(), "other\n");
+
+        // The file goes with its last token.
+        let more = Changes {
+            delete: vec![a(5), a(6)],
+            ..Changes::default()
+        };
+        std::fs::write(&approval, approval_token(&more)).unwrap();
+        guard.apply_changes(more).await.unwrap();
+        assert_eq!(inner.len(), 3);
+        assert!(!approval.exists());
+
+        // A failed batch keeps its approval for the retry. Two failures in a row trip the breaker.
+        let failing = Changes {
+            delete: vec![a(7), a(42)],
+            ..Changes::default()
+        };
+        std::fs::write(&approval, approval_token(&failing)).unwrap();
+        for _ in 0..2 {
+            assert!(guard.apply_changes(failing.clone()).await.is_err());
+            assert!(approval.exists());
+        }
+        assert!(guard.is_open());
+        let small = Changes {
+            create: vec![a(50)],
+            ..Changes::default()
+        };
+        assert!(guard.apply_changes(small.clone()).await.is_err());
+        assert_eq!(inner.len(), 3);
+
+        std::fs::write(&approval, format!("{RESET_TOKEN}\n")).unwrap();
+        guard.apply_changes(small).await.unwrap();
+        assert!(!guard.is_open());
+        assert_eq!(inner.len(), 4);
+        assert!(!approval.exists());
+    }
+}
@@ -0,0 +1,8 @@
+//! `Provider`s wrapping another `Provider`, to add safety or behaviours to any implementation.
+
+pub mod batching;
+pub mod limits;
+pub mod ownership;
+pub mod policy;
+pub mod protected;
+pub mod reload;
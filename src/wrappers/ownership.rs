@@ -0,0 +1,345 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use tracing::{instrument, warn};
+
+use crate::{
+    changes::Changes,
+    domain_filter::DomainFilter,
+    endpoint::{Endpoint, RecordKey, RecordType},
+    provider::Provider,
+    registry::{Heritage, Owned, TxtNaming, pair},
+};
+
+/// What `OwnershipGuard` does with changes touching records owned by someone else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Refusal {
+    /// Fail the whole batch, applying nothing.
+    #[default]
+    Reject,
+    /// Apply the batch without the refused changes.
+    Drop,
+}
+
+/// Keeps an External-DNS instance to its own records, when several instances with
+/// different `--txt-owner-id` share a DNS backend.
+///
+/// Updates and deletes are refused when the record is owned by another owner, according to
+/// the TXT registry records of the backend, or to the `owner` label of the endpoint
+/// External-DNS sent when the backend does not have the record. Updates are refused as well
+/// when they would replace another owner's record. TXT registry records of other owners are
+/// refused the same way. Creations are let through.
+#[derive(Debug)]
+pub struct OwnershipGuard {
+    inner: Arc<dyn Provider>,
+    owner: String,
+    naming: TxtNaming,
+    refusal: Refusal,
+    allow_unowned: bool,
+    #[cfg(feature = "txt-encryption")]
+    key: Option<crate::registry::encryption::TxtEncryptionKey>,
+}
+impl OwnershipGuard {
+    /// Constructor of `OwnershipGuard`, guarding `inner` for the `--txt-owner-id` `owner`.
+    /// It rejects refused batches, and refuses records without an owner.
+    #[must_use]
+    pub fn new(inner: Arc<dyn Provider>, owner: &str) -> Self {
+        Self {
+            inner,
+            owner: owner.to_string(),
+            naming: TxtNaming::default(),
+            refusal: Refusal::default(),
+            allow_unowned: false,
+            #[cfg(feature = "txt-encryption")]
+            key: None,
+        }
+    }
+
+    /// The TXT registry naming of the External-DNS instances, to find the owners in the backend.
+    #[must_use]
+    pub fn with_naming(mut self, naming: TxtNaming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Set what to do with refused changes.
+    #[must_use]
+    pub const fn with_refusal(mut self, refusal: Refusal) -> Self {
+        self.refusal = refusal;
+        self
+    }
+
+    /// Let changes to records without any known owner through, such as records
+    /// created before the TXT registry was in use.
+    #[must_use]
+    pub const fn with_unowned_allowed(mut self, allow_unowned: bool) -> Self {
+        self.allow_unowned = allow_unowned;
+        self
+    }
+
+    /// The `--txt-encrypt-aes-key`, when the TXT registry is encrypted.
+    #[cfg(feature = "txt-encryption")]
+    #[must_use]
+    pub const fn with_encryption_key(
+        mut self,
+        key: crate::registry::encryption::TxtEncryptionKey,
+    ) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    fn pair<'a>(&self, records: &'a [Endpoint]) -> Vec<Owned<'a>> {
+        #[cfg(feature = "txt-encryption")]
+        if let Some(key) = &self.key {
+            return crate::registry::pair_encrypted(records, &self.naming, key);
+        }
+        pair(records, &self.naming)
+    }
+
+    // The owner written in a TXT registry record.
+    #[cfg_attr(not(feature = "txt-encryption"), allow(clippy::unused_self))]
+    fn txt_owner(&self, endpoint: &Endpoint) -> Option<String> {
+        if endpoint.record_type != Some(RecordType::TXT) {
+            return None;
+        }
+        let target = endpoint.targets.as_ref()?.first()?;
+        #[cfg(feature = "txt-encryption")]
+        if let Some(key) = &self.key {
+            return Heritage::decrypt(target, key)
+                .ok()?
+                .owner()
+                .map(ToString::to_string);
+        }
+        let heritage: Heritage = target.parse().ok()?;
+        heritage.owner().map(ToString::to_string)
+    }
+
+    // The owner the TXT registry of the backend gives one of its records.
+    fn record_owner(&self, record: &Endpoint, current: &[Owned<'_>]) -> Option<String> {
+        self.txt_owner(record).or_else(|| {
+            current
+                .iter()
+                .find(|o| std::ptr::eq(o.record, record))
+                .and_then(|o| o.owner().map(ToString::to_string))
+        })
+    }
+
+    /// Why the change to `endpoint` is refused, if it is.
+    fn refusal_reason(
+        &self,
+        endpoint: &Endpoint,
+        records: &[Endpoint],
+        current: &[Owned<'_>],
+    ) -> Option<String> {
+        let owner = backend_record(endpoint, records).map_or_else(
+            || owner_label(endpoint).or_else(|| self.txt_owner(endpoint)),
+            |record| self.record_owner(record, current),
+        );
+        match owner {
+            Some(owner) if owner == self.owner => None,
+            Some(owner) => Some(format!("owned by {owner}")),
+            None if self.allow_unowned => None,
+            None => Some("without owner".to_string()),
+        }
+    }
+}
+
+// The record of the backend with the key of `endpoint`.
+fn backend_record<'a>(endpoint: &Endpoint, records: &'a [Endpoint]) -> Option<&'a Endpoint> {
+    let key = RecordKey::of(endpoint).ok()?;
+    records
+        .iter()
+        .find(|r| RecordKey::of(r).is_ok_and(|k| k == key))
+}
+
+// The owner External-DNS read from its registry, when it sent the label.
+fn owner_label(endpoint: &Endpoint) -> Option<String> {
+    endpoint
+        .typed_labels()
+        .owner()
+        .filter(|o| !o.is_empty())
+        .map(ToString::to_string)
+}
+
+#[async_trait]
+impl Provider for OwnershipGuard {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        self.inner.domain_filter().await
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        self.inner.records().await
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, mut changes: Changes) -> Result<()> {
+        let records = if changes.update.is_empty() && changes.delete.is_empty() {
+            vec![]
+        } else {
+            self.inner.records().await?
+        };
+        let current = self.pair(&records);
+
+        let mut refused = Vec::new();
+        let mut check = |action: &str, endpoint: &Endpoint| {
+            let reason = self.refusal_reason(endpoint, &records, &current);
+            if let Some(reason) = &reason {
+                warn!(target: "ownership", message = format!(
+                    "Refusing to {action} {} {:?}, {reason}, not by {}",
+                    endpoint.dns_name.as_deref().unwrap_or_default(),
+                    endpoint.record_type,
+                    self.owner
+                ));
+                refused.push(format!(
+                    "{action} {}",
+                    endpoint.dns_name.as_deref().unwrap_or_default()
+                ));
+            }
+            reason.is_none()
+        };
+        changes.update.retain(|ft| {
+            // Renaming onto an existing record replaces it, whoever the update comes from.
+            let replaced = RecordKey::of(&ft.to).ok() != RecordKey::of(&ft.from).ok()
+                && backend_record(&ft.to, &records).is_some();
+            check("update", &ft.from) && (!replaced || check("update", &ft.to))
+        });
+        changes.delete.retain(|ep| check("delete", ep));
+
+        if refused.is_empty() || self.refusal == Refusal::Drop {
+            self.inner.apply_changes(changes).await
+        } else {
+            Err(eyre!(
+                "Changes to records not owned by {}: {}",
+                self.owner,
+                refused.join(", ")
+            ))
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        self.inner.adjust_endpoints(endpoints).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::ep;
+    use crate::{changes::FromTo, labels::Labels, providers::memory::InMemoryProvider};
+
+    const SEED: &str = r#"[
+    {"dnsName": "nextcloud.magicloud.lan", "targets": ["192.168.0.102"], "recordType": "A"},
+    {"dnsName": "a-nextcloud.magicloud.lan", "targets": ["\"heritage=external-dns,external-dns/owner=default\""], "recordType": "TXT"},
+    {"dnsName": "gitea.magicloud.lan", "targets": ["192.168.0.103"], "recordType": "A"},
+    {"dnsName": "a-gitea.magicloud.lan", "targets": ["\"heritage=external-dns,external-dns/owner=other\""], "recordType": "TXT"},
+    {"dnsName": "legacy.magicloud.lan", "targets": ["192.168.0.104"], "recordType": "A"}
+]"#;
+
+    fn inner() -> Arc<InMemoryProvider> {
+        let ret = Arc::new(InMemoryProvider::default());
+        ret.seed_json(SEED).unwrap();
+        ret
+    }
+
+    #[tokio::test]
+    async fn it_works() {
+        let inner = inner();
+        let guard = OwnershipGuard::new(inner.clone(), "default");
+        let nextcloud = ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]);
+        let gitea = ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"]);
+
+        // Own record, known from the registry in the backend.
+        guard
+            .apply_changes(Changes {
+                update: vec![FromTo {
+                    from: nextcloud.clone(),
+                    to: ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.105"]),
+                }],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            inner.get("nextcloud.magicloud.lan", &RecordType::A)[0].targets,
+            Some(vec!["192.168.0.105".to_string()])
+        );
+
+        // Another owner's record and its registry record: nothing is applied.
+        let err = guard
+            .apply_changes(Changes {
+                delete: vec![
+                    gitea.clone(),
+                    ep(
+                        "a-gitea.magicloud.lan",
+                        RecordType::TXT,
+                        &["\"heritage=external-dns,external-dns/owner=other\""],
+                    ),
+                    ep("legacy.magicloud.lan", RecordType::A, &["192.168.0.104"]),
+                ],
+                ..Changes::default()
+            })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("delete gitea.magicloud.lan"));
+        assert!(err.to_string().contains("delete legacy.magicloud.lan"));
+        assert_eq!(inner.len(), 5);
+
+        // The backend takes precedence over the label External-DNS sends.
+        let mut labeled = gitea.clone();
+        let mut labels = Labels::new();
+        labels.set_owner("default");
+        labeled.set_typed_labels(labels);
+        assert!(
+            guard
+                .apply_changes(Changes {
+                    delete: vec![labeled],
+                    ..Changes::default()
+                })
+                .await
+                .is_err()
+        );
+
+        // Nor can an own record replace another owner's one.
+        let mut renamed = gitea;
+        renamed.targets = Some(vec!["192.168.0.105".to_string()]);
+        assert!(
+            guard
+                .apply_changes(Changes {
+                    update: vec![FromTo {
+                        from: ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.105"]),
+                        to: renamed,
+                    }],
+                    ..Changes::default()
+                })
+                .await
+                .is_err()
+        );
+        assert!(inner.contains("nextcloud.magicloud.lan", &RecordType::A));
+    }
+
+    #[tokio::test]
+    async fn drops_refused() {
+        let inner = inner();
+        let guard = OwnershipGuard::new(inner.clone(), "default")
+            .with_refusal(Refusal::Drop)
+            .with_unowned_allowed(true);
+        guard
+            .apply_changes(Changes {
+                create: vec![ep("wiki.magicloud.lan", RecordType::A, &["192.168.0.106"])],
+                delete: vec![
+                    ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"]),
+                    ep("legacy.magicloud.lan", RecordType::A, &["192.168.0.104"]),
+                ],
+                ..Changes::default()
+            })
+            .await
+            .unwrap();
+        assert!(inner.contains("wiki.magicloud.lan", &RecordType::A));
+        assert!(inner.contains("gitea.magicloud.lan", &RecordType::A));
+        assert!(!inner.contains("legacy.magicloud.lan", &RecordType::A));
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use tracing::{instrument, warn};
+
+use crate::{
+    changes::Changes, domain_filter::DomainFilter, endpoint::Endpoint, provider::Provider,
+};
+
+/// The `--policy` of External-DNS, enforced on the webhook side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Policy {
+    /// Everything is applied.
+    #[default]
+    Sync,
+    /// Deletes are suppressed.
+    UpsertOnly,
+    /// Deletes and updates are suppressed.
+    CreateOnly,
+}
+/// Parses the `--policy` values of External-DNS.
+impl FromStr for Policy {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sync" => Ok(Self::Sync),
+            "upsert-only" => Ok(Self::UpsertOnly),
+            "create-only" => Ok(Self::CreateOnly),
+            _ => Err(eyre!("Unknown policy {s}")),
+        }
+    }
+}
+impl Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Sync => "sync",
+            Self::UpsertOnly => "upsert-only",
+            Self::CreateOnly => "create-only",
+        })
+    }
+}
+
+/// Counts of changes suppressed by a `PolicyGuard` since it started.
+#[derive(Debug, Default)]
+pub struct Suppressed {
+    updates: AtomicU64,
+    deletes: AtomicU64,
+}
+impl Suppressed {
+    #[must_use]
+    pub fn updates(&self) -> u64 {
+        self.updates.load(Ordering::Relaxed)
+    }
+
+    #[must_use]
+    pub fn deletes(&self) -> u64 {
+        self.deletes.load(Ordering::Relaxed)
+    }
+}
+
+/// Applies a `Policy` to the changes External-DNS posts, whatever External-DNS is told,
+/// so a misconfigured instance cannot delete or overwrite records.
+///
+/// Suppressed changes are logged. Their counts are kept in `Suppressed`, for the application
+/// to push with its metrics.
+#[derive(Debug)]
+pub struct PolicyGuard {
+    inner: Arc<dyn Provider>,
+    policy: Policy,
+    suppressed: Arc<Suppressed>,
+}
+impl PolicyGuard {
+    /// Constructor of `PolicyGuard`.
+    #[must_use]
+    pub fn new(inner: Arc<dyn Provider>, policy: Policy) -> Self {
+        Self {
+            inner,
+            policy,
+            suppressed: Arc::default(),
+        }
+    }
+
+    /// The counts of suppressed changes, shared with the guard.
+    #[must_use]
+    pub fn suppressed(&self) -> Arc<Suppressed> {
+        self.suppressed.clone()
+    }
+}
+
+fn describe(endpoint: &Endpoint) -> String {
+    format!(
+        "{} {:?}",
+        endpoint.dns_name.as_deref().unwrap_or_default(),
+        endpoint.record_type
+    )
+}
+
+#[async_trait]
+impl Provider for PolicyGuard {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        self.inner.domain_filter().await
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        self.inner.records().await
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, mut changes: Changes) -> Result<()> {
+        if self.policy == Policy::CreateOnly {
+            for ft in changes.update.drain(..) {
+                warn!(target: "policy", message = format!(
+                    "Suppressed update of {} by policy {}", describe(&ft.from), self.policy
+                ));
+                self.suppressed.updates.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if self.policy != Policy::Sync {
+            for ep in changes.delete.drain(..) {
+                warn!(target: "policy", message = format!(
+                    "Suppressed delete of {} by policy {}", describe(&ep), self.policy
+                ));
+                self.suppressed.deletes.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.inner.apply_changes(changes).await
+    }
+
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        self.inner.adjust_endpoints(endpoints).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::ep;
+    use crate::{changes::FromTo, endpoint::RecordType, providers::memory::InMemoryProvider};
+
+    fn changes() -> Changes {
+        Changes {
+            create: vec![ep("wiki.magicloud.lan", RecordType::A, &["192.168.0.106"])],
+            update: vec![FromTo {
+                from: ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]),
+                to: ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.105"]),
+            }],
+            delete: vec![ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"])],
+        }
+    }
+
+    async fn apply(policy: Policy) -> (Arc<InMemoryProvider>, Arc<Suppressed>) {
+        let inner = Arc::new(InMemoryProvider::default());
+        inner
+            .seed([
+                ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]),
+                ep("gitea.magicloud.lan", RecordType::A, &["192.168.0.103"]),
+            ])
+            .unwrap();
+        let guard = PolicyGuard::new(inner.clone(), policy);
+        guard.apply_changes(changes()).await.unwrap();
+        (inner, guard.suppressed())
+    }
+
+    #[tokio::test]
+    async fn it_works() {
+        let (inner, suppressed) = apply("sync".parse().unwrap()).await;
+        assert!(!inner.contains("gitea.magicloud.lan", &RecordType::A));
+        assert_eq!((suppressed.updates(), suppressed.deletes()), (0, 0));
+
+        let (inner, suppressed) = apply("upsert-only".parse().unwrap()).await;
+        assert!(inner.contains("gitea.magicloud.lan", &RecordType::A));
+        assert_eq!(
+            inner.get("nextcloud.magicloud.lan", &RecordType::A),
+            vec![ep(
+                "nextcloud.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.105"]
+            )]
+        );
+        assert_eq!((suppressed.updates(), suppressed.deletes()), (0, 1));
+
+        let (inner, suppressed) = apply(Policy::CreateOnly).await;
+        assert!(inner.contains("wiki.magicloud.lan", &RecordType::A));
+        assert_eq!(
+            inner.get("nextcloud.magicloud.lan", &RecordType::A),
+            vec![ep(
+                "nextcloud.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.102"]
+            )]
+        );
+        assert_eq!((suppressed.updates(), suppressed.deletes()), (1, 1));
+
+        assert!("upsert".parse::<Policy>().is_err());
+    }
+}
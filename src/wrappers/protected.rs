@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use eyre::Result;
+use tracing::{instrument, warn};
+
+use crate::{
+    changes::Changes,
+    domain_filter::DomainFilter,
+    endpoint::{Endpoint, RecordType},
+    provider::Provider,
+};
+
+/// Records matching both the names and, if any, the types.
+#[derive(Debug, Clone)]
+pub struct ProtectedRule {
+    pub names: DomainFilter,
+    /// `None` protects all types.
+    pub record_types: Option<Vec<RecordType>>,
+}
+impl ProtectedRule {
+    /// Constructor of `ProtectedRule`.
+    #[must_use]
+    pub const fn new(names: DomainFilter, record_types: Option<Vec<RecordType>>) -> Self {
+        Self {
+            names,
+            record_types,
+        }
+    }
+
+    #[must_use]
+    pub fn matches(&self, endpoint: &Endpoint) -> bool {
+        endpoint
+            .dns_name
+            .as_deref()
+            .is_some_and(|n| self.names.matches(n))
+            && self.record_types.as_ref().is_none_or(|types| {
+                endpoint
+                    .record_type
+                    .as_ref()
+                    .is_some_and(|t| types.contains(t))
+            })
+    }
+}
+
+/// Keeps External-DNS away from records it must never change, such as the apex NS and MX,
+/// or the SPF TXT record.
+///
+/// Creates, updates and deletes of protected records are dropped from `Changes`, and logged.
+/// Protected records are listed by `records` unless hidden.
+#[derive(Debug)]
+pub struct ProtectedRecords {
+    inner: Arc<dyn Provider>,
+    rules: Vec<ProtectedRule>,
+    hidden: bool,
+}
+impl ProtectedRecords {
+    /// Constructor of `ProtectedRecords`, exposing the protected records.
+    #[must_use]
+    pub fn new(inner: Arc<dyn Provider>, rules: Vec<ProtectedRule>) -> Self {
+        Self {
+            inner,
+            rules,
+            hidden: false,
+        }
+    }
+
+    /// Leave protected records out of `records`, so External-DNS does not see them at all.
+    #[must_use]
+    pub const fn with_hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    #[must_use]
+    pub fn is_protected(&self, endpoint: &Endpoint) -> bool {
+        self.rules.iter().any(|r| r.matches(endpoint))
+    }
+
+    fn keep(&self, action: &str, endpoint: &Endpoint) -> bool {
+        let protected = self.is_protected(endpoint);
+        if protected {
+            warn!(target: "protected", message = format!(
+                "Dropped {action} of protected {} {:?}",
+                endpoint.dns_name.as_deref().unwrap_or_default(),
+                endpoint.record_type
+            ));
+        }
+        !protected
+    }
+}
+
+#[async_trait]
+impl Provider for ProtectedRecords {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        self.inner.domain_filter().await
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        let mut records = self.inner.records().await?;
+        if self.hidden {
+            records.retain(|ep| !self.is_protected(ep));
+        }
+        Ok(records)
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, mut changes: Changes) -> Result<()> {
+        changes.create.retain(|ep| self.keep("create", ep));
+        changes
+            .update
+            .retain(|ft| self.keep("update", &ft.from) && self.keep("update", &ft.to));
+        changes.delete.retain(|ep| self.keep("delete", ep));
+        self.inner.apply_changes(changes).await
+    }
+
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        self.inner.adjust_endpoints(endpoints).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::ep;
+    use crate::{changes::FromTo, providers::memory::InMemoryProvider};
+    use regex::Regex;
+
+    #[tokio::test]
+    async fn it_works() {
+        let inner = Arc::new(InMemoryProvider::default());
+        inner
+            .seed([
+                ep("magicloud.lan", RecordType::MX, &["10 mail.magicloud.lan"]),
+                ep("magicloud.lan", RecordType::TXT, &["v=spf1 mx -all"]),
+                ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]),
+            ])
+            .unwrap();
+        let apex = DomainFilter::Regex {
+            regex_include: Some(Regex::new(r"^magicloud\.(lan|local)$").unwrap()),
+            regex_exclude: None,
+        };
+        let protected = ProtectedRecords::new(
+            inner.clone(),
+            vec![ProtectedRule::new(
+                apex,
+                Some(vec![RecordType::MX, RecordType::TXT, RecordType::NS]),
+            )],
+        );
+
+        assert_eq!(protected.records().await.unwrap().len(), 3);
+        protected
+            .apply_changes(Changes {
+                create: vec![ep("magicloud.lan", RecordType::NS, &["ns.magicloud.lan"])],
+                update: vec![FromTo {
+                    from: ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.102"]),
+                    to: ep("nextcloud.magicloud.lan", RecordType::A, &["192.168.0.105"]),
+                }],
+                delete: vec![ep("magicloud.lan", RecordType::TXT, &["v=spf1 mx -all"])],
+            })
+            .await
+            .unwrap();
+        assert!(!inner.contains("magicloud.lan", &RecordType::NS));
+        assert!(inner.contains("magicloud.lan", &RecordType::TXT));
+        assert_eq!(
+            inner.get("nextcloud.magicloud.lan", &RecordType::A),
+            vec![ep(
+                "nextcloud.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.105"]
+            )]
+        );
+
+        let protected = protected.with_hidden(true);
+        assert_eq!(
+            protected.records().await.unwrap(),
+            vec![ep(
+                "nextcloud.magicloud.lan",
+                RecordType::A,
+                &["192.168.0.105"]
+            )]
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    fs::File,
+    path::PathBuf,
+    process::Command,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime},
+};
+
+use actix_web::http::StatusCode;
+use async_trait::async_trait;
+use eyre::{Result, eyre};
+use tokio::time::Instant;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    changes::Changes, domain_filter::DomainFilter, endpoint::Endpoint, provider::Provider,
+    status::Status,
+};
+
+/// What to do for the DNS server to pick up the records a file-based provider wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReloadAction {
+    /// Send a signal, such as `HUP` or `SIGUSR1` (or its number), to the process whose PID
+    /// is in the pidfile. Unix only.
+    Signal { pidfile: PathBuf, signal: String },
+    /// Run a program with arguments, such as `systemctl restart dnsmasq`.
+    Command(Vec<String>),
+    /// Create the file, or update its modification time, for a watcher to notice.
+    Touch(PathBuf),
+}
+impl ReloadAction {
+    fn run(&self) -> Result<()> {
+        match self {
+            Self::Signal { pidfile, signal } => {
+                let pid = std::fs::read_to_string(pidfile)
+                    .map_err(|e| eyre!("Cannot read {}: {e}", pidfile.display()))?;
+                let pid = pid
+                    .trim()
+                    .parse::<u32>()
+                    .map_err(|e| eyre!("No PID in {}: {e}", pidfile.display()))?;
+                kill(pid, signal)
+            }
+            Self::Command(command) => run(command),
+            Self::Touch(path) => {
+                let file = File::options().create(true).append(true).open(path)?;
+                file.set_modified(SystemTime::now())?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn kill(pid: u32, signal: &str) -> Result<()> {
+    let name = signal.to_ascii_uppercase();
+    let number = match name.strip_prefix("SIG").unwrap_or(&name) {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "KILL" => libc::SIGKILL,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "TERM" => libc::SIGTERM,
+        number => number
+            .parse()
+            .map_err(|_| eyre!("Unknown signal {signal}"))?,
+    };
+    // 0 and negative PIDs would signal process groups.
+    let pid = libc::pid_t::try_from(pid)
+        .ok()
+        .filter(|p| *p > 0)
+        .ok_or_else(|| eyre!("Invalid PID {pid}"))?;
+    // SAFETY: kill only reads its arguments.
+    if unsafe { libc::kill(pid, number) } == 0 {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "Cannot send {signal} to {pid}: {}",
+            std::io::Error::last_os_error()
+        ))
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(_: u32, signal: &str) -> Result<()> {
+    Err(eyre!("Cannot send {signal}: signals are Unix only"))
+}
+
+fn run(command: &[String]) -> Result<()> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| eyre!("Empty reload command"))?;
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| eyre!("Cannot run {program}: {e}"))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "{} failed with {}: {}",
+            command.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// Runs reload actions after changes were applied, for file-based providers whose
+/// DNS server does not watch its files.
+///
+/// The reload waits until no changes were applied for the debounce delay, so a burst of
+/// changes causes one reload. A failed reload makes `healthz` answer 503 with the error,
+/// until a reload succeeds.
+#[derive(Debug)]
+pub struct Reloading {
+    inner: Arc<dyn Provider>,
+    actions: Vec<ReloadAction>,
+    debounce: Duration,
+    state: Arc<Mutex<State>>,
+}
+impl Reloading {
+    /// Constructor of `Reloading`, reloading once no changes came for one second.
+    #[must_use]
+    pub fn new(inner: Arc<dyn Provider>, actions: Vec<ReloadAction>) -> Self {
+        Self {
+            inner,
+            actions,
+            debounce: Duration::from_secs(1),
+            state: Arc::default(),
+        }
+    }
+
+    /// The time without changes to wait before reloading.
+    #[must_use]
+    pub const fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// The error of the last reload, if it failed.
+    #[must_use]
+    pub fn last_error(&self) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .last_error
+            .clone()
+    }
+
+    // Ask for a reload. Returns if the reloading task must start.
+    fn request(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.pending = true;
+        state.last_change = Some(Instant::now());
+        let start = !state.running;
+        state.running = true;
+        drop(state);
+        start
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pending: bool,
+    last_change: Option<Instant>,
+    running: bool,
+    last_error: Option<String>,
+}
+
+// Take the pending request, or stop running when there is none.
+fn take_pending(state: &Mutex<State>) -> bool {
+    let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+    let pending = std::mem::take(&mut state.pending);
+    state.running = pending;
+    pending
+}
+
+fn set_result(state: &Mutex<State>, result: Result<()>) {
+    let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+    state.last_error = result.err().map(|e| format!("Reload failed: {e}"));
+}
+
+// When the debounce delay after the last change ends.
+fn quiet_from(state: &Mutex<State>, debounce: Duration) -> Option<Instant> {
+    let state = state.lock().unwrap_or_else(PoisonError::into_inner);
+    state.last_change.map(|t| t + debounce)
+}
+
+async fn reload(actions: Vec<ReloadAction>, state: Arc<Mutex<State>>, debounce: Duration) {
+    loop {
+        // Each change pushes the reload back.
+        while let Some(deadline) = quiet_from(&state, debounce)
+            && deadline > Instant::now()
+        {
+            tokio::time::sleep_until(deadline).await;
+        }
+        if !take_pending(&state) {
+            return;
+        }
+        let actions = actions.clone();
+        let result =
+            tokio::task::spawn_blocking(move || actions.iter().try_for_each(ReloadAction::run))
+                .await
+                .unwrap_or_else(|e| Err(eyre!("Reload panicked: {e}")));
+        match &result {
+            Ok(()) => info!(target: "reload", message = "Reloaded"),
+            Err(e) => warn!(target: "reload", message = format!("Reload failed: {e}")),
+        }
+        set_result(&state, result);
+    }
+}
+
+#[async_trait]
+impl Provider for Reloading {
+    #[instrument(skip_all)]
+    async fn domain_filter(&self) -> Result<DomainFilter> {
+        self.inner.domain_filter().await
+    }
+
+    #[instrument(skip_all)]
+    async fn records(&self) -> Result<Vec<Endpoint>> {
+        self.inner.records().await
+    }
+
+    #[instrument(skip_all)]
+    async fn apply_changes(&self, changes: Changes) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+        self.inner.apply_changes(changes).await?;
+        if self.request() {
+            tokio::spawn(reload(
+                self.actions.clone(),
+                self.state.clone(),
+                self.debounce,
+            ));
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn adjust_endpoints(&self, endpoints: Vec<Endpoint>) -> Result<Vec<Endpoint>> {
+        self.inner.adjust_endpoints(endpoints).await
+    }
+}
+
+#[async_trait]
+impl Status for Reloading {
+    async fn healthz(&self) -> (String, StatusCode) {
+        self.last_error().map_or_else(
+            || ("OK".to_string(), StatusCode::OK),
+            |e| (e, StatusCode::SERVICE_UNAVAILABLE),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        endpoint::{RecordType, ep},
+        providers::memory::InMemoryProvider,
+    };
+
+    fn create(name: &str) -> Changes {
+        Changes {
+            create: vec![ep(name, RecordType::A, &["192.168.0.1"])],
+            ..Changes::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_works() {
+        let dir = std::env::temp_dir().join(format!("reload-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("reloads");
+        let provider = Reloading::new(
+            Arc::new(InMemoryProvider::default()),
+            vec![
+                ReloadAction::Command(vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    format!("echo reload >> {}", log.display()),
+                ]),
+                ReloadAction::Touch(dir.join("touched")),
+            ],
+        )
+        .with_debounce(Duration::from_millis(50));
+
+        // Each change pushes the reload back: 60ms after the first change, nothing was taken.
+        provider.apply_changes(create("a.lan")).await.unwrap();
+        tokio::task::yield_now().await;
+        tokio::time::advance(Duration::from_millis(30)).await;
+        provider.apply_changes(create("b.lan")).await.unwrap();
+        tokio::time::advance(Duration::from_millis(30)).await;
+        tokio::task::yield_now().await;
+        assert!(provider.state.lock().unwrap().pending);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "reload\n");
+        assert!(dir.join("touched").exists());
+        assert_eq!(provider.healthz().await.1, StatusCode::OK);
+
+        let failing = Reloading::new(
+            Arc::new(InMemoryProvider::default()),
+            vec![ReloadAction::Signal {
+                pidfile: dir.join("missing.pid"),
+                signal: "HUP".to_string(),
+            }],
+        )
+        .with_debounce(Duration::from_millis(10));
+        failing.apply_changes(create("a.lan")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (message, status) = failing.healthz().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(message.contains("missing.pid"), "{message}");
+
+        // Signal 0 only checks the process exists.
+        let pidfile = dir.join("test.pid");
+        std::fs::write(&pidfile, format!("{}\n", std::process::id())).unwrap();
+        let signal = |signal: &str| {
+            ReloadAction::Signal {
+                pidfile: pidfile.clone(),
+                signal: signal.to_string(),
+            }
+            .run()
+        };
+        signal("0").unwrap();
+        assert!(signal("SIGNOPE").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}